@@ -0,0 +1,305 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use sha2::{Digest, Sha256};
+use crate::api::{AppConfig, Client, MiResult};
+use crate::api::history::NoteHistory;
+use crate::api::markup::{self, LineKind};
+use crate::api::models::{Folder, Note};
+
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const ATTACHMENT_DIR: &str = "attachments/";
+
+/// Everything in a backup archive except the attachment bytes, which are stored
+/// as separate `attachments/<file id>` entries next to `manifest.json`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    pub version: u32,
+    pub created_at: i64,
+    pub folders: Vec<Folder>,
+    pub notes: Vec<Note>,
+    /// Settings with the account cookie removed.
+    pub config: AppConfig,
+    /// Attachments referenced by notes that could not be downloaded.
+    #[serde(default)]
+    pub missing_attachments: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct RestoreReport {
+    pub folders: usize,
+    pub notes: usize,
+    /// Already in the account (same id, same folder name, or same subject and content).
+    pub skipped_folders: usize,
+    pub skipped_notes: usize,
+    /// Attachment files in the archive. They are not uploaded again; there is no
+    /// upload call in the API client.
+    pub attachments_not_restored: usize,
+    /// Created notes that reference attachments, which only display while the
+    /// original files still exist on the account they came from.
+    pub notes_with_attachments: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+static BACKUP_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Backups of the profile whose data directory is `data_dir`.
+pub fn backup_dir(data_dir: Option<&Path>) -> Option<PathBuf> {
+    data_dir.map(|dir| dir.join("backups"))
+}
+
+/// Archives in the backup directory, oldest first.
+pub fn list(data_dir: Option<&Path>) -> Vec<PathBuf> {
+    let Some(dir) = backup_dir(data_dir) else { return Vec::new() };
+    let Ok(read) = fs::read_dir(dir) else { return Vec::new() };
+    let mut archives: Vec<PathBuf> = read
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("minote-backup-") && n.ends_with(".tar.gz")))
+        .collect();
+    // Names carry a sortable timestamp
+    archives.sort();
+    archives
+}
+
+/// True when scheduled backups are enabled and the newest archive is older than the interval.
+pub fn is_due(config: &AppConfig) -> bool {
+    if config.backup_interval_hours == 0 {
+        return false;
+    }
+    let Some(latest) = list(AppConfig::data_dir_of(config).as_deref()).pop() else { return true };
+    let age = fs::metadata(&latest).and_then(|m| m.modified()).ok().and_then(|t| t.elapsed().ok());
+    age.is_none_or(|age| age >= Duration::from_secs(config.backup_interval_hours * 3600))
+}
+
+/// Removes the oldest archives beyond `keep` (at least one is always kept).
+pub fn prune(data_dir: Option<&Path>, keep: usize) -> MiResult<usize> {
+    let archives = list(data_dir);
+    let excess = archives.len().saturating_sub(keep.max(1));
+    for path in &archives[..excess] {
+        fs::remove_file(path)?;
+    }
+    Ok(excess)
+}
+
+/// Writes a new archive of the client's profile, whose settings are `config`, and
+/// applies the retention policy. Returns `None` when another backup is already
+/// running in this process.
+pub async fn backup_now(client: &Client, config: &AppConfig) -> MiResult<Option<PathBuf>> {
+    if BACKUP_RUNNING.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+    let result = create(client, config).await;
+    BACKUP_RUNNING.store(false, Ordering::SeqCst);
+
+    let path = result?;
+    prune(client.data_dir(), config.backup_keep)?;
+    Ok(Some(path))
+}
+
+fn attachment_ids(note: &Note) -> Vec<String> {
+    let mut ids = Vec::new();
+    if let Some(files) = note.setting.as_ref().and_then(|s| s.data.as_ref()).and_then(|d| d.as_array()) {
+        ids.extend(files.iter().filter_map(|f| f.get("fileId").and_then(|v| v.as_str()).map(String::from)));
+    }
+    if let Some(ref content) = note.content {
+        for line in markup::parse(content) {
+            if let LineKind::Image(id) = line.kind {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+/// Archive entry name for an attachment; file ids are opaque so keep them path-safe.
+fn attachment_entry(id: &str) -> String {
+    let safe: String = id.chars().map(|c| if c == '/' || c == '\\' { '_' } else { c }).collect();
+    format!("{}{}", ATTACHMENT_DIR, safe)
+}
+
+async fn create(client: &Client, config: &AppConfig) -> MiResult<PathBuf> {
+    let (listed, folders) = client.list_all().await?;
+
+    // Listing only returns snippets
+    let mut notes = Vec::with_capacity(listed.len());
+    for note in &listed {
+        notes.push(client.get_note(&note.id).await?);
+    }
+
+    let mut attachments = Vec::new();
+    let mut missing_attachments = Vec::new();
+    let mut seen = HashSet::new();
+    for id in notes.iter().flat_map(attachment_ids) {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        match client.download_file(&id).await {
+            Ok(bytes) => attachments.push((id, bytes)),
+            Err(e) => {
+                eprintln!("[Backup] Skipping attachment {}: {}", id, e);
+                missing_attachments.push(id);
+            }
+        }
+    }
+
+    let mut config = config.clone();
+    config.account_cookie = None;
+
+    let backup = Backup {
+        version: FORMAT_VERSION,
+        created_at: chrono::Utc::now().timestamp_millis(),
+        folders,
+        notes,
+        config,
+        missing_attachments,
+    };
+
+    let dir = backup_dir(client.data_dir()).ok_or("No data directory available for backups")?;
+    fs::create_dir_all(&dir)?;
+    let name = format!("minote-backup-{}.tar.gz", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    let partial = dir.join(format!("{}.part", name));
+    let path = dir.join(name);
+
+    // Write next to the target and rename so an interrupted backup never looks complete
+    if let Err(e) = write_archive(&partial, &backup, &attachments) {
+        fs::remove_file(&partial).ok();
+        return Err(e);
+    }
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+fn append_entry<W: Write>(tar: &mut tar::Builder<W>, name: &str, data: &[u8]) -> MiResult<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+fn write_archive(path: &Path, backup: &Backup, attachments: &[(String, Vec<u8>)]) -> MiResult<()> {
+    let encoder = GzEncoder::new(File::create(path)?, Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    append_entry(&mut tar, MANIFEST, &serde_json::to_vec_pretty(backup)?)?;
+    for (id, bytes) in attachments {
+        append_entry(&mut tar, &attachment_entry(id), bytes)?;
+    }
+    tar.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// Reads an archive back into its manifest and attachments keyed by entry name.
+pub fn read(path: &Path) -> MiResult<(Backup, HashMap<String, Vec<u8>>)> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    let mut manifest = None;
+    let mut attachments = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        if name == MANIFEST {
+            manifest = Some(serde_json::from_slice::<Backup>(&data)?);
+        } else if name.starts_with(ATTACHMENT_DIR) {
+            attachments.insert(name, data);
+        }
+    }
+
+    let backup = manifest.ok_or("Archive has no manifest.json")?;
+    if backup.version > FORMAT_VERSION {
+        return Err(format!("Backup format {} is newer than this version supports", backup.version).into());
+    }
+    Ok((backup, attachments))
+}
+
+/// Recreates an archive's folders and notes in the signed-in account, skipping
+/// what is already there: folders by id or name, notes by id or by subject and
+/// content, so restoring the same archive twice creates nothing the second time.
+/// Attachments stay in the archive: notes keep their original file ids, which
+/// resolve as long as the files still exist on the account they came from.
+pub async fn restore(client: &Client, path: &Path) -> MiResult<RestoreReport> {
+    let (backup, attachments) = read(path)?;
+    let mut report = RestoreReport::default();
+
+    let (existing_notes, existing_folders) = client.list_all().await?;
+    let mut folder_map: HashMap<String, String> = HashMap::new();
+    for folder in &backup.folders {
+        if let Some(same) = existing_folders.iter().find(|f| f.id == folder.id || f.subject == folder.subject) {
+            folder_map.insert(folder.id.clone(), same.id.clone());
+            report.skipped_folders += 1;
+            continue;
+        }
+        match client.create_folder(&folder.subject).await {
+            Ok(created) => {
+                folder_map.insert(folder.id.clone(), created.id);
+                report.folders += 1;
+            }
+            Err(e) => report.failed.push((format!("folder {}", folder.subject), e.to_string())),
+        }
+    }
+
+    let existing_ids: HashSet<&str> = existing_notes.iter().map(|n| n.id.as_str()).collect();
+    let restoring: Vec<&Note> = backup.notes.iter().filter(|n| !existing_ids.contains(n.id.as_str())).collect();
+    report.skipped_notes = backup.notes.len() - restoring.len();
+    let existing_keys = if restoring.is_empty() { HashSet::new() } else { content_keys(client, existing_notes).await? };
+
+    for note in restoring {
+        let content = note.content.as_deref().unwrap_or(&note.snippet);
+        if existing_keys.contains(&content_key(&note.subject, content)) {
+            report.skipped_notes += 1;
+            continue;
+        }
+        let folder_id = note.folder_id.as_deref()
+            .and_then(|id| folder_map.get(id))
+            .map(String::as_str)
+            .unwrap_or("0");
+        match client.create_note(folder_id, &note.subject, content).await {
+            Ok(_) => {
+                report.notes += 1;
+                if !attachment_ids(note).is_empty() {
+                    report.notes_with_attachments.push(note.display_title());
+                }
+            }
+            Err(e) => report.failed.push((note.display_title(), e.to_string())),
+        }
+    }
+
+    report.attachments_not_restored = attachments.len();
+    Ok(report)
+}
+
+/// Identifies a note by what it says rather than by id, which changes on restore.
+fn content_key(subject: &str, content: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(subject.as_bytes());
+    hasher.update([0]);
+    hasher.update(content.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Content keys of the account's notes. Listings only carry snippets, so full
+/// contents come from the local history where it is current, else from the server.
+async fn content_keys(client: &Client, mut notes: Vec<Note>) -> MiResult<HashSet<[u8; 32]>> {
+    NoteHistory::fill_content(client.data_dir(), &mut notes);
+    let mut keys = HashSet::with_capacity(notes.len());
+    for note in notes {
+        let note = match note.content {
+            Some(_) => note,
+            None => client.get_note(&note.id).await?,
+        };
+        keys.insert(content_key(&note.subject, note.content.as_deref().unwrap_or(&note.snippet)));
+    }
+    Ok(keys)
+}
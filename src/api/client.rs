@@ -1,43 +1,149 @@
 use crate::api::models::*;
 use crate::api::auth::MiAccount;
-use reqwest::{header, Client as HttpClient};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::api::error::{ApiError, as_conflict};
+use crate::api::session::Session;
+use crate::api::schedule;
+use crate::api::status::SessionStatus;
+use crate::api::AppConfig;
+use reqwest::{header, redirect, Client as HttpClient, Url};
+use crate::api::http::{HttpSettings, SessionJar};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
 use std::collections::HashMap;
 
+/// Attempts per request, counting the first.
+const REQUEST_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a request that couldn't be sent, doubling up to `RETRY_MAX`.
+const RETRY_BASE: Duration = Duration::from_millis(500);
+const RETRY_MAX: Duration = Duration::from_secs(4);
+
+/// Longer `Retry-After` waits are left to the sync scheduler instead of holding the request.
+const MAX_INLINE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36";
+
+/// One account's connection to the notes service. Clones share the connection
+/// pool, cookies and session, and `set_credentials` changes them in place.
 #[derive(Clone)]
 pub struct Client {
+    /// For note requests; see `same_host_redirects`.
     http: HttpClient,
+    /// For the STS refresh, which reads the `Location` of each step itself, so it
+    /// follows no redirects. Shares the connection settings and the jar with `http`.
+    sts: HttpClient,
+    /// The micloud cookie (or the account cookie until a session exists).
+    jar: Arc<SessionJar>,
     account: Arc<RwLock<MiAccount>>,
-    micloud_cookie: Arc<RwLock<String>>,
-    user_agent: String,
+    session: Arc<RwLock<Option<Session>>>,
+    user_agent: Arc<std::sync::RwLock<String>>,
+    base_url: String,
+    site: Url,
+    status: Arc<watch::Sender<SessionStatus>>,
+    /// The profile the client was made for, and its local state directory, fixed
+    /// at creation so work still running after a profile switch stays with it.
+    profile: String,
+    data_dir: Option<PathBuf>,
 }
 
 type ReqResult = (Vec<u8>, reqwest::StatusCode);
 
-impl Client {
-    pub fn new(account_cookie: &str, user_agent: Option<String>) -> Self {
-        let account = MiAccount::new(account_cookie);
-        let http = HttpClient::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .unwrap();
-
-        let initial_micloud_cookie = if account_cookie.contains("serviceToken") {
-            account_cookie.to_string()
+/// Redirects within the notes service are followed. One to another host (the
+/// account login page when the session is gone) is handed back as it is, and
+/// `do_request` treats it like a 401.
+fn same_host_redirects() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        let same_host = attempt.previous().first().is_some_and(|first| first.host_str() == attempt.url().host_str());
+        if attempt.previous().len() > 10 {
+            attempt.error("too many redirects")
+        } else if same_host {
+            attempt.follow()
         } else {
-            String::new()
+            attempt.stop()
+        }
+    })
+}
+
+impl Client {
+    /// Client for the account, using the user agent, server and connection settings from `config`.
+    pub fn new(account_cookie: &str, config: &AppConfig) -> crate::api::MiResult<Self> {
+        let base_url = config.api_base_url.trim_end_matches('/').to_string();
+        let site = Url::parse(&format!("{}/", base_url))?;
+        let jar = Arc::new(SessionJar::default());
+        let settings = HttpSettings::from_config(config);
+        let http = settings.builder()?
+            .cookie_provider(jar.clone())
+            .redirect(same_host_redirects())
+            .build()?;
+        let sts = settings.builder()?
+            .cookie_provider(jar.clone())
+            .redirect(redirect::Policy::none())
+            .build()?;
+
+        let mut client = Self {
+            http,
+            sts,
+            jar,
+            account: Arc::new(RwLock::new(MiAccount::new(account_cookie).with_base_url(&base_url))),
+            session: Arc::new(RwLock::new(None)),
+            user_agent: Arc::new(std::sync::RwLock::new(String::new())),
+            base_url,
+            site,
+            status: Arc::new(watch::channel(SessionStatus::Valid).0),
+            profile: config.profile.clone(),
+            data_dir: AppConfig::data_dir_of(config),
         };
+        let session = client.load_credentials(account_cookie, config.user_agent.clone());
+        client.session = Arc::new(RwLock::new(session));
+        Ok(client)
+    }
 
-        let ua = user_agent.unwrap_or_else(|| "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36".to_string());
+    /// Switches to another account cookie or user agent, keeping the connections.
+    pub async fn set_credentials(&self, account_cookie: &str, user_agent: Option<String>) {
+        *self.account.write().await = MiAccount::new(account_cookie).with_base_url(&self.base_url);
+        let session = self.load_credentials(account_cookie, user_agent);
+        *self.session.write().await = session;
+        self.set_status(SessionStatus::Valid);
+    }
 
-        Self {
-            http,
-            account: Arc::new(RwLock::new(account)),
-            micloud_cookie: Arc::new(RwLock::new(initial_micloud_cookie)),
-            user_agent: ua,
-        }
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// Where this client's profile keeps its outbox, history and backups.
+    pub fn data_dir(&self) -> Option<&Path> {
+        self.data_dir.as_deref()
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        *self.status.borrow()
+    }
+
+    /// A receiver that is marked changed whenever `status()` changes.
+    pub fn watch_status(&self) -> watch::Receiver<SessionStatus> {
+        self.status.subscribe()
+    }
+
+    fn set_status(&self, status: SessionStatus) {
+        self.status.send_if_modified(|current| {
+            if *current == status {
+                return false;
+            }
+            *current = status;
+            true
+        });
+    }
+
+    /// Fills the jar and user agent, returning the stored session to use, if any.
+    fn load_credentials(&self, account_cookie: &str, user_agent: Option<String>) -> Option<Session> {
+        // Prefer a session refreshed by an earlier run (GUI or CLI) for this account
+        let session = Session::load_for(account_cookie);
+        let cookie = session.as_ref().map(|s| s.cookie.as_str()).unwrap_or(account_cookie);
+        self.jar.reset(cookie, &self.site);
+        *self.user_agent.write().unwrap() = user_agent.unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        session
     }
 
     fn now_ms() -> u128 {
@@ -47,31 +153,63 @@ impl Client {
             .unwrap_or(0)
     }
 
+    /// `serviceToken` for form parameters, taken from the active micloud cookie.
+    async fn service_token(&self) -> String {
+        match self.jar.value(&self.site, "serviceToken") {
+            Some(token) => token,
+            None => self.account.read().await.get_service_token(),
+        }
+    }
+
+    /// Exchanges the passToken for a new micloud cookie and persists it for later runs.
+    async fn refresh_session(&self) -> crate::api::MiResult<()> {
+        let before = self.status();
+        self.set_status(SessionStatus::Refreshing);
+        // Get fresh cookies via STS in a scope to ensure locks are released
+        let session = {
+            let account = self.account.read().await;
+            account.gen_micloud_session(&self.sts).await.inspect_err(|_| self.set_status(before))?
+        };
+        if let Err(e) = session.save(self.data_dir()) {
+            eprintln!("Failed to save session: {}", e);
+        }
+
+        self.jar.reset(&session.cookie, &self.site);
+        *self.session.write().await = Some(session);
+        self.set_status(SessionStatus::Valid);
+        Ok(())
+    }
+
+    /// Refreshes ahead of the recorded expiry so requests don't have to hit a 401 first.
+    async fn refresh_if_expiring(&self) {
+        let expiring = self.session.read().await.as_ref().is_some_and(|s| s.needs_refresh());
+        if expiring {
+            crate::dprintln!("Session close to expiry, refreshing proactively...");
+            if let Err(_e) = self.refresh_session().await {
+                crate::dprintln!("Proactive STS refresh failed: {}", _e);
+            }
+        }
+    }
+
     async fn do_request(&self, method: reqwest::Method, url: String, form: Option<HashMap<String, String>>) -> crate::api::MiResult<ReqResult> {
-        let is_mobile = self.user_agent.contains("iPhone") || self.user_agent.contains("Android") || self.user_agent.contains("Mobile");
+        let user_agent = self.user_agent.read().unwrap().clone();
+        let is_mobile = user_agent.contains("iPhone") || user_agent.contains("Android") || user_agent.contains("Mobile");
+        self.refresh_if_expiring().await;
 
-        for _i in 0..3 {
+        for _i in 0..REQUEST_ATTEMPTS {
             if _i > 0 {
                 crate::dprintln!("[Background API] Attempt {} for URL: {}", _i + 1, url);
             }
             let mut req = self.http.request(method.clone(), &url);
 
-            // Add cookies
-            let cookie_str = self.micloud_cookie.read().await;
-            let final_cookie = if !cookie_str.is_empty() {
-                cookie_str.clone()
-            } else {
-                let account = self.account.read().await;
-                account.cookie.clone()
-            };
-            req = req.header(header::COOKIE, final_cookie.as_str());
+            // Cookies come from the jar, so Set-Cookie from any response is kept
 
             // Headers matching actual browser behavior (from cURL analysis)
-            req = req.header(header::USER_AGENT, &self.user_agent)
+            req = req.header(header::USER_AGENT, &user_agent)
                 .header(header::ACCEPT, "*/*")
                 .header("Accept-Language", "en-US,en;q=0.9,zh-CN;q=0.8,zh;q=0.7")
                 .header("priority", "u=1, i")
-                .header(header::REFERER, "https://i.mi.com/note/h5")
+                .header(header::REFERER, format!("{}/note/h5", self.base_url))
                 .header("Sec-Ch-Ua-Mobile", if is_mobile { "?1" } else { "?0" })
                 .header("Sec-Ch-Ua-Platform", if is_mobile { "\"iOS\"" } else { "\"Windows\"" })
                 .header("Sec-Fetch-Dest", "empty")
@@ -80,7 +218,7 @@ impl Client {
 
             // X-XSRF-TOKEN is ONLY for POST/PUT/DELETE, NOT for GET requests
             if method != reqwest::Method::GET {
-                if let Some(ph) = Self::extract_cookie_value(&final_cookie, "i.mi.com_ph") {
+                if let Some(ph) = self.jar.value(&self.site, "i.mi.com_ph") {
                     crate::dprintln!("Adding X-XSRF-TOKEN header for {} request", method);
                     req = req.header("X-XSRF-TOKEN", ph);
                 }
@@ -94,124 +232,164 @@ impl Client {
                 Ok(r) => r,
                 Err(e) => {
                     crate::dprintln!("Request SEND ERROR: {} | URL: {}", e, url);
-                    if _i == 2 { return Err(e.into()); }
-                    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                    // A write that may have reached the server is not sent twice
+                    if _i + 1 == REQUEST_ATTEMPTS || (method != reqwest::Method::GET && !e.is_connect()) {
+                        self.set_status(SessionStatus::Offline);
+                        return Err(e.into());
+                    }
+                    tokio::time::sleep(schedule::backoff(RETRY_BASE, _i + 1, RETRY_MAX)).await;
                     continue;
                 }
             };
             let status = resp.status();
-            if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = resp.headers().get(header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(schedule::parse_retry_after);
+                crate::dprintln!("Rate limited (Retry-After: {:?}) | URL: {}", retry_after, url);
+                match retry_after {
+                    Some(wait) if wait <= MAX_INLINE_RETRY_AFTER && _i + 1 < REQUEST_ATTEMPTS => {
+                        tokio::time::sleep(wait).await;
+                        continue;
+                    }
+                    _ => return Err(ApiError::RateLimited { retry_after }.into()),
+                }
+            }
+            // A redirect that got this far leads off the service, to the account login page
+            if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN || status.is_redirection() {
                 let bytes = resp.bytes().await?.to_vec();
                 let _body = String::from_utf8_lossy(&bytes);
 
-                if _i < 2 {
+                if _i + 1 < REQUEST_ATTEMPTS {
                     crate::dprintln!("Attempting STS refresh...");
 
-                    // Get fresh cookies via STS in a scope to ensure locks are released
-                    let new_cookie_result = {
-                        let account = self.account.read().await;  // Use read lock, not write
-                        account.gen_micloud_cookie().await
-                    };
-
-
-                    let new_cookie = match new_cookie_result {
-                        Ok(cookie) => {
-                            crate::dprintln!("STS refresh successful.");
-                            cookie
-                        },
+                    match self.refresh_session().await {
+                        Ok(()) => { crate::dprintln!("STS refresh successful."); }
                         Err(e) => {
                             crate::dprintln!("STS refresh FAILED: {}", e);
-                            return Err(format!("STS error: {}", e).into());
+                            self.set_status(SessionStatus::after_failure(&e));
+                            // Without an answer the session may still be fine
+                            if e.downcast_ref::<reqwest::Error>().is_some() {
+                                return Err(e);
+                            }
+                            // The passToken was rejected, so the stored session is no good either
+                            Session::clear(&self.account.read().await.cookie);
+                            return Err(ApiError::SessionExpired(format!("STS refresh failed: {}", e)).into());
                         },
-                    };
-
-
-                    // Update the cookie in a separate scope
-                    {
-                        let mut mc = self.micloud_cookie.write().await;
-                        *mc = new_cookie;
                     }
 
                     continue;
                 }
 
                 eprintln!("Session expired. Please login again.");
-                return Err(format!("Authentication error: {}", status).into());
+                self.set_status(SessionStatus::Expired);
+                return Err(ApiError::SessionExpired(format!("HTTP {}", status)).into());
             }
 
+            // Any other answer means the session was accepted
+            self.set_status(SessionStatus::Valid);
             let status = resp.status();
             let bytes = resp.bytes().await?.to_vec();
             return Ok((bytes, status));
         }
-        Err("Max retries reached".into())
+        unreachable!("the last attempt always returns")
     }
 
-    #[allow(dead_code)]
-    async fn request_json<T: serde::de::DeserializeOwned>(&self, method: reqwest::Method, url: String, form: Option<HashMap<String, String>>) -> crate::api::MiResult<T> {
+    /// Sends a request and unwraps Xiaomi's `code`/`result`/`data` envelope.
+    async fn request_data(&self, method: reqwest::Method, url: String, form: Option<HashMap<String, String>>) -> crate::api::MiResult<Option<serde_json::Value>> {
         let (bytes, status) = self.do_request(method, url, form).await?;
-        if !status.is_success() {
-            return Err(format!("Request failed with status: {} (body: {})", status, String::from_utf8_lossy(&bytes)).into());
-        }
+        decode_response(status.as_u16(), &bytes).map_err(|e| {
+            crate::dprintln!("API Error: {} | Body: {}", e, String::from_utf8_lossy(&bytes));
+            e
+        })
+    }
 
-        let json: serde_json::Value = match serde_json::from_slice(&bytes) {
-            Ok(j) => j,
-            Err(e) => {
-                crate::dprintln!("JSON Parse ERROR: {} | Body: {}", e, String::from_utf8_lossy(&bytes));
-                return Err(e.into());
-            }
-        };
-        let data = json.get("data").ok_or_else(|| {
-            println!("API Error: No 'data' in response. Body: {}", json);
-            "no data in response"
-        })?;
-        let result: T = serde_json::from_value(data.clone())?;
-        Ok(result)
+    async fn request_json<T: serde::de::DeserializeOwned>(&self, method: reqwest::Method, url: String, form: Option<HashMap<String, String>>) -> crate::api::MiResult<T> {
+        let data = self.request_data(method, url, form).await?
+            .ok_or_else(|| ApiError::UnexpectedResponse("no data in response".to_string()))?;
+        Ok(serde_json::from_value(data)?)
     }
 
     pub async fn list_notes(&self, limit: i32) -> crate::api::MiResult<NotesResponse> {
-        let url = format!("https://i.mi.com/note/full/page/?limit={}&ts={}", limit, Self::now_ms());
-        let (bytes, status) = self.do_request(reqwest::Method::GET, url, None).await?;
+        let url = format!("{}/note/full/page/?limit={}&ts={}", self.base_url, limit, Self::now_ms());
+        let result: NotesResponse = self.request_json(reqwest::Method::GET, url, None).await?;
+        crate::dprintln!("DEBUG: API returned {} notes.", result.entries.len());
+        Ok(result)
+    }
 
-        if !status.is_success() {
-            return Err(format!("Request failed with status: {}", status).into());
-        }
+    /// Every live note and folder, following `syncTag` paging until the last page.
+    /// Notes carry snippets only; use `get_note` for full content.
+    pub async fn list_all(&self) -> crate::api::MiResult<(Vec<Note>, Vec<Folder>)> {
+        let mut notes = Vec::new();
+        let mut folders = Vec::new();
+        let mut sync_tag: Option<String> = None;
+        loop {
+            let mut url = format!("{}/note/full/page/?limit=200&ts={}", self.base_url, Self::now_ms());
+            if let Some(ref tag) = sync_tag {
+                url.push_str(&format!("&syncTag={}", tag));
+            }
+            let page: NotesResponse = self.request_json(reqwest::Method::GET, url, None).await?;
+            notes.extend(page.entries.into_iter().filter(|n| !n.is_deleted()));
+            folders.extend(page.folders.unwrap_or_default());
 
-        let json: serde_json::Value = serde_json::from_slice(&bytes)?;
-        let data = json.get("data").ok_or_else(|| {
-            println!("API Error: No 'data' in response. Body: {}", json);
-            "no data in response"
-        })?;
+            if page.last_page || page.sync_tag.is_none() || page.sync_tag == sync_tag {
+                break;
+            }
+            sync_tag = page.sync_tag;
+        }
+        Ok((notes, folders))
+    }
 
-        if let Some(_entries) = data.get("entries").and_then(|e| e.as_array()) {
-            crate::dprintln!("DEBUG: API returned {} notes.", _entries.len());
+    /// Downloads an attachment referenced by a note (images, audio).
+    pub async fn download_file(&self, file_id: &str) -> crate::api::MiResult<Vec<u8>> {
+        let url = format!("{}/file/full?type=note_img&fileid={}", self.base_url, file_id);
+        let (bytes, status) = self.do_request(reqwest::Method::GET, url, None).await?;
+        if !status.is_success() {
+            return Err(ApiError::Http { status: status.as_u16(), body: String::from_utf8_lossy(&bytes).into_owned() }.into());
         }
+        Ok(bytes)
+    }
 
-        let result: NotesResponse = serde_json::from_value(data.clone())?;
+    /// Notes in the recycle bin, following `syncTag` paging until the last page.
+    pub async fn list_trash(&self, limit: i32) -> crate::api::MiResult<Vec<Note>> {
+        let mut notes = Vec::new();
+        let mut sync_tag: Option<String> = None;
+        loop {
+            let mut url = format!("{}/note/deleted/page/?limit={}&ts={}", self.base_url, limit, Self::now_ms());
+            if let Some(ref tag) = sync_tag {
+                url.push_str(&format!("&syncTag={}", tag));
+            }
+            let page: NotesResponse = self.request_json(reqwest::Method::GET, url, None).await?;
+            notes.extend(page.entries.into_iter().filter(|n| n.is_deleted()));
 
-        Ok(result)
+            if page.last_page || page.sync_tag.is_none() || page.sync_tag == sync_tag {
+                break;
+            }
+            sync_tag = page.sync_tag;
+        }
+        Ok(notes)
     }
 
-    #[allow(dead_code)]
-    pub async fn get_note(&self, id: &str) -> crate::api::MiResult<Note> {
-        let url = format!("https://i.mi.com/note/note/{}?ts={}", id, Self::now_ms());
-        let data: serde_json::Value = self.request_json(reqwest::Method::GET, url, None).await?;
+    pub async fn restore_note(&self, id: &str, tag: &str) -> crate::api::MiResult<()> {
+        let url = format!("{}/note/note/{}/restore", self.base_url, id);
+        let mut params = HashMap::new();
+        params.insert("tag".to_string(), tag.to_string());
 
-        // Strategy 1: Try wrapped EntryResponse
-        if let Ok(res) = serde_json::from_value::<EntryResponse>(data.clone()) {
-            return Ok(res.entry);
-        }
+        let service_token = self.service_token().await;
+        params.insert("serviceToken".to_string(), service_token);
 
-        // Strategy 2: Try direct Note
-        if let Ok(note) = serde_json::from_value::<Note>(data.clone()) {
-            return Ok(note);
-        }
+        self.request_data(reqwest::Method::POST, url, Some(params)).await?;
+        Ok(())
+    }
 
-        Err(format!("Could not parse Note from API response: {}", data).into())
+    pub async fn get_note(&self, id: &str) -> crate::api::MiResult<Note> {
+        let url = format!("{}/note/note/{}?ts={}", self.base_url, id, Self::now_ms());
+        let data = self.request_data(reqwest::Method::GET, url, None).await?;
+        decode_entry(data)
     }
 
-    #[allow(dead_code)]
     pub async fn create_note(&self, folder_id: &str, subject: &str, content: &str) -> crate::api::MiResult<Note> {
-        let url = "https://i.mi.com/note/full/post";
+        let url = format!("{}/note/full/post", self.base_url);
         let mut params = HashMap::new();
         params.insert("folder_id".to_string(), folder_id.to_string());
 
@@ -221,34 +399,15 @@ impl Client {
         let entry_json = serde_json::to_string(&entry)?;
 
         params.insert("entry".to_string(), entry_json);
-        let service_token = self.account.read().await.get_service_token();
+        let service_token = self.service_token().await;
         params.insert("serviceToken".to_string(), service_token);
 
-        let data: serde_json::Value = self.request_json(reqwest::Method::POST, url.to_string(), Some(params)).await?;
-
-        // Success check: If we got here, request_json already checked status is_success
-        // Try to return the created note, otherwise return a synthetic one based on inputs
-        if let Ok(res) = serde_json::from_value::<EntryResponse>(data.clone()) {
-            Ok(res.entry)
-        } else if let Ok(note) = serde_json::from_value::<Note>(data.clone()) {
-            Ok(note)
-        } else {
-            // Fallback: If creation succeeded at status level, return a dummy Note to satisfy types
-            // UI will refresh anyway
-            Ok(Note {
-                id: "synthetic".to_string(),
-                folder_id: Some(folder_id.to_string()),
-                subject: subject.to_string(),
-                snippet: "".to_string(),
-                tag: "".to_string(),
-                ..Default::default()
-            })
-        }
+        let data = self.request_data(reqwest::Method::POST, url, Some(params)).await?;
+        decode_entry(data)
     }
 
-    #[allow(dead_code)]
     pub async fn update_note(&self, id: &str, tag: &str, subject: &str, content: &str, folder_id: Option<&str>) -> crate::api::MiResult<Note> {
-        let url = format!("https://i.mi.com/note/note/{}", id);
+        let url = format!("{}/note/note/{}", self.base_url, id);
         let mut params = HashMap::new();
         params.insert("tag".to_string(), tag.to_string());
 
@@ -263,124 +422,167 @@ impl Client {
         let entry_json = serde_json::to_string(&entry)?;
 
         params.insert("entry".to_string(), entry_json);
-        let service_token = self.account.read().await.get_service_token();
+        let service_token = self.service_token().await;
         params.insert("serviceToken".to_string(), service_token);
 
-        let data: serde_json::Value = self.request_json(reqwest::Method::POST, url, Some(params)).await?;
+        match self.request_data(reqwest::Method::POST, url, Some(params)).await {
+            Ok(data) => decode_entry(data),
+            // A stale tag is reported as a rejected write; confirm against the server copy
+            Err(e) if matches!(e.downcast_ref::<ApiError>(), Some(ApiError::Api { .. } | ApiError::Http { status: 400 | 409 | 412, .. })) => {
+                let remote = self.get_note(id).await?;
+                if remote.tag != tag {
+                    return Err(ApiError::Conflict { remote: Box::new(remote) }.into());
+                }
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-        if let Ok(res) = serde_json::from_value::<EntryResponse>(data.clone()) {
-            Ok(res.entry)
-        } else if let Ok(note) = serde_json::from_value::<Note>(data.clone()) {
-            Ok(note)
-        } else {
-            // Fallback for success cases with minimal body
-            Ok(Note {
-                id: id.to_string(),
-                folder_id: folder_id.map(|s| s.to_string()),
-                subject: subject.to_string(),
-                snippet: "".to_string(),
-                tag: tag.to_string(),
-                ..Default::default()
-            })
+    /// Appends markup lines to a note. The note is re-read right before writing so
+    /// edits made elsewhere are kept, and the write is retried if its tag moved on.
+    pub async fn append_to_note(&self, id: &str, line: &str) -> crate::api::MiResult<Note> {
+        let mut last_err: Option<crate::api::MiError> = None;
+        for _attempt in 0..3 {
+            let note = self.get_note(id).await?;
+            let content = note.content.clone().ok_or("Note content missing; refusing to overwrite it")?;
+            let new_content = if content.is_empty() { line.to_string() } else { format!("{}\n{}", content, line) };
+
+            match self.update_note(id, &note.tag, &note.subject, &new_content, note.folder_id.as_deref()).await {
+                Ok(updated) => return Ok(updated),
+                Err(e) if as_conflict(&e).is_some() => {
+                    crate::dprintln!("Note {} changed remotely, retrying append: {}", id, e);
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
         }
+        Err(last_err.unwrap_or_else(|| "Append failed".into()))
     }
 
-    #[allow(dead_code)]
+    /// Moves a note to the recycle bin, or with `purge` deletes it permanently (also from the bin).
     pub async fn delete_note(&self, id: &str, tag: &str, purge: bool) -> crate::api::MiResult<()> {
-        let url = format!("https://i.mi.com/note/full/{}/delete", id);
+        let url = format!("{}/note/full/{}/delete", self.base_url, id);
         let mut params = HashMap::new();
         params.insert("tag".to_string(), tag.to_string());
         params.insert("purge".to_string(), purge.to_string());
 
-        let service_token = self.account.read().await.get_service_token();
+        let service_token = self.service_token().await;
         params.insert("serviceToken".to_string(), service_token);
 
-        let (_bytes, status) = self.do_request(reqwest::Method::POST, url, Some(params)).await?;
-        if !status.is_success() {
-            return Err(format!("Delete failed with status: {}", status).into());
-        }
+        self.request_data(reqwest::Method::POST, url, Some(params)).await?;
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub async fn create_folder(&self, subject: &str) -> crate::api::MiResult<Folder> {
-        let url = "https://i.mi.com/note/folder/post";
+        let url = format!("{}/note/folder/post", self.base_url);
         let mut params = HashMap::new();
         params.insert("subject".to_string(), subject.to_string());
 
-        let service_token = self.account.read().await.get_service_token();
+        let service_token = self.service_token().await;
         params.insert("serviceToken".to_string(), service_token);
 
-        let data: serde_json::Value = self.request_json(reqwest::Method::POST, url.to_string(), Some(params)).await?;
-
-        if let Ok(res) = serde_json::from_value::<FolderResponse>(data.clone()) {
-            Ok(res.folder)
-        } else if let Ok(folder) = serde_json::from_value::<Folder>(data.clone()) {
-            Ok(folder)
-        } else {
-            // Fallback for success (200 OK)
-            Ok(Folder {
-                id: "synthetic".to_string(),
-                subject: subject.to_string(),
-                tag: "".to_string(),
-                ..Default::default()
-            })
-        }
+        let data = self.request_data(reqwest::Method::POST, url, Some(params)).await?;
+        decode_folder(data)
     }
 
-    #[allow(dead_code)]
     pub async fn delete_folder(&self, id: &str, tag: &str) -> crate::api::MiResult<()> {
-        let url = format!("https://i.mi.com/note/folder/{}/delete", id);
+        let url = format!("{}/note/folder/{}/delete", self.base_url, id);
         let mut params = HashMap::new();
         params.insert("tag".to_string(), tag.to_string());
 
-        let service_token = self.account.read().await.get_service_token();
+        let service_token = self.service_token().await;
         params.insert("serviceToken".to_string(), service_token);
 
-        let (_bytes, status) = self.do_request(reqwest::Method::POST, url, Some(params)).await?;
-        if !status.is_success() {
-            return Err(format!("Delete folder failed with status: {}", status).into());
-        }
+        self.request_data(reqwest::Method::POST, url, Some(params)).await?;
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub async fn update_folder(&self, id: &str, tag: &str, subject: &str) -> crate::api::MiResult<Folder> {
-        let url = format!("https://i.mi.com/note/folder/{}", id);
+        let url = format!("{}/note/folder/{}", self.base_url, id);
         let mut params = HashMap::new();
         params.insert("tag".to_string(), tag.to_string());
         params.insert("subject".to_string(), subject.to_string());
 
-        let service_token = self.account.read().await.get_service_token();
+        let service_token = self.service_token().await;
         params.insert("serviceToken".to_string(), service_token);
 
-        let data: serde_json::Value = self.request_json(reqwest::Method::POST, url, Some(params)).await?;
+        let data = self.request_data(reqwest::Method::POST, url, Some(params)).await?;
+        decode_folder(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock_server::{MockServer, Response};
 
-        if let Ok(res) = serde_json::from_value::<FolderResponse>(data.clone()) {
-            Ok(res.folder)
-        } else if let Ok(folder) = serde_json::from_value::<Folder>(data.clone()) {
-            Ok(folder)
-        } else {
-            // Fallback for success
-            Ok(Folder {
-                id: id.to_string(),
-                subject: subject.to_string(),
-                tag: tag.to_string(),
-                ..Default::default()
-            })
-        }
+    const NOTES: &str = r#"{"code":0,"result":"ok","data":{"entries":[],"folders":[]}}"#;
+
+    fn client(server: &MockServer) -> Client {
+        let config = AppConfig { api_base_url: server.url.clone(), ..AppConfig::default() };
+        Client::new("userId=1; passToken=p; serviceToken=s", &config).unwrap()
     }
 
-    fn extract_cookie_value(cookie_str: &str, name: &str) -> Option<String> {
-        for part in cookie_str.split(';') {
-            let part = part.trim();
-            if part.starts_with(name) && part.contains('=') {
-                let kv: Vec<&str> = part.splitn(2, '=').collect();
-                if kv.len() == 2 && kv[0].trim() == name {
-                    return Some(kv[1].trim().to_string());
-                }
+    #[tokio::test]
+    async fn redirects_within_the_service_are_followed() {
+        let server = MockServer::start(|req| match req.path() {
+            "/note/full/page/" => Response::status(302).header("Location", "/note/full/page/v2"),
+            "/note/full/page/v2" => Response::ok(NOTES),
+            _ => Response::status(404),
+        });
+        let client = client(&server);
+        client.list_notes(10).await.unwrap();
+        assert_eq!(server.hits("/note/full/page/v2").len(), 1);
+        assert_eq!(client.status(), SessionStatus::Valid);
+    }
+
+    #[tokio::test]
+    async fn a_redirect_to_another_host_is_an_auth_failure() {
+        // "localhost" reaches the same server under another host name
+        let server = MockServer::start(|req| match req.path() {
+            "/note/full/page/" => {
+                let port = req.header("host").unwrap().rsplit(':').next().unwrap().to_string();
+                Response::status(302).header("Location", &format!("http://localhost:{}/pass/serviceLogin", port))
+            }
+            _ => Response::status(404),
+        });
+        let client = client(&server);
+        assert!(client.list_notes(10).await.is_err());
+        assert!(server.hits("/pass/serviceLogin").is_empty(), "followed the redirect to the login page");
+        // The session was refreshed instead; the mock has no STS, so that fails too
+        assert_eq!(server.hits("/api/user/login").len(), 1);
+        assert_ne!(client.status(), SessionStatus::Valid);
+    }
+
+    fn note_with_tag(tag: &str) -> String {
+        format!(r#"{{"code":0,"result":"ok","data":{{"entry":{{"id":"7","tag":"{}","content":"remote"}}}}}}"#, tag)
+    }
+
+    #[tokio::test]
+    async fn a_rejected_update_with_a_stale_tag_is_a_conflict() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "POST" => Response::ok(r#"{"code":10017,"result":"error","description":"bad tag"}"#),
+            _ => Response::ok(note_with_tag("t2")),
+        });
+        let err = client(&server).update_note("7", "t1", "", "local", None).await.unwrap_err();
+        assert_eq!(as_conflict(&err).map(|remote| remote.tag.as_str()), Some("t2"));
+    }
+
+    #[tokio::test]
+    async fn other_update_failures_are_returned_without_probing() {
+        for status in [429, 500] {
+            let server = MockServer::start(move |req| match req.method.as_str() {
+                "POST" => Response::status(status),
+                _ => Response::ok(note_with_tag("t2")),
+            });
+            let err = client(&server).update_note("7", "t1", "", "local", None).await.unwrap_err();
+            match err.downcast_ref::<ApiError>() {
+                Some(ApiError::RateLimited { .. }) => assert_eq!(status, 429),
+                Some(ApiError::Http { status: got, .. }) => assert_eq!(*got, status),
+                _ => panic!("unexpected error for HTTP {}: {}", status, err),
             }
+            assert!(server.requests().iter().all(|req| req.method == "POST"), "probed the note after HTTP {}", status);
         }
-        None
     }
 }
@@ -1,52 +1,670 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::fs;
 use std::path::PathBuf;
-use directories::ProjectDirs;
+use crate::api::hotkey::{self, Hotkey, HotkeyAction};
+use crate::api::http;
+use crate::api::json_file;
+use crate::api::markup::PasteFormat;
+use crate::api::overrides::{self, Source};
+use crate::api::profile;
+use crate::api::secrets::{self, SecretBackend, SecretStore};
 
-#[derive(Serialize, Deserialize, Clone)]
+const ACCOUNT_COOKIE_KEY: &str = "account_cookie";
+
+const DEFAULT_API_BASE_URL: &str = "https://i.mi.com";
+
+/// Schema version written to `config.json`; one migration per step in `MIGRATIONS`.
+pub const CONFIG_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// `MIGRATIONS[n]` upgrades a version `n` file to version `n + 1`.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_v0_to_v1];
+
+/// Where the launch bar puts the selected note.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Destination {
+    #[default]
+    Clipboard,
+    /// Typed into the window that had focus before the launch bar opened.
+    PreviousProgram,
+}
+
+/// Which variant of the notes web app the main window emulates.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Theme {
+    Desktop,
+    #[default]
+    Mobile,
+}
+
+impl Theme {
+    pub fn toggled(self) -> Self {
+        match self {
+            Theme::Desktop => Theme::Mobile,
+            Theme::Mobile => Theme::Desktop,
+        }
+    }
+}
+
+/// Missing fields take their value from `Default`, so a partial file keeps the rest.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct AppConfig {
+    pub version: u32,
+    /// Profile the config was loaded from; it decides where `save` writes.
+    #[serde(skip)]
+    pub profile: String,
+    /// Kept in the secret store; only read from the file to migrate old plaintext configs.
+    #[serde(skip_serializing)]
     pub account_cookie: Option<String>,
     pub user_agent: Option<String>,
-    pub hotkey: String,       // e.g. "Alt-L"
-    pub destination: String,  // "Clipboard" or "PreviousProgram"
-    pub theme: String,        // "Desktop" or "Mobile"
+    pub hotkey: Hotkey, // opens the launch bar
+    pub quick_capture_hotkey: Option<Hotkey>,
+    pub open_window_hotkey: Option<Hotkey>,
+    pub search_clipboard_hotkey: Option<Hotkey>,
+    pub destination: Destination,
+    pub theme: Theme,
+    pub paste_format: PasteFormat,
+    pub capture_folder_id: String, // "0" is the root "All notes" folder
+    pub backup_interval_hours: u64, // 0 disables scheduled backups
+    pub backup_keep: usize,
+    pub secret_backend: SecretBackend,
+    pub search_all_profiles: bool, // launch bar also matches notes of other profiles
+    pub api_base_url: String, // e.g. a local mock of i.mi.com
+    pub sync_interval_secs: u64,
+    pub idle_sync_interval_secs: u64, // used once several syncs in a row found no changes
+    pub max_backoff_secs: u64, // longest wait between retries after failed syncs
+    /// e.g. "http://proxy:8080" or "socks5://127.0.0.1:1080"; "none" connects directly.
+    /// Unset uses `HTTPS_PROXY` and friends, then the system settings.
+    pub proxy: Option<String>,
+    pub ca_bundle: Option<String>, // extra trusted root certificates (PEM bundle or DER file)
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+    /// Keeps history, the outbox and backups here, in a subdirectory named after the
+    /// profile, instead of the profile's data directory.
+    pub cache_dir: Option<String>,
+    /// Settings that came from `MINOTE_*` variables or `--set`; `save` leaves the
+    /// file's values for them alone.
+    #[serde(skip)]
+    pub overridden: Vec<String>,
+    /// Settings this version doesn't know, kept so a downgrade doesn't lose them.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
+            profile: profile::active(),
             account_cookie: None,
             user_agent: None,
-            hotkey: "Alt-L".to_string(),
-            destination: "Clipboard".to_string(),
-            theme: "Mobile".to_string(),
+            hotkey: Hotkey::default(),
+            quick_capture_hotkey: None,
+            open_window_hotkey: None,
+            search_clipboard_hotkey: None,
+            destination: Destination::default(),
+            theme: Theme::default(),
+            paste_format: PasteFormat::default(),
+            capture_folder_id: "0".to_string(),
+            backup_interval_hours: 24,
+            backup_keep: 7,
+            secret_backend: SecretBackend::default(),
+            search_all_profiles: false,
+            api_base_url: DEFAULT_API_BASE_URL.to_string(),
+            sync_interval_secs: 60,
+            idle_sync_interval_secs: 600,
+            max_backoff_secs: 1800,
+            proxy: None,
+            ca_bundle: None,
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            cache_dir: None,
+            overridden: Vec::new(),
+            extra: Map::new(),
+        }
+    }
+}
+
+/// Files from before the schema was versioned were often edited by hand;
+/// accept numbers and strings interchangeably where the type was easy to get wrong.
+fn migrate_v0_to_v1(map: &mut Map<String, Value>) {
+    if let Some(Value::Number(n)) = map.get("capture_folder_id") {
+        let id = n.to_string();
+        map.insert("capture_folder_id".to_string(), Value::String(id));
+    }
+    for key in ["backup_interval_hours", "backup_keep"] {
+        if let Some(n) = map.get(key).and_then(Value::as_str).and_then(|s| s.trim().parse::<u64>().ok()) {
+            map.insert(key.to_string(), Value::from(n));
         }
     }
 }
 
+/// Outcome of reading `config.json`.
+struct Parsed {
+    config: AppConfig,
+    /// Fields that couldn't be used and were replaced by their defaults.
+    problems: Vec<String>,
+    migrated: bool,
+}
+
 impl AppConfig {
-    fn get_config_path() -> Option<PathBuf> {
-        ProjectDirs::from("com", "minote", "MiNoteWebView")
-            .map(|proj_dirs| proj_dirs.config_dir().join("config.json"))
+    /// `--config` (or `MINOTE_CONFIG`) replaces the file of the profile in use at startup.
+    pub fn get_config_path(profile: &str) -> Option<PathBuf> {
+        if let Some(path) = overrides::config_path(profile) {
+            return Some(path);
+        }
+        profile::config_dir(profile).map(|dir| dir.join("config.json"))
+    }
+
+    /// Directory for local state that is not configuration (outbox, history, backups)
+    /// of the active profile.
+    pub fn data_dir() -> Option<PathBuf> {
+        Self::data_dir_of(&crate::api::config_service::current())
+    }
+
+    /// Directory for local state of the profile `config` belongs to. Work that may
+    /// outlive a profile switch resolves it once, up front (see `Client::data_dir`).
+    pub fn data_dir_of(config: &AppConfig) -> Option<PathBuf> {
+        match config.cache_dir {
+            // Several profiles may set the same directory
+            Some(ref dir) => Some(PathBuf::from(dir).join(&config.profile)),
+            None => profile::data_dir(&config.profile),
+        }
+    }
+
+    /// Settings from the file alone, without touching the secret store. Fields that
+    /// can't be used fall back to their defaults; the original file is kept as a backup.
+    fn read_file(profile: &str) -> Self {
+        let mut config = Self { profile: profile.to_string(), ..Self::default() };
+        let Some(path) = Self::get_config_path(profile) else { return config };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return config,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                return config;
+            }
+        };
+
+        let parsed = match Self::parse(&content) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                // Not JSON at all: move it out of the way so it is reported only once
+                match json_file::set_aside(&path) {
+                    Ok(backup) => eprintln!("{} is unreadable ({}); moved it to {} and using defaults", path.display(), e, backup.display()),
+                    Err(move_err) => eprintln!("{} is unreadable ({}) and could not be moved: {}", path.display(), e, move_err),
+                }
+                return config;
+            }
+        };
+        config = Self { profile: profile.to_string(), ..parsed.config };
+
+        if !parsed.migrated && parsed.problems.is_empty() {
+            return config;
+        }
+        for problem in &parsed.problems {
+            eprintln!("{}: {}", path.display(), problem);
+        }
+        // Every rewrite, migration-only ones included, keeps the original first
+        match Self::back_up(&path, &content) {
+            Ok(backup) if !parsed.problems.is_empty() => eprintln!("The original settings were saved to {}", backup.display()),
+            Ok(_) => {}
+            Err(e) => {
+                // Don't overwrite the only copy
+                eprintln!("Failed to back up {}: {}", path.display(), e);
+                return config;
+            }
+        }
+        // With a plaintext cookie the file is left alone: `load_profile` moves the
+        // cookie into the secret store first and only then rewrites it
+        if config.account_cookie.is_none() {
+            config.write_file().unwrap_or_else(|e| eprintln!("Failed to update {}: {}", path.display(), e));
+        }
+        config
+    }
+
+    /// Migrates and deserializes a config file, field by field if it has to.
+    fn parse(content: &str) -> crate::api::MiResult<Parsed> {
+        let Value::Object(mut map) = serde_json::from_str(content)? else {
+            return Err("expected a JSON object".into());
+        };
+
+        let version = map.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+        if version > CONFIG_VERSION {
+            eprintln!("config.json was written by a newer version (schema {}, this build knows {}); unknown settings are kept as they are", version, CONFIG_VERSION);
+        }
+        for migration in MIGRATIONS.iter().skip(version as usize) {
+            migration(&mut map);
+        }
+        let migrated = version < CONFIG_VERSION;
+        map.insert("version".to_string(), Value::from(CONFIG_VERSION.max(version)));
+
+        let mut problems = Vec::new();
+        let mut config: Self = match serde_json::from_value(Value::Object(map.clone())) {
+            Ok(config) => config,
+            Err(_) => {
+                // Keep every field that deserializes on its own, default the rest
+                let mut good = match serde_json::to_value(Self::default())? {
+                    Value::Object(defaults) => defaults,
+                    _ => Map::new(),
+                };
+                for (key, value) in map {
+                    let mut trial = good.clone();
+                    trial.insert(key.clone(), value);
+                    match serde_json::from_value::<Self>(Value::Object(trial.clone())) {
+                        Ok(_) => good = trial,
+                        Err(e) => problems.push(format!("{}: {} (using the default)", key, e)),
+                    }
+                }
+                serde_json::from_value(Value::Object(good))?
+            }
+        };
+        problems.extend(config.repair());
+        Ok(Parsed { config, problems, migrated })
+    }
+
+    /// Name of the setting holding an action's hotkey.
+    fn hotkey_field(action: HotkeyAction) -> &'static str {
+        match action {
+            HotkeyAction::ToggleLaunchBar => "hotkey",
+            HotkeyAction::QuickCapture => "quick_capture_hotkey",
+            HotkeyAction::OpenMainWindow => "open_window_hotkey",
+            HotkeyAction::SearchClipboard => "search_clipboard_hotkey",
+        }
+    }
+
+    /// Every bound global hotkey; the launch bar one is always set.
+    pub fn hotkey_bindings(&self) -> Vec<(HotkeyAction, Hotkey)> {
+        let optional = [
+            (HotkeyAction::QuickCapture, self.quick_capture_hotkey),
+            (HotkeyAction::OpenMainWindow, self.open_window_hotkey),
+            (HotkeyAction::SearchClipboard, self.search_clipboard_hotkey),
+        ];
+        std::iter::once((HotkeyAction::ToggleLaunchBar, self.hotkey))
+            .chain(optional.into_iter().filter_map(|(action, hotkey)| hotkey.map(|h| (action, h))))
+            .collect()
+    }
+
+    /// Problems with the current values, as messages naming the setting.
+    fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        for (first, second, hotkey) in hotkey::conflicts(&self.hotkey_bindings()) {
+            // The launch bar comes first, so it keeps its hotkey when repaired
+            problems.push((Self::hotkey_field(second), format!("{} is already bound to {}", hotkey, first.label())));
+        }
+        if self.capture_folder_id.is_empty() || !self.capture_folder_id.chars().all(|c| c.is_ascii_digit()) {
+            problems.push(("capture_folder_id", format!("'{}' is not a folder id; folder ids are numbers (0 is All notes)", self.capture_folder_id)));
+        }
+        if self.backup_keep == 0 {
+            problems.push(("backup_keep", "must keep at least 1 backup".to_string()));
+        }
+        if self.user_agent.as_deref().is_some_and(|ua| ua.trim().is_empty() || ua.contains(['\r', '\n'])) {
+            problems.push(("user_agent", "must be a single non-empty line".to_string()));
+        }
+        if !(self.api_base_url.starts_with("https://") || self.api_base_url.starts_with("http://")) || self.api_base_url.contains(char::is_whitespace) {
+            problems.push(("api_base_url", format!("'{}' is not an http(s) URL", self.api_base_url)));
+        }
+        for (field, secs) in [("sync_interval_secs", self.sync_interval_secs), ("idle_sync_interval_secs", self.idle_sync_interval_secs), ("max_backoff_secs", self.max_backoff_secs)] {
+            if secs < 10 {
+                problems.push((field, "must be at least 10 seconds".to_string()));
+            }
+        }
+        if let Some(e) = self.proxy.as_deref().filter(|p| *p != http::NO_PROXY).and_then(|p| http::parse_proxy(p).err()) {
+            problems.push(("proxy", e.to_string()));
+        }
+        if let Some(e) = self.ca_bundle.as_deref().and_then(|path| http::load_certificates(path).err()) {
+            problems.push(("ca_bundle", e.to_string()));
+        }
+        for (field, secs) in [("connect_timeout_secs", self.connect_timeout_secs), ("read_timeout_secs", self.read_timeout_secs)] {
+            if secs == 0 {
+                problems.push((field, "must be at least 1 second".to_string()));
+            }
+        }
+        if self.cache_dir.as_deref().is_some_and(|dir| dir.trim().is_empty()) {
+            problems.push(("cache_dir", "must be a directory path".to_string()));
+        }
+        problems
+    }
+
+    /// Checks the settings, e.g. before saving what the user entered.
+    pub fn validate(&self) -> crate::api::MiResult<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(problems.iter().map(|(field, msg)| format!("{}: {}", field, msg)).collect::<Vec<_>>().join("\n").into())
     }
 
+    /// Resets invalid settings to their defaults, returning what was changed.
+    fn repair(&mut self) -> Vec<String> {
+        let defaults = Self::default();
+        let mut repaired = Vec::new();
+        for (field, msg) in self.problems() {
+            match field {
+                "capture_folder_id" => self.capture_folder_id = defaults.capture_folder_id.clone(),
+                "backup_keep" => self.backup_keep = defaults.backup_keep,
+                "user_agent" => self.user_agent = None,
+                "api_base_url" => self.api_base_url = defaults.api_base_url.clone(),
+                "sync_interval_secs" => self.sync_interval_secs = defaults.sync_interval_secs,
+                "idle_sync_interval_secs" => self.idle_sync_interval_secs = defaults.idle_sync_interval_secs,
+                "max_backoff_secs" => self.max_backoff_secs = defaults.max_backoff_secs,
+                "proxy" => self.proxy = None,
+                "ca_bundle" => self.ca_bundle = None,
+                "connect_timeout_secs" => self.connect_timeout_secs = defaults.connect_timeout_secs,
+                "read_timeout_secs" => self.read_timeout_secs = defaults.read_timeout_secs,
+                "cache_dir" => self.cache_dir = None,
+                "quick_capture_hotkey" => self.quick_capture_hotkey = None,
+                "open_window_hotkey" => self.open_window_hotkey = None,
+                "search_clipboard_hotkey" => self.search_clipboard_hotkey = None,
+                _ => {}
+            }
+            repaired.push(format!("{}: {} (using the default)", field, msg));
+        }
+        repaired
+    }
+
+    /// Writes `content`, the file as read, to a timestamped `.bak` next to it. A
+    /// plaintext account cookie is left out, since it is about to move into the
+    /// secret store.
+    fn back_up(path: &std::path::Path, content: &str) -> crate::api::MiResult<PathBuf> {
+        let scrubbed = serde_json::from_str::<Map<String, Value>>(content).ok()
+            .filter(|map| map.contains_key(ACCOUNT_COOKIE_KEY))
+            .map(|mut map| {
+                map.remove(ACCOUNT_COOKIE_KEY);
+                serde_json::to_string_pretty(&map)
+            })
+            .transpose()?;
+        let backup = json_file::backup_path(path);
+        fs::write(&backup, scrubbed.as_deref().unwrap_or(content))?;
+        Ok(backup)
+    }
+
+    /// The secret store selected in the active profile's config.
+    pub fn secret_store() -> Box<dyn SecretStore> {
+        secrets::open(crate::api::config_service::current().secret_backend)
+    }
+
+    fn cookie_key(&self) -> String {
+        profile::secret_key(ACCOUNT_COOKIE_KEY, &self.profile)
+    }
+
+    /// The settings of the profile in use, with the environment and command-line overrides.
     pub fn load() -> Self {
-        if let Some(path) = Self::get_config_path() {
-            if let Ok(content) = fs::read_to_string(path) {
-                return serde_json::from_str(&content).unwrap_or_default();
+        let config = Self::load_profile(&profile::active());
+        match config.with_overrides() {
+            Ok(overridden) => overridden,
+            Err(e) => {
+                eprintln!("Ignoring the setting overrides: {}", e);
+                config
+            }
+        }
+    }
+
+    /// Fails if the `MINOTE_*` variables or `--set` flags give unusable values.
+    pub fn check_overrides() -> crate::api::MiResult<()> {
+        Self::load_profile(&profile::active()).with_overrides().map(|_| ())
+    }
+
+    /// Every setting that can be read, set or overridden, by its `config.json` name.
+    pub fn setting_keys() -> Vec<String> {
+        let mut keys = vec![ACCOUNT_COOKIE_KEY.to_string()];
+        if let Ok(Value::Object(map)) = serde_json::to_value(Self::default()) {
+            keys.extend(map.into_iter().map(|(key, _)| key).filter(|key| key != "version"));
+        }
+        keys
+    }
+
+    /// Current value of a setting as JSON; the cookie only says whether it is set.
+    pub fn get_value(&self, key: &str) -> crate::api::MiResult<Value> {
+        overrides::check_key(key, &Self::setting_keys())?;
+        if key == ACCOUNT_COOKIE_KEY {
+            return Ok(self.account_cookie.as_ref().map(|_| Value::from("(set)")).unwrap_or(Value::Null));
+        }
+        let Value::Object(map) = serde_json::to_value(self)? else { return Ok(Value::Null) };
+        Ok(map.get(key).cloned().unwrap_or(Value::Null))
+    }
+
+    /// Sets a setting from text as typed on the command line or in a variable:
+    /// JSON where the setting needs it (`true`, `12`), a plain string otherwise,
+    /// and empty for "not set". Doesn't save.
+    pub fn set_value(&mut self, key: &str, raw: &str) -> crate::api::MiResult<()> {
+        overrides::check_key(key, &Self::setting_keys())?;
+        if key == ACCOUNT_COOKIE_KEY {
+            self.account_cookie = Some(raw.trim()).filter(|c| !c.is_empty()).map(str::to_string);
+            return Ok(());
+        }
+
+        let mut candidates = Vec::new();
+        if raw.trim().is_empty() {
+            candidates.push(Value::Null);
+        }
+        if let Ok(json) = serde_json::from_str::<Value>(raw) {
+            candidates.push(json);
+        }
+        candidates.push(Value::String(raw.to_string()));
+
+        let Value::Object(map) = serde_json::to_value(&*self)? else { return Err("settings are not an object".into()) };
+        let mut last_error = String::new();
+        for candidate in candidates {
+            let mut trial = map.clone();
+            trial.insert(key.to_string(), candidate);
+            match serde_json::from_value::<Self>(Value::Object(trial)) {
+                Ok(parsed) => {
+                    *self = Self {
+                        profile: std::mem::take(&mut self.profile),
+                        account_cookie: self.account_cookie.take(),
+                        overridden: std::mem::take(&mut self.overridden),
+                        ..parsed
+                    };
+                    return Ok(());
+                }
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(format!("'{}' is not a valid value for {}: {}", raw, key, last_error).into())
+    }
+
+    /// A copy with the `MINOTE_*` variables and `--set` flags applied, in that order.
+    fn with_overrides(&self) -> crate::api::MiResult<Self> {
+        let mut config = self.clone();
+        for (key, raw, source) in overrides::layers(&Self::setting_keys()) {
+            config.set_value(&key, &raw).map_err(|e| format!("{} ({})", e, source))?;
+            if !config.overridden.contains(&key) {
+                config.overridden.push(key);
+            }
+        }
+        config.validate().map_err(|e| format!("The overridden settings are invalid:\n{}", e))?;
+        Ok(config)
+    }
+
+    /// Every setting with its effective value and where that value came from.
+    pub fn sources(&self) -> Vec<(String, Value, Source)> {
+        let path = Self::get_config_path(&self.profile);
+        let in_file = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str::<Map<String, Value>>(&content).ok())
+            .unwrap_or_default();
+        let keys = Self::setting_keys();
+        let layers = overrides::layers(&keys);
+        keys.into_iter().map(|key| {
+            let value = self.get_value(&key).unwrap_or(Value::Null);
+            let source = layers.iter().rev().find(|(k, _, _)| *k == key).map(|(_, _, source)| source.clone())
+                .or_else(|| (key == ACCOUNT_COOKIE_KEY && self.account_cookie.is_some()).then_some(Source::SecretStore))
+                .or_else(|| path.clone().filter(|_| in_file.contains_key(&key)).map(Source::File))
+                .unwrap_or(Source::Default);
+            (key, value, source)
+        }).collect()
+    }
+
+    pub fn load_profile(profile: &str) -> Self {
+        let mut config = Self::read_file(profile);
+        if config.account_cookie.is_some() {
+            // Plaintext cookie from an older version: move it into the secret store
+            if let Err(e) = config.save() {
+                eprintln!("Failed to move the account cookie into secret storage: {}", e);
             }
+        } else {
+            config.read_cookie();
         }
-        Self::default()
+        config
+    }
+
+    fn read_cookie(&mut self) {
+        self.account_cookie = secrets::open(self.secret_backend).get(&self.cookie_key())
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to read the account cookie from secret storage: {}", e);
+                None
+            });
+    }
+
+    /// Reads the settings without repairing or rewriting anything; any problem is an
+    /// error. Used for edits made while the app runs, which shouldn't be moved aside.
+    pub fn read_checked(profile: &str) -> crate::api::MiResult<Self> {
+        let path = Self::get_config_path(profile).ok_or("No config directory available")?;
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => {
+                let parsed = Self::parse(&content)?;
+                if !parsed.problems.is_empty() {
+                    return Err(parsed.problems.join("\n").into());
+                }
+                parsed.config
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        config.profile = profile.to_string();
+        // A plaintext cookie is kept as it is until the next save migrates it
+        if config.account_cookie.is_none() {
+            config.read_cookie();
+        }
+        if profile == profile::active() {
+            config = config.with_overrides()?;
+        }
+        Ok(config)
     }
 
     pub fn save(&self) -> crate::api::MiResult<()> {
-        if let Some(path) = Self::get_config_path() {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
+        self.validate()?;
+        // Store the secret before rewriting the file, so a failed migration keeps the plaintext copy
+        if let Some(cookie) = self.account_cookie.as_ref().filter(|_| !self.overridden.iter().any(|k| k == ACCOUNT_COOKIE_KEY)) {
+            let store = secrets::open(self.secret_backend);
+            let key = self.cookie_key();
+            if store.get(&key).ok().flatten().as_deref() != Some(cookie) {
+                store.set(&key, cookie)?;
             }
-            let content = serde_json::to_string_pretty(self)?;
-            fs::write(path, content)?;
+        }
+
+        self.write_file()
+    }
+
+    /// Writes the settings (never the cookie) to `config.json`.
+    fn write_file(&self) -> crate::api::MiResult<()> {
+        if let Some(path) = Self::get_config_path(&self.profile) {
+            let mut value = serde_json::to_value(self)?;
+            if let Value::Object(ref mut map) = value {
+                // Overrides last for this run only; keep what the file says for them
+                let on_disk = fs::read_to_string(&path).ok()
+                    .and_then(|content| serde_json::from_str::<Map<String, Value>>(&content).ok())
+                    .unwrap_or_default();
+                for key in &self.overridden {
+                    match on_disk.get(key) {
+                        Some(original) => map.insert(key.clone(), original.clone()),
+                        None => map.remove(key),
+                    };
+                }
+            }
+            // Replace the file in one step so the watcher never sees it half-written
+            json_file::save(&path, &value)?;
         }
         Ok(())
     }
+
+    /// Forgets the stored account cookie, e.g. when the profile is removed.
+    pub fn delete_secrets(&self) {
+        secrets::open(self.secret_backend).delete(&self.cookie_key()).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minote-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn enums_read_the_legacy_string_values() {
+        // Before they were typed, `destination` and `theme` were free strings with these values
+        for (legacy, destination) in [("Clipboard", Destination::Clipboard), ("PreviousProgram", Destination::PreviousProgram)] {
+            let json = format!("\"{}\"", legacy);
+            assert_eq!(serde_json::from_str::<Destination>(&json).unwrap(), destination);
+            assert_eq!(serde_json::to_string(&destination).unwrap(), json);
+        }
+        for (legacy, theme) in [("Desktop", Theme::Desktop), ("Mobile", Theme::Mobile)] {
+            let json = format!("\"{}\"", legacy);
+            assert_eq!(serde_json::from_str::<Theme>(&json).unwrap(), theme);
+            assert_eq!(serde_json::to_string(&theme).unwrap(), json);
+        }
+        assert!(serde_json::from_str::<Theme>("\"Tablet\"").is_err());
+    }
+
+    #[test]
+    fn legacy_file_keeps_its_typed_settings() {
+        let parsed = AppConfig::parse(r#"{"hotkey":"Ctrl-Shift-N","destination":"PreviousProgram","theme":"Desktop"}"#).unwrap();
+        assert!(parsed.problems.is_empty());
+        assert_eq!(parsed.config.hotkey.to_string(), "Ctrl-Shift-N");
+        assert_eq!(parsed.config.destination, Destination::PreviousProgram);
+        assert_eq!(parsed.config.theme, Theme::Desktop);
+    }
+
+    #[test]
+    fn repairing_a_hotkey_conflict_keeps_the_launch_bar_binding() {
+        let parsed = AppConfig::parse(r#"{"hotkey":"Alt-L","quick_capture_hotkey":"Alt-L","open_window_hotkey":"Ctrl-Alt-M"}"#).unwrap();
+        assert_eq!(parsed.problems.len(), 1, "{:?}", parsed.problems);
+        assert_eq!(parsed.config.hotkey.to_string(), "Alt-L");
+        assert_eq!(parsed.config.quick_capture_hotkey, None);
+        assert_eq!(parsed.config.open_window_hotkey.map(|h| h.to_string()).as_deref(), Some("Ctrl-Alt-M"));
+    }
+
+    #[test]
+    fn cache_dir_is_split_by_profile() {
+        let dir = std::env::temp_dir().join("minote-cache");
+        let config_for = |profile: &str| AppConfig {
+            profile: profile.to_string(),
+            cache_dir: Some(dir.to_string_lossy().into_owned()),
+            ..AppConfig::default()
+        };
+        assert_eq!(AppConfig::data_dir_of(&config_for("work")), Some(dir.join("work")));
+        assert_ne!(AppConfig::data_dir_of(&config_for("work")), AppConfig::data_dir_of(&config_for("default")));
+    }
+
+    #[test]
+    fn unversioned_file_is_migrated() {
+        let parsed = AppConfig::parse(r#"{"capture_folder_id":12,"backup_keep":"3"}"#).unwrap();
+        assert!(parsed.migrated);
+        assert!(parsed.problems.is_empty());
+        assert_eq!(parsed.config.capture_folder_id, "12");
+        assert_eq!(parsed.config.backup_keep, 3);
+        assert!(!AppConfig::parse(&format!(r#"{{"version":{}}}"#, CONFIG_VERSION)).unwrap().migrated);
+    }
+
+    #[test]
+    fn backup_leaves_out_a_plaintext_cookie() {
+        let dir = scratch_dir("backup");
+        let path = dir.join("config.json");
+        let backup = AppConfig::back_up(&path, r#"{"account_cookie":"passToken=secret","backup_keep":3}"#).unwrap();
+        let saved = fs::read_to_string(&backup).unwrap();
+        assert!(!saved.contains("secret"));
+        assert!(saved.contains("\"backup_keep\": 3"));
+
+        // Anything else, even unparseable, is kept byte for byte
+        let backup = AppConfig::back_up(&path, "{\"backup_keep\":3}").unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "{\"backup_keep\":3}");
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
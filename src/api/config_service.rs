@@ -0,0 +1,137 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use crate::api::{AppConfig, MiResult};
+use crate::api::profile;
+
+/// How often `config.json` is checked for edits made outside the app. Polling one
+/// small file is cheap and, unlike a file watch, survives editors that save by
+/// replacing the file.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The active profile's settings, loaded once and shared. Readers take the current
+/// value with `current()`; anything that reacts to changes holds a `subscribe()` receiver.
+struct Service {
+    sender: watch::Sender<Arc<AppConfig>>,
+    /// Modification time of `config.json` as of our last read or write.
+    stamp: Mutex<Option<SystemTime>>,
+}
+
+static SERVICE: Lazy<Service> = Lazy::new(|| {
+    let config = AppConfig::load();
+    let stamp = file_stamp(&config.profile);
+    Service {
+        sender: watch::channel(Arc::new(config)).0,
+        stamp: Mutex::new(stamp),
+    }
+});
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Settings of another profile, with the modification time of the file they were read from.
+type Cached = (Option<SystemTime>, Arc<AppConfig>);
+
+static OTHER_PROFILES: Lazy<Mutex<HashMap<String, Cached>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn file_stamp(profile: &str) -> Option<SystemTime> {
+    AppConfig::get_config_path(profile)
+        .and_then(|path| std::fs::metadata(path).ok())
+        .and_then(|meta| meta.modified().ok())
+}
+
+/// Replaces the shared config, notifying subscribers only if something changed.
+fn publish(config: AppConfig) -> Arc<AppConfig> {
+    let config = Arc::new(config);
+    SERVICE.sender.send_if_modified(|current| {
+        if **current == *config {
+            return false;
+        }
+        *current = config.clone();
+        true
+    });
+    config
+}
+
+pub fn current() -> Arc<AppConfig> {
+    SERVICE.sender.borrow().clone()
+}
+
+/// The settings of `profile`: the shared ones for the active profile, otherwise a
+/// copy that is read again only when that profile's `config.json` changes.
+pub fn of_profile(profile: &str) -> Arc<AppConfig> {
+    let active = current();
+    if active.profile == profile {
+        return active;
+    }
+    let mut cache = OTHER_PROFILES.lock().unwrap();
+    if let Some((_, config)) = cache.get(profile).filter(|(stamp, _)| *stamp == file_stamp(profile)) {
+        return config.clone();
+    }
+    let config = Arc::new(AppConfig::load_profile(profile));
+    // Stamped after loading, which may have rewritten an old file
+    cache.insert(profile.to_string(), (file_stamp(profile), config.clone()));
+    config
+}
+
+/// A receiver that is marked changed whenever the settings change, from this
+/// process or from an edit to `config.json`.
+pub fn subscribe() -> watch::Receiver<Arc<AppConfig>> {
+    SERVICE.sender.subscribe()
+}
+
+/// Applies `change` to the current settings, validates and saves them, then
+/// notifies subscribers. Nothing is published if saving fails.
+pub fn update(change: impl FnOnce(&mut AppConfig)) -> MiResult<Arc<AppConfig>> {
+    let mut config = (*current()).clone();
+    change(&mut config);
+    config.save()?;
+    *SERVICE.stamp.lock().unwrap() = file_stamp(&config.profile);
+    Ok(publish(config))
+}
+
+/// Loads the active profile's settings again, e.g. after switching profiles.
+pub fn reload() -> Arc<AppConfig> {
+    let config = AppConfig::load();
+    *SERVICE.stamp.lock().unwrap() = file_stamp(&config.profile);
+    publish(config)
+}
+
+/// Starts polling `config.json` for outside edits. Valid edits are published;
+/// invalid ones are reported and the previous settings stay in effect.
+pub fn watch() {
+    if WATCHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    std::thread::spawn(|| loop {
+        std::thread::sleep(POLL_INTERVAL);
+        check_for_edits();
+    });
+}
+
+fn check_for_edits() {
+    let profile = profile::active();
+    if current().profile != profile {
+        reload();
+        return;
+    }
+
+    let stamp = file_stamp(&profile);
+    {
+        let mut last = SERVICE.stamp.lock().unwrap();
+        if *last == stamp {
+            return;
+        }
+        *last = stamp;
+    }
+
+    match AppConfig::read_checked(&profile) {
+        Ok(config) => {
+            crate::dprintln!("[Config] config.json changed on disk; reloading");
+            publish(config);
+        }
+        Err(e) => eprintln!("Ignoring the edit to config.json, the current settings stay in effect:\n{}", e),
+    }
+}
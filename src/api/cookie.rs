@@ -0,0 +1,438 @@
+use reqwest::Url;
+use crate::api::secrets::redact;
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// `(name, value)` pairs of a `Cookie` header ("a=1; b=2"), the format account
+/// cookies and sessions are stored in. Values may contain `=`; pairs without one are skipped.
+pub fn pairs(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .filter(|(k, _)| !k.is_empty())
+}
+
+/// Value of the cookie named exactly `name` in a `Cookie` header.
+pub fn value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    pairs(header).find(|(k, _)| *k == name).map(|(_, v)| v)
+}
+
+/// One stored cookie, as in RFC 6265 section 5.3.
+#[derive(Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase host or domain, without a leading dot.
+    pub domain: String,
+    /// Sent to `domain` only, not its subdomains (no `Domain` attribute was given).
+    pub host_only: bool,
+    pub path: String,
+    /// Milliseconds since the epoch; `None` for a session cookie.
+    pub expires_at: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl std::fmt::Debug for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cookie")
+            .field("name", &self.name)
+            .field("value", &redact(&format!("{}={}", self.name, self.value)))
+            .field("domain", &self.domain)
+            .field("host_only", &self.host_only)
+            .field("path", &self.path)
+            .field("expires_at", &self.expires_at)
+            .field("secure", &self.secure)
+            .field("http_only", &self.http_only)
+            .finish()
+    }
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` header received from `url` (RFC 6265 section 5.2). `None`
+    /// if it is malformed or names a domain `url` isn't part of. There is no public
+    /// suffix list; the app only talks to Xiaomi hosts and the configured server.
+    pub fn parse(set_cookie: &str, url: &Url) -> Option<Self> {
+        let host = url.host_str()?.to_lowercase();
+        let (pair, attributes) = set_cookie.split_once(';').unwrap_or((set_cookie, ""));
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires_at: None,
+            secure: false,
+            http_only: false,
+        };
+        let mut max_age = None;
+        let mut expires = None;
+        for attribute in attributes.split(';') {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                // Commas are part of the date ("Wed, 21 Oct 2015 07:28:00 GMT")
+                "expires" => expires = parse_date(value).or(expires),
+                "max-age" => max_age = parse_max_age(value).or(max_age),
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_lowercase();
+                    if !domain.is_empty() {
+                        if !domain_matches(&host, &domain) {
+                            return None;
+                        }
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                }
+                "path" => {
+                    cookie.path = if value.starts_with('/') { value.to_string() } else { default_path(url) };
+                }
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires wherever they appear
+        cookie.expires_at = max_age.or(expires);
+        Some(cookie)
+    }
+
+    /// A cookie from a `Cookie` header pair, sent to `url`'s site on every path.
+    /// Domain names include their subdomains; IP hosts (local mocks) can't.
+    pub fn for_site(name: &str, value: &str, url: &Url) -> Option<Self> {
+        Some(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: url.host_str()?.to_lowercase(),
+            host_only: url.domain().is_none(),
+            path: "/".to_string(),
+            expires_at: None,
+            secure: false,
+            http_only: false,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= now_ms())
+    }
+
+    /// Whether this cookie goes with a request to `url` (RFC 6265 section 5.4).
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str().map(str::to_lowercase) else {
+            return false;
+        };
+        let host_ok = if self.host_only { host == self.domain } else { domain_matches(&host, &self.domain) };
+        host_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "https")
+    }
+
+    fn same_slot(&self, other: &Self) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// `host` is `domain` or a subdomain of it; IP addresses only match themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    let is_ip = host.starts_with('[') || host.parse::<std::net::Ipv4Addr>().is_ok();
+    !is_ip && host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The directory of the request path: "/note/full/page" gives "/note/full".
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => url.path()[..i].to_string(),
+    }
+}
+
+/// Zero or negative means already expired.
+fn parse_max_age(value: &str) -> Option<i64> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs = value.parse::<i64>().unwrap_or(if value.starts_with('-') { i64::MIN } else { i64::MAX });
+    if secs <= 0 {
+        return Some(i64::MIN);
+    }
+    Some(now_ms().saturating_add(secs.saturating_mul(1000)))
+}
+
+/// `Expires` dates as servers send them: RFC 1123, the dashed RFC 850 form
+/// ("Wed, 21-Oct-15 07:28:00 GMT") and asctime.
+fn parse_date(value: &str) -> Option<i64> {
+    // The weekday is redundant, and RFC 850 spells it out in full
+    let date = value.split_once(',').map_or(value, |(_, date)| date).trim();
+    chrono::DateTime::parse_from_rfc2822(date)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(&date.replace('-', " ")))
+        .map(|d| d.timestamp_millis())
+        .ok()
+        .or_else(|| {
+            let asctime = value.split_whitespace().skip(1).collect::<Vec<_>>().join(" ");
+            chrono::NaiveDateTime::parse_from_str(&asctime, "%b %d %H:%M:%S %Y").ok()
+                .map(|d| d.and_utc().timestamp_millis())
+        })
+}
+
+/// Cookies by name, domain and path, with their expiry.
+#[derive(Clone, Default, Debug)]
+pub struct CookieJar {
+    /// In the order they were (last) set.
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// The cookies of a stored `Cookie` header ("a=1; b=2"), sent to `url`'s site.
+    pub fn from_header(header: &str, url: &Url) -> Self {
+        let mut jar = Self::default();
+        jar.add_header(header, url);
+        jar
+    }
+
+    pub fn add_header(&mut self, header: &str, url: &Url) {
+        for (name, value) in pairs(header) {
+            if let Some(cookie) = Cookie::for_site(name, value, url) {
+                self.insert(cookie);
+            }
+        }
+    }
+
+    /// Stores a `Set-Cookie` header received from `url`; false if it was rejected.
+    pub fn set_cookie(&mut self, set_cookie: &str, url: &Url) -> bool {
+        match Cookie::parse(set_cookie, url) {
+            Some(cookie) => {
+                self.insert(cookie);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the cookie with the same name, domain and path; an expired one
+    /// just removes it.
+    pub fn insert(&mut self, cookie: Cookie) {
+        self.cookies.retain(|c| !c.same_slot(&cookie) && !c.is_expired());
+        if !cookie.is_expired() {
+            self.cookies.push(cookie);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie> {
+        self.cookies.iter().filter(|c| !c.is_expired())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// The most recently set cookie called `name`, for any domain.
+    pub fn get(&self, name: &str) -> Option<&Cookie> {
+        self.iter().filter(|c| c.name == name).last()
+    }
+
+    /// Cookies for a request to `url`, longest path first.
+    pub fn matching(&self, url: &Url) -> Vec<&Cookie> {
+        let mut cookies: Vec<_> = self.iter().filter(|c| c.matches(url)).collect();
+        cookies.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        cookies
+    }
+
+    /// Value of the cookie called `name` that a request to `url` would carry.
+    pub fn value(&self, url: &Url, name: &str) -> Option<&str> {
+        self.matching(url).into_iter().find(|c| c.name == name).map(|c| c.value.as_str())
+    }
+
+    /// The `Cookie` header for a request to `url`, empty if there is none.
+    pub fn header(&self, url: &Url) -> String {
+        join(self.matching(url))
+    }
+
+    /// Every cookie as one `Cookie` header, sorted by name; where several domains
+    /// set the same name the latest wins. This is how sessions are stored.
+    pub fn to_cookie_string(&self) -> String {
+        let mut latest: Vec<&Cookie> = Vec::new();
+        for cookie in self.iter() {
+            latest.retain(|c| c.name != cookie.name);
+            latest.push(cookie);
+        }
+        latest.sort_by(|a, b| a.name.cmp(&b.name));
+        join(latest)
+    }
+}
+
+fn join(cookies: Vec<&Cookie>) -> String {
+    cookies.iter().map(|c| format!("{}={}", c.name, c.value)).collect::<Vec<_>>().join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2015-10-21 07:28:00 UTC
+    const OCT_21_2015: i64 = 1_445_412_480_000;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn parse(set_cookie: &str) -> Option<Cookie> {
+        Cookie::parse(set_cookie, &url("https://i.mi.com/note/full/page"))
+    }
+
+    #[test]
+    fn expires_may_contain_commas() {
+        let cookie = parse("serviceToken=abc; Path=/; Expires=Wed, 21 Oct 2015 07:28:00 GMT; HttpOnly; Secure").unwrap();
+        assert_eq!(cookie.value, "abc");
+        assert_eq!(cookie.expires_at, Some(OCT_21_2015));
+        assert!(cookie.http_only && cookie.secure);
+        assert!(cookie.is_expired());
+    }
+
+    #[test]
+    fn expires_accepts_rfc_850_and_asctime() {
+        for date in ["Wednesday, 21-Oct-15 07:28:00 GMT", "Wed, 21-Oct-2015 07:28:00 GMT", "Wed Oct 21 07:28:00 2015"] {
+            assert_eq!(parse_date(date), Some(OCT_21_2015), "{}", date);
+        }
+        assert_eq!(parse_date("Wed Oct  1 07:28:00 2015"), Some(OCT_21_2015 - 20 * 24 * 3600 * 1000));
+        assert_eq!(parse_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn max_age_wins_over_expires() {
+        // In either order
+        let cookie = parse("a=1; Max-Age=3600; Expires=Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert!(!cookie.is_expired());
+        let cookie = parse("a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=3600").unwrap();
+        let expected = now_ms() + 3600 * 1000;
+        assert!((cookie.expires_at.unwrap() - expected).abs() < 5000);
+
+        // Zero and negative expire at once, even with a future Expires
+        for max_age in ["0", "-1", "-99999999999999999999"] {
+            let cookie = parse(&format!("a=1; Expires=Fri, 01 Jan 2100 00:00:00 GMT; Max-Age={}", max_age)).unwrap();
+            assert!(cookie.is_expired(), "Max-Age={}", max_age);
+        }
+        // An unusable Max-Age is ignored
+        assert_eq!(parse("a=1; Max-Age=soon; Expires=Wed, 21 Oct 2015 07:28:00 GMT").unwrap().expires_at, Some(OCT_21_2015));
+        assert_eq!(parse("a=1").unwrap().expires_at, None);
+    }
+
+    #[test]
+    fn domain_attribute() {
+        let cookie = parse("a=1; Domain=.mi.com; Path=/").unwrap();
+        assert_eq!(cookie.domain, "mi.com");
+        assert!(!cookie.host_only);
+        assert!(cookie.matches(&url("https://account.xiaomi.mi.com/")));
+        assert!(cookie.matches(&url("https://mi.com/")));
+        assert!(!cookie.matches(&url("https://notmi.com/")));
+
+        // A domain the response didn't come from is rejected
+        assert!(parse("a=1; Domain=example.com").is_none());
+        assert!(parse("a=1; Domain=account.i.mi.com").is_none());
+
+        // Without one, only the host itself gets the cookie
+        let cookie = parse("a=1; Path=/").unwrap();
+        assert!(cookie.host_only);
+        assert!(cookie.matches(&url("https://i.mi.com/note/")));
+        assert!(!cookie.matches(&url("https://sub.i.mi.com/note/")));
+    }
+
+    #[test]
+    fn lookup_is_by_exact_name() {
+        let header = "serviceTokenX=wrong; serviceToken=right; userId=1";
+        assert_eq!(value(header, "serviceToken"), Some("right"));
+        assert_eq!(value(header, "serviceTokenX"), Some("wrong"));
+        assert_eq!(value(header, "service"), None);
+
+        let site = url("https://i.mi.com/");
+        let jar = CookieJar::from_header(header, &site);
+        assert_eq!(jar.value(&site, "serviceToken"), Some("right"));
+        assert_eq!(jar.get("serviceTokenX").map(|c| c.value.as_str()), Some("wrong"));
+    }
+
+    #[test]
+    fn values_may_contain_equals_signs() {
+        assert_eq!(value("passToken=V1:abc==; userId=1", "passToken"), Some("V1:abc=="));
+        assert_eq!(parse("serviceToken=a=b=; Path=/").unwrap().value, "a=b=");
+        // Pairs without `=` are skipped
+        assert_eq!(pairs("flag; a=1").collect::<Vec<_>>(), vec![("a", "1")]);
+    }
+
+    #[test]
+    fn path_matching() {
+        assert!(path_matches("/note", "/note"));
+        assert!(path_matches("/note/full", "/note"));
+        assert!(path_matches("/note/full", "/note/"));
+        assert!(path_matches("/anything", "/"));
+        assert!(!path_matches("/notebook", "/note"));
+        assert!(!path_matches("/", "/note"));
+
+        // The default path is the request's directory
+        assert_eq!(parse("a=1").unwrap().path, "/note/full");
+        assert_eq!(parse("a=1; Path=relative").unwrap().path, "/note/full");
+        assert_eq!(Cookie::parse("a=1", &url("https://i.mi.com/page")).unwrap().path, "/");
+    }
+
+    #[test]
+    fn ip_hosts_only_match_themselves() {
+        let local = url("http://127.0.0.1:8080/api");
+        let cookie = Cookie::parse("a=1; Domain=127.0.0.1", &local).unwrap();
+        assert!(cookie.matches(&local));
+        assert!(Cookie::parse("a=1; Domain=0.0.1", &local).is_none());
+        assert!(!domain_matches("127.0.0.1", "0.0.1"));
+        assert!(!domain_matches("[::1]", "1]"));
+
+        // Cookies restored from a header are host-only for IP hosts, site-wide for names
+        assert!(Cookie::for_site("a", "1", &local).unwrap().host_only);
+        assert!(!Cookie::for_site("a", "1", &url("https://i.mi.com/")).unwrap().host_only);
+    }
+
+    #[test]
+    fn secure_cookies_need_https() {
+        let cookie = parse("a=1; Secure").unwrap();
+        assert!(cookie.matches(&url("https://i.mi.com/note/full")));
+        assert!(!cookie.matches(&url("http://i.mi.com/note/full")));
+    }
+
+    #[test]
+    fn jar_replaces_by_name_domain_and_path() {
+        let site = url("https://i.mi.com/note/full/page");
+        let mut jar = CookieJar::default();
+        assert!(jar.set_cookie("a=1; Path=/", &site));
+        assert!(jar.set_cookie("a=2; Path=/note", &site));
+        assert!(jar.set_cookie("a=3; Path=/", &site));
+        // Longest path first
+        assert_eq!(jar.header(&site), "a=2; a=3");
+        assert!(jar.set_cookie("a=gone; Path=/; Max-Age=0", &site));
+        assert_eq!(jar.header(&site), "a=2");
+        assert!(!jar.set_cookie("b=1; Domain=example.com", &site));
+    }
+
+    #[test]
+    fn cookie_string_keeps_the_latest_of_each_name_sorted() {
+        let mut jar = CookieJar::from_header("userId=1; serviceToken=old; b=x", &url("https://i.mi.com/"));
+        jar.set_cookie("serviceToken=new; Domain=mi.com; Path=/", &url("https://sts.api.io.mi.com/sts"));
+        jar.set_cookie("a=1; Path=/", &url("https://i.mi.com/"));
+        assert_eq!(jar.to_cookie_string(), "a=1; b=x; serviceToken=new; userId=1");
+
+        // An expired cookie drops out instead of reviving an older value
+        jar.set_cookie("serviceToken=; Domain=mi.com; Path=/; Max-Age=0", &url("https://sts.api.io.mi.com/sts"));
+        assert_eq!(jar.to_cookie_string(), "a=1; b=x; serviceToken=old; userId=1");
+    }
+}
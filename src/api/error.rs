@@ -0,0 +1,45 @@
+use std::fmt;
+use crate::api::MiError;
+use crate::api::models::Note;
+
+/// Errors callers may want to match on. Everything else stays a boxed string error;
+/// use `downcast_ref::<ApiError>()` on a `MiError` to inspect these.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The tag sent with an update is stale; `remote` is the server's current version.
+    Conflict { remote: Box<Note> },
+    /// The envelope reported failure (`code` != 0 or `result` != "ok"), even if HTTP said 200.
+    Api { code: i64, description: String },
+    /// HTTP 429; `retry_after` is the server's `Retry-After`, if it sent one.
+    RateLimited { retry_after: Option<std::time::Duration> },
+    /// Non-2xx response without a readable envelope.
+    Http { status: u16, body: String },
+    /// The body could not be decoded into the expected shape.
+    UnexpectedResponse(String),
+    /// The session was rejected and could not be refreshed; only signing in again helps.
+    SessionExpired(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Conflict { remote } => write!(f, "Conflict: note {} was changed elsewhere (server tag {})", remote.id, remote.tag),
+            ApiError::Api { code, description } => write!(f, "API error {}: {}", code, description),
+            ApiError::RateLimited { retry_after: Some(wait) } => write!(f, "Rate limited by the server; retry after {}s", wait.as_secs()),
+            ApiError::RateLimited { retry_after: None } => write!(f, "Rate limited by the server"),
+            ApiError::Http { status, body } => write!(f, "Request failed with status: {} (body: {})", status, body),
+            ApiError::UnexpectedResponse(msg) => write!(f, "Unexpected response: {}", msg),
+            ApiError::SessionExpired(reason) => write!(f, "Session expired: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// The server's current note if `e` is a tag conflict.
+pub fn as_conflict(e: &MiError) -> Option<&Note> {
+    match e.downcast_ref::<ApiError>() {
+        Some(ApiError::Conflict { remote }) => Some(remote),
+        _ => None,
+    }
+}
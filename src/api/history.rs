@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use crate::api::{Client, MiResult};
+use crate::api::json_file;
+use crate::api::models::Note;
+
+/// Versions kept per note; the oldest are dropped first.
+const MAX_VERSIONS: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteVersion {
+    pub tag: String,
+    pub modify_date: i64,
+    pub recorded_at: i64,
+    pub subject: String,
+    pub content: String,
+}
+
+/// Local snapshots of note contents, since the server only keeps the latest body.
+/// Versions are ordered oldest first. Like the outbox, functions take the data
+/// directory of the profile they work on, resolved once by the caller.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct NoteHistory {
+    pub notes: HashMap<String, Vec<NoteVersion>>,
+}
+
+static HISTORY_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+impl NoteHistory {
+    fn path_in(dir: Option<&Path>) -> Option<PathBuf> {
+        dir.map(|dir| dir.join("history.json"))
+    }
+
+    /// The snapshots on disk; an error (rather than no history) if they can't be
+    /// read, so recording a version never replaces the others.
+    fn read(dir: Option<&Path>) -> MiResult<Self> {
+        match Self::path_in(dir) {
+            Some(path) => Ok(json_file::load(&path)?.unwrap_or_default()),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn load(dir: Option<&Path>) -> Self {
+        Self::read(dir).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Self::default()
+        })
+    }
+
+    fn save(&self, dir: Option<&Path>) -> MiResult<()> {
+        if let Some(path) = Self::path_in(dir) {
+            json_file::save(&path, self)?;
+        }
+        Ok(())
+    }
+
+    pub fn versions(dir: Option<&Path>, id: &str) -> Vec<NoteVersion> {
+        let _guard = HISTORY_LOCK.lock().unwrap();
+        Self::load(dir).notes.remove(id).unwrap_or_default()
+    }
+
+    fn latest(&self, id: &str) -> Option<&NoteVersion> {
+        self.notes.get(id).and_then(|v| v.last())
+    }
+
+    /// True when the server copy is newer than the last snapshot.
+    fn is_stale(&self, note: &Note) -> bool {
+        self.latest(&note.id)
+            .is_none_or(|v| v.tag != note.tag || v.modify_date != note.modify_date)
+    }
+
+    fn push(&mut self, note: &Note) -> bool {
+        let Some(ref content) = note.content else { return false };
+        if !self.is_stale(note) {
+            return false;
+        }
+        let versions = self.notes.entry(note.id.clone()).or_default();
+        versions.push(NoteVersion {
+            tag: note.tag.clone(),
+            modify_date: note.modify_date,
+            recorded_at: chrono::Utc::now().timestamp_millis(),
+            subject: note.subject.clone(),
+            content: content.clone(),
+        });
+        if versions.len() > MAX_VERSIONS {
+            let excess = versions.len() - MAX_VERSIONS;
+            versions.drain(..excess);
+        }
+        true
+    }
+
+    /// Records a fully loaded note if its tag or modify date moved on.
+    pub fn record(dir: Option<&Path>, note: &Note) -> MiResult<bool> {
+        let _guard = HISTORY_LOCK.lock().unwrap();
+        let mut history = Self::read(dir)?;
+        let added = history.push(note);
+        if added {
+            history.save(dir)?;
+        }
+        Ok(added)
+    }
+
+    /// Fetches and records full contents for listed notes that changed since their
+    /// last snapshot. At most `budget` notes are fetched per call so a first sync
+    /// over many notes is spread across several cycles.
+    pub async fn snapshot_changed(client: &Client, notes: &[Note], budget: usize) -> MiResult<usize> {
+        let dir = client.data_dir();
+        let stale: Vec<String> = {
+            let _guard = HISTORY_LOCK.lock().unwrap();
+            let history = Self::load(dir);
+            notes.iter()
+                .filter(|n| !n.id.starts_with("local-") && history.is_stale(n))
+                .take(budget)
+                .map(|n| n.id.clone())
+                .collect()
+        };
+
+        let mut recorded = 0;
+        for id in stale {
+            let note = client.get_note(&id).await?;
+            if Self::record(dir, &note)? {
+                recorded += 1;
+            }
+        }
+        Ok(recorded)
+    }
+
+    /// Fills in `content` for listed notes (which only carry snippets) from the
+    /// latest snapshot when it matches the server tag.
+    pub fn fill_content(dir: Option<&Path>, notes: &mut [Note]) {
+        let _guard = HISTORY_LOCK.lock().unwrap();
+        let history = Self::load(dir);
+        for note in notes.iter_mut().filter(|n| n.content.is_none()) {
+            if let Some(v) = history.latest(&note.id).filter(|v| v.tag == note.tag) {
+                note.content = Some(v.content.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Modifier keys held with a hotkey.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Self = Self { ctrl: false, alt: false, shift: false, win: false };
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::NONE
+    }
+
+    /// `fsModifiers` for `RegisterHotKey` (MOD_CONTROL, MOD_ALT, MOD_SHIFT, MOD_WIN).
+    pub fn win32_flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.alt { flags |= 0x0001; }
+        if self.ctrl { flags |= 0x0002; }
+        if self.shift { flags |= 0x0004; }
+        if self.win { flags |= 0x0008; }
+        flags
+    }
+}
+
+impl fmt::Display for Modifiers {
+    /// Canonical order, each followed by the separator: "Ctrl-Alt-".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [(self.ctrl, "Ctrl"), (self.alt, "Alt"), (self.shift, "Shift"), (self.win, "Win")] {
+            if held {
+                write!(f, "{}-", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys that have a name rather than a character.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NamedKey {
+    Space,
+    Tab,
+    Enter,
+    Escape,
+    Backspace,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    PrintScreen,
+    Pause,
+    PlayPause,
+    NextTrack,
+    PrevTrack,
+    Stop,
+    VolumeUp,
+    VolumeDown,
+    VolumeMute,
+}
+
+/// Virtual-key code, then the canonical name and accepted aliases (all compared case-insensitively).
+const NAMED_KEYS: &[(NamedKey, u32, &[&str])] = &[
+    (NamedKey::Space, 0x20, &["Space"]),
+    (NamedKey::Tab, 0x09, &["Tab"]),
+    (NamedKey::Enter, 0x0D, &["Enter", "Return"]),
+    (NamedKey::Escape, 0x1B, &["Esc", "Escape"]),
+    (NamedKey::Backspace, 0x08, &["Backspace"]),
+    (NamedKey::Insert, 0x2D, &["Insert", "Ins"]),
+    (NamedKey::Delete, 0x2E, &["Delete", "Del"]),
+    (NamedKey::Home, 0x24, &["Home"]),
+    (NamedKey::End, 0x23, &["End"]),
+    (NamedKey::PageUp, 0x21, &["PageUp", "PgUp"]),
+    (NamedKey::PageDown, 0x22, &["PageDown", "PgDn"]),
+    (NamedKey::Up, 0x26, &["Up"]),
+    (NamedKey::Down, 0x28, &["Down"]),
+    (NamedKey::Left, 0x25, &["Left"]),
+    (NamedKey::Right, 0x27, &["Right"]),
+    (NamedKey::PrintScreen, 0x2C, &["PrintScreen", "PrtSc"]),
+    (NamedKey::Pause, 0x13, &["Pause"]),
+    (NamedKey::PlayPause, 0xB3, &["MediaPlayPause", "PlayPause"]),
+    (NamedKey::NextTrack, 0xB0, &["MediaNext", "NextTrack"]),
+    (NamedKey::PrevTrack, 0xB1, &["MediaPrev", "PrevTrack"]),
+    (NamedKey::Stop, 0xB2, &["MediaStop"]),
+    (NamedKey::VolumeUp, 0xAF, &["VolumeUp"]),
+    (NamedKey::VolumeDown, 0xAE, &["VolumeDown"]),
+    (NamedKey::VolumeMute, 0xAD, &["VolumeMute", "Mute"]),
+];
+
+impl NamedKey {
+    fn entry(&self) -> Option<&'static (NamedKey, u32, &'static [&'static str])> {
+        NAMED_KEYS.iter().find(|(key, _, _)| key == self)
+    }
+
+    fn names(&self) -> &'static [&'static str] {
+        self.entry().map(|(_, _, names)| *names).unwrap_or(&[])
+    }
+
+    /// Media keys do nothing when typing, so they may be used without modifiers.
+    pub fn is_media(&self) -> bool {
+        matches!(self, NamedKey::PlayPause | NamedKey::NextTrack | NamedKey::PrevTrack | NamedKey::Stop
+            | NamedKey::VolumeUp | NamedKey::VolumeDown | NamedKey::VolumeMute)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    /// A letter (stored uppercase) or digit.
+    Char(char),
+    /// F1 to F24.
+    Function(u8),
+    Named(NamedKey),
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Function(n) => write!(f, "F{}", n),
+            Key::Named(key) => f.write_str(key.names()[0]),
+        }
+    }
+}
+
+impl Key {
+    /// Windows virtual-key code.
+    pub fn vk(&self) -> u32 {
+        match self {
+            // VK_0..VK_9 and VK_A..VK_Z are the ASCII codes
+            Key::Char(c) => *c as u32,
+            Key::Function(n) => 0x70 + (*n as u32 - 1),
+            Key::Named(key) => key.entry().map(|(_, vk, _)| *vk).unwrap_or(0),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(c) = s.chars().next().filter(|c| s.len() == 1 && c.is_ascii_alphanumeric()) {
+            return Ok(Key::Char(c.to_ascii_uppercase()));
+        }
+        if let Some(n) = s.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u8>().ok()) {
+            return if (1..=24).contains(&n) { Ok(Key::Function(n)) } else { Err(format!("F{} is not a function key (F1 to F24)", n)) };
+        }
+        NAMED_KEYS.iter()
+            .find(|(_, _, names)| names.iter().any(|name| name.eq_ignore_ascii_case(s)))
+            .map(|(key, _, _)| Key::Named(*key))
+            .ok_or_else(|| format!("Unknown key '{}'", s))
+    }
+}
+
+/// A global shortcut such as "Alt-L", "Ctrl-Shift-F9" or "MediaPlayPause".
+/// Parsing accepts `-` or `+` between parts and any letter case; `Display`
+/// writes the canonical form, which is what `config.json` stores.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    pub key: Key,
+}
+
+impl Default for Hotkey {
+    fn default() -> Self {
+        Self { modifiers: Modifiers { alt: true, ..Modifiers::NONE }, key: Key::Char('L') }
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.modifiers, self.key)
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(['-', '+']).map(str::trim).collect();
+        let (key, modifier_parts) = parts.split_last().ok_or("Empty hotkey")?;
+        if key.is_empty() {
+            return Err(format!("'{}' has no key after the modifiers", s));
+        }
+
+        let mut modifiers = Modifiers::NONE;
+        for part in modifier_parts {
+            let held = match part.to_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "alt" => &mut modifiers.alt,
+                "shift" => &mut modifiers.shift,
+                "win" | "super" | "meta" => &mut modifiers.win,
+                _ => return Err(format!("Unknown modifier '{}' in '{}' (use Ctrl, Alt, Shift or Win)", part, s)),
+            };
+            if *held {
+                return Err(format!("'{}' repeats the modifier {}", s, part));
+            }
+            *held = true;
+        }
+
+        let key: Key = key.parse()?;
+        // Without modifiers, ordinary keys would be swallowed while typing
+        let standalone = matches!(key, Key::Function(_)) || matches!(key, Key::Named(k) if k.is_media());
+        if modifiers.is_empty() && !standalone {
+            return Err(format!("'{}' needs a modifier (Ctrl, Alt, Shift or Win); only function and media keys work alone", s));
+        }
+        Ok(Self { modifiers, key })
+    }
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Hotkey> for String {
+    fn from(hotkey: Hotkey) -> Self {
+        hotkey.to_string()
+    }
+}
+
+/// Things a global hotkey can do.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HotkeyAction {
+    ToggleLaunchBar,
+    /// Opens the launch bar in quick-capture mode ("+").
+    QuickCapture,
+    OpenMainWindow,
+    /// Opens the launch bar searching for the clipboard text.
+    SearchClipboard,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 4] = [
+        HotkeyAction::ToggleLaunchBar,
+        HotkeyAction::QuickCapture,
+        HotkeyAction::OpenMainWindow,
+        HotkeyAction::SearchClipboard,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HotkeyAction::ToggleLaunchBar => "Launch bar",
+            HotkeyAction::QuickCapture => "Quick capture",
+            HotkeyAction::OpenMainWindow => "Open main window",
+            HotkeyAction::SearchClipboard => "Search clipboard text",
+        }
+    }
+
+    /// Stable id passed to `RegisterHotKey` and reported back in `WM_HOTKEY`.
+    pub fn id(&self) -> i32 {
+        100 + Self::ALL.iter().position(|a| a == self).unwrap_or(0) as i32
+    }
+
+    pub fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|a| a.id() == id)
+    }
+}
+
+/// Pairs of actions bound to the same key combination, which can't both be registered.
+pub fn conflicts(bindings: &[(HotkeyAction, Hotkey)]) -> Vec<(HotkeyAction, HotkeyAction, Hotkey)> {
+    let mut found = Vec::new();
+    for (i, (first, hotkey)) in bindings.iter().enumerate() {
+        for (second, other) in &bindings[i + 1..] {
+            if hotkey == other {
+                found.push((*first, *second, *hotkey));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_modifiers() -> impl Iterator<Item = Modifiers> {
+        (0..16u8).map(|bits| Modifiers { ctrl: bits & 1 != 0, alt: bits & 2 != 0, shift: bits & 4 != 0, win: bits & 8 != 0 })
+    }
+
+    fn all_keys() -> Vec<Key> {
+        let chars = ('A'..='Z').chain('0'..='9').map(Key::Char);
+        let functions = (1..=24).map(Key::Function);
+        let named = NAMED_KEYS.iter().map(|(key, _, _)| Key::Named(*key));
+        chars.chain(functions).chain(named).collect()
+    }
+
+    #[test]
+    fn display_round_trips_for_every_combination() {
+        let mut checked = 0;
+        for modifiers in all_modifiers() {
+            for key in all_keys() {
+                let hotkey = Hotkey { modifiers, key };
+                let standalone = matches!(key, Key::Function(_)) || matches!(key, Key::Named(k) if k.is_media());
+                if modifiers.is_empty() && !standalone {
+                    assert!(hotkey.to_string().parse::<Hotkey>().is_err(), "{} parsed without a modifier", hotkey);
+                    continue;
+                }
+                assert_eq!(hotkey.to_string().parse::<Hotkey>(), Ok(hotkey), "{}", hotkey);
+                checked += 1;
+            }
+        }
+        // 15 modifier sets for every key, plus F1-F24 and the seven media keys alone
+        assert_eq!(checked, 15 * all_keys().len() + 24 + 7);
+    }
+
+    #[test]
+    fn parsing_accepts_aliases_plus_signs_and_any_case() {
+        let hotkey: Hotkey = "shift + control + del".parse().unwrap();
+        assert_eq!(hotkey, Hotkey { modifiers: Modifiers { ctrl: true, shift: true, ..Modifiers::NONE }, key: Key::Named(NamedKey::Delete) });
+        assert_eq!(hotkey.to_string(), "Ctrl-Shift-Delete");
+        assert_eq!("f9".parse::<Hotkey>().unwrap().to_string(), "F9");
+        assert_eq!("Mute".parse::<Hotkey>().unwrap().to_string(), "VolumeMute");
+    }
+
+    #[test]
+    fn malformed_hotkeys_are_rejected() {
+        for bad in ["", "Alt-", "Ctrl-Ctrl-A", "Ctrl-Control-A", "A", "Space", "F25", "F0", "Ctrl-F25", "Hyper-A", "Ctrl-AB"] {
+            assert!(bad.parse::<Hotkey>().is_err(), "'{}' was accepted", bad);
+        }
+    }
+
+    #[test]
+    fn virtual_key_codes() {
+        // Letters and digits are their ASCII codes
+        assert_eq!(Key::Char('A').vk(), 0x41);
+        assert_eq!(Key::Char('Z').vk(), 0x5A);
+        assert_eq!(Key::Char('0').vk(), 0x30);
+        assert_eq!(Key::Char('9').vk(), 0x39);
+        assert_eq!("Alt-l".parse::<Hotkey>().unwrap().key.vk(), 0x4C);
+        assert_eq!(Key::Function(1).vk(), 0x70);
+        assert_eq!(Key::Function(12).vk(), 0x7B);
+        assert_eq!(Key::Function(24).vk(), 0x87);
+        assert_eq!(Key::Named(NamedKey::PlayPause).vk(), 0xB3);
+        assert_eq!(Key::Named(NamedKey::NextTrack).vk(), 0xB0);
+        assert_eq!(Key::Named(NamedKey::PrevTrack).vk(), 0xB1);
+        assert_eq!(Key::Named(NamedKey::Stop).vk(), 0xB2);
+        assert_eq!(Key::Named(NamedKey::VolumeMute).vk(), 0xAD);
+        assert_eq!(Key::Named(NamedKey::VolumeDown).vk(), 0xAE);
+        assert_eq!(Key::Named(NamedKey::VolumeUp).vk(), 0xAF);
+        // Every named key has a code
+        assert!(NAMED_KEYS.iter().all(|(key, _, _)| Key::Named(*key).vk() != 0));
+    }
+
+    #[test]
+    fn modifier_flags() {
+        assert_eq!(Modifiers::NONE.win32_flags(), 0);
+        assert_eq!(Modifiers { alt: true, ..Modifiers::NONE }.win32_flags(), 0x0001);
+        assert_eq!(Modifiers { ctrl: true, ..Modifiers::NONE }.win32_flags(), 0x0002);
+        assert_eq!(Modifiers { shift: true, ..Modifiers::NONE }.win32_flags(), 0x0004);
+        assert_eq!(Modifiers { win: true, ..Modifiers::NONE }.win32_flags(), 0x0008);
+        assert_eq!("Ctrl-Alt-Shift-Win-A".parse::<Hotkey>().unwrap().modifiers.win32_flags(), 0x000F);
+    }
+
+    #[test]
+    fn conflicts_name_the_earlier_binding_first() {
+        let launch_bar = Hotkey::default();
+        let other: Hotkey = "Ctrl-Shift-N".parse().unwrap();
+        let bindings = [
+            (HotkeyAction::ToggleLaunchBar, launch_bar),
+            (HotkeyAction::QuickCapture, other),
+            (HotkeyAction::OpenMainWindow, launch_bar),
+            (HotkeyAction::SearchClipboard, launch_bar),
+        ];
+        // The launch bar is always first, so it is never the one that has to give way
+        assert_eq!(conflicts(&bindings), vec![
+            (HotkeyAction::ToggleLaunchBar, HotkeyAction::OpenMainWindow, launch_bar),
+            (HotkeyAction::ToggleLaunchBar, HotkeyAction::SearchClipboard, launch_bar),
+            (HotkeyAction::OpenMainWindow, HotkeyAction::SearchClipboard, launch_bar),
+        ]);
+        assert!(conflicts(&bindings[..2]).is_empty());
+    }
+
+    #[test]
+    fn action_ids_round_trip() {
+        for action in HotkeyAction::ALL {
+            assert_eq!(HotkeyAction::from_id(action.id()), Some(action));
+        }
+        assert_eq!(HotkeyAction::from_id(0), None);
+    }
+
+    #[test]
+    fn serde_uses_the_canonical_string() {
+        let hotkey: Hotkey = serde_json::from_str("\"alt+l\"").unwrap();
+        assert_eq!(hotkey, Hotkey::default());
+        assert_eq!(serde_json::to_string(&hotkey).unwrap(), "\"Alt-L\"");
+        assert!(serde_json::from_str::<Hotkey>("\"F25\"").is_err());
+    }
+}
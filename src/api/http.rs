@@ -0,0 +1,149 @@
+use reqwest::cookie::CookieStore;
+use reqwest::header::HeaderValue;
+use reqwest::{Certificate, ClientBuilder, Proxy, Url};
+use std::sync::RwLock;
+use std::time::Duration;
+use crate::api::{AppConfig, MiResult};
+use crate::api::cookie::CookieJar;
+
+/// `proxy` value that connects directly, ignoring `HTTPS_PROXY` and the system settings.
+pub const NO_PROXY: &str = "none";
+
+/// Connection settings every HTTP client of the app is built from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct HttpSettings {
+    /// `None` leaves it to reqwest: `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` (with
+    /// `NO_PROXY`), then the system proxy settings.
+    pub proxy: Option<String>,
+    /// PEM bundle or DER certificate trusted in addition to the built-in roots.
+    pub ca_bundle: Option<String>,
+    pub connect_timeout: Duration,
+    /// Bounds the whole response; reqwest 0.11 has no separate per-read timeout.
+    pub read_timeout: Duration,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self::from_config(&AppConfig::default())
+    }
+}
+
+impl HttpSettings {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            proxy: config.proxy.clone(),
+            ca_bundle: config.ca_bundle.clone(),
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+            read_timeout: Duration::from_secs(config.read_timeout_secs),
+        }
+    }
+
+    /// A client builder with the proxy, certificates and timeouts applied; callers
+    /// add what is specific to them, such as a redirect policy or cookie jar.
+    pub fn builder(&self) -> MiResult<ClientBuilder> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.read_timeout);
+        match self.proxy.as_deref() {
+            None => {}
+            Some(NO_PROXY) => builder = builder.no_proxy(),
+            Some(url) => builder = builder.proxy(parse_proxy(url)?),
+        }
+        if let Some(ref path) = self.ca_bundle {
+            for cert in load_certificates(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Where traffic to `base_url` first goes: the proxy if one is set, else the server itself.
+    pub fn first_hop(&self, base_url: &str) -> String {
+        match self.proxy.as_deref() {
+            Some(NO_PROXY) => base_url.to_string(),
+            Some(url) => url.to_string(),
+            None => ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"].iter()
+                .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+                .unwrap_or_else(|| base_url.to_string()),
+        }
+    }
+}
+
+/// HTTP(S) and SOCKS5 proxies, with optional `user:password@` credentials.
+pub fn parse_proxy(url: &str) -> MiResult<Proxy> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme.to_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https" | "socks5" | "socks5h") => Ok(Proxy::all(url)?),
+        _ => Err(format!("'{}' is not a proxy URL; use http://, https://, socks5:// or socks5h://, or '{}'", url, NO_PROXY).into()),
+    }
+}
+
+/// Every certificate in a PEM bundle, or the one in a DER file.
+pub fn load_certificates(path: &str) -> MiResult<Vec<Certificate>> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Can't read the CA bundle {}: {}", path, e))?;
+    let certs = if bytes.windows(11).any(|w| w == b"-----BEGIN ") {
+        Certificate::from_pem_bundle(&bytes)
+    } else {
+        Certificate::from_der(&bytes).map(|cert| vec![cert])
+    };
+    match certs {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        Ok(_) => Err(format!("{} contains no certificates", path).into()),
+        Err(e) => Err(format!("{} is not a certificate file: {}", path, e).into()),
+    }
+}
+
+/// The cookies an HTTP client sends, as its reqwest cookie provider. Responses
+/// (including each step of a redirect) update them; switching accounts replaces
+/// them wholesale, so the client and its pooled connections can stay.
+#[derive(Default)]
+pub struct SessionJar {
+    jar: RwLock<CookieJar>,
+}
+
+impl SessionJar {
+    /// Replaces every cookie with those in `cookie` ("a=1; b=2"), sent to `url`'s site.
+    pub fn reset(&self, cookie: &str, url: &Url) {
+        *self.jar.write().unwrap() = CookieJar::from_header(cookie, url);
+    }
+
+    /// Adds the pairs of `cookie` ("a=1; b=2") for `url`'s site.
+    pub fn add(&self, cookie: &str, url: &Url) {
+        self.jar.write().unwrap().add_header(cookie, url);
+    }
+
+    /// The `Cookie` header sent to `url`, empty if there is none.
+    pub fn header(&self, url: &Url) -> String {
+        self.jar.read().unwrap().header(url)
+    }
+
+    /// Value of the cookie called `name` sent to `url`.
+    pub fn value(&self, url: &Url, name: &str) -> Option<String> {
+        self.jar.read().unwrap().value(url, name).map(str::to_string)
+    }
+}
+
+impl CookieStore for SessionJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let mut jar = self.jar.write().unwrap();
+        for header in cookie_headers.filter_map(|h| h.to_str().ok()) {
+            if !jar.set_cookie(header, url) {
+                // Never log the header itself; its value is a credential
+                let host = url.host_str().unwrap_or_default();
+                let name = header.split(';').next().and_then(|pair| pair.split_once('=')).map(|(name, _)| name.trim());
+                match name.filter(|name| !name.is_empty()) {
+                    Some(name) => { crate::dprintln!("Ignored Set-Cookie '{}' from {}: its Domain doesn't cover that host", name, host); }
+                    None => { crate::dprintln!("Ignored malformed Set-Cookie from {}", host); }
+                }
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let header = self.header(url);
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
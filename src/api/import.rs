@@ -0,0 +1,216 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use crate::api::MiResult;
+use crate::api::cookie;
+use crate::api::secrets::redact;
+
+/// Cookies and browser identity lifted from an existing browser session.
+#[derive(Default)]
+pub struct ImportedSession {
+    pub cookie: String,
+    pub user_agent: Option<String>,
+}
+
+impl std::fmt::Debug for ImportedSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImportedSession")
+            .field("cookie", &redact(&self.cookie))
+            .field("user_agent", &self.user_agent)
+            .finish()
+    }
+}
+
+/// Domains whose cookies make up a notes session (i.mi.com plus the account server).
+fn is_xiaomi_domain(domain: &str) -> bool {
+    let d = domain.trim_start_matches('.').to_lowercase();
+    d == "mi.com" || d.ends_with(".mi.com") || d == "xiaomi.com" || d.ends_with(".xiaomi.com")
+}
+
+fn add_cookie_header(map: &mut BTreeMap<String, String>, header: &str) {
+    for (k, v) in cookie::pairs(header).filter(|(_, v)| !v.is_empty()) {
+        map.insert(k.to_string(), v.to_string());
+    }
+}
+
+fn finish(map: BTreeMap<String, String>, user_agent: Option<String>) -> MiResult<ImportedSession> {
+    if !map.contains_key("serviceToken") && !map.contains_key("passToken") {
+        return Err("No Xiaomi session cookies (serviceToken or passToken) found".into());
+    }
+    let cookie = map.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("; ");
+    Ok(ImportedSession { cookie, user_agent })
+}
+
+/// Splits a shell command line into words, honouring quotes, backslash escapes and
+/// line continuations. Commands copied "as cURL (cmd)" use `^` escapes; those are
+/// turned into their bash equivalent first.
+fn shell_words(cmd: &str) -> Vec<String> {
+    let cmd = if cmd.contains("^\"") {
+        let mut unescaped = String::with_capacity(cmd.len());
+        let mut chars = cmd.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '^' => match chars.next() {
+                    Some('\r') | Some('\n') | None => {}
+                    Some(n) => unescaped.push(n),
+                },
+                _ => unescaped.push(c),
+            }
+        }
+        unescaped
+    } else {
+        cmd.to_string()
+    };
+
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = cmd.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => word.push(c),
+            (Some('"'), '"') => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some(n @ ('"' | '\\' | '$' | '`')) => word.push(n),
+                Some('\n') => {}
+                Some(n) => {
+                    word.push('\\');
+                    word.push(n);
+                }
+                None => word.push('\\'),
+            },
+            (Some(_), _) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '\\') => match chars.next() {
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(n) => {
+                    word.push(n);
+                    in_word = true;
+                }
+                None => {}
+            },
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, '$') if chars.peek() == Some(&'\'') => {
+                // ANSI-C quoting ($'...') as produced by some browsers; treat like '...'
+                chars.next();
+                quote = Some('\'');
+                in_word = true;
+            }
+            (None, _) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Parses a "Copy as cURL" command for its Cookie and User-Agent.
+pub fn from_curl(cmd: &str) -> MiResult<ImportedSession> {
+    let words = shell_words(cmd);
+    if words.first().is_none_or(|w| !w.ends_with("curl") && !w.ends_with("curl.exe")) {
+        return Err("Not a curl command".into());
+    }
+
+    let mut cookies = BTreeMap::new();
+    let mut user_agent = None;
+    let mut args = words.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-H" | "--header" => {
+                let Some(header) = args.next() else { break };
+                let Some((name, value)) = header.split_once(':') else { continue };
+                match name.trim().to_lowercase().as_str() {
+                    "cookie" => add_cookie_header(&mut cookies, value),
+                    "user-agent" => user_agent = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+            "-b" | "--cookie" => {
+                if let Some(value) = args.next() {
+                    add_cookie_header(&mut cookies, value);
+                }
+            }
+            "-A" | "--user-agent" => user_agent = args.next().cloned(),
+            _ => {}
+        }
+    }
+    finish(cookies, user_agent)
+}
+
+/// Parses a Netscape `cookies.txt` export, keeping unexpired Xiaomi cookies.
+pub fn from_netscape(text: &str) -> MiResult<ImportedSession> {
+    let now = chrono::Utc::now().timestamp();
+    let mut cookies = BTreeMap::new();
+
+    for line in text.lines() {
+        // HttpOnly cookies are written as comments with this prefix
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 7 {
+            continue;
+        }
+        let (domain, expires, name, value) = (fields[0], fields[4], fields[5], fields[6].trim_end());
+        let expired = expires.parse::<i64>().is_ok_and(|e| e != 0 && e < now);
+        if is_xiaomi_domain(domain) && !expired && !value.is_empty() {
+            cookies.insert(name.to_string(), value.to_string());
+        }
+    }
+    finish(cookies, None)
+}
+
+/// Parses a HAR capture: cookies sent to or set by Xiaomi hosts, plus the browser's
+/// User-Agent. Later requests win, so the freshest tokens are kept.
+pub fn from_har(json: &str) -> MiResult<ImportedSession> {
+    let har: Value = serde_json::from_str(json)?;
+    let entries = har.pointer("/log/entries").and_then(|e| e.as_array()).ok_or("Not a HAR file (no log.entries)")?;
+
+    let mut cookies = BTreeMap::new();
+    let mut user_agent = None;
+    for entry in entries {
+        let url = entry.pointer("/request/url").and_then(|u| u.as_str()).unwrap_or("");
+        let host = url.split("://").nth(1).and_then(|r| r.split(['/', ':', '?']).next()).unwrap_or("");
+        if !is_xiaomi_domain(host) {
+            continue;
+        }
+
+        for header in entry.pointer("/request/headers").and_then(|h| h.as_array()).into_iter().flatten() {
+            let name = header.get("name").and_then(|n| n.as_str()).unwrap_or("").to_lowercase();
+            let value = header.get("value").and_then(|v| v.as_str()).unwrap_or("");
+            match name.as_str() {
+                "cookie" => add_cookie_header(&mut cookies, value),
+                "user-agent" => user_agent = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        for part in ["/request/cookies", "/response/cookies"] {
+            for cookie in entry.pointer(part).and_then(|c| c.as_array()).into_iter().flatten() {
+                let name = cookie.get("name").and_then(|n| n.as_str()).unwrap_or("");
+                let value = cookie.get("value").and_then(|v| v.as_str()).unwrap_or("");
+                if !name.is_empty() && !value.is_empty() {
+                    cookies.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+    }
+    finish(cookies, user_agent)
+}
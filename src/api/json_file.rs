@@ -0,0 +1,99 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::api::MiResult;
+
+/// Reads a JSON state file (outbox, history). `None` if it doesn't exist yet. A file
+/// that doesn't parse is moved to a timestamped `.bak` and reported, so the next save
+/// starts afresh without destroying what was in it.
+pub fn load<T: DeserializeOwned>(path: &Path) -> MiResult<Option<T>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Can't read {}: {}", path.display(), e).into()),
+    };
+    match serde_json::from_str(&content) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            let backup = set_aside(path).map_err(|move_err| format!("{} is unreadable ({}) and could not be moved: {}", path.display(), e, move_err))?;
+            eprintln!("{} is unreadable ({}); moved it to {}", path.display(), e, backup.display());
+            Ok(None)
+        }
+    }
+}
+
+/// Replaces the file in one step so a crash never leaves it half-written.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> MiResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// An unused timestamped `.bak` path next to a JSON file.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let mut backup = path.with_extension(format!("json.bak-{}", stamp));
+    let mut n = 1;
+    while backup.exists() {
+        n += 1;
+        backup = path.with_extension(format!("json.bak-{}-{}", stamp, n));
+    }
+    backup
+}
+
+/// Moves a JSON file to a timestamped `.bak` next to it.
+pub fn set_aside(path: &Path) -> std::io::Result<PathBuf> {
+    let backup = backup_path(path);
+    fs::rename(path, &backup)?;
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minote-json-file-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn missing_file_is_none() {
+        let dir = scratch_dir("missing");
+        assert!(load::<HashMap<String, u32>>(&dir.join("state.json")).unwrap().is_none());
+    }
+
+    #[test]
+    fn save_then_load_round_trips_without_leaving_a_temp_file() {
+        let dir = scratch_dir("round-trip");
+        let path = dir.join("state.json");
+        let value = HashMap::from([("a".to_string(), 1u32)]);
+        save(&path, &value).unwrap();
+        assert_eq!(load::<HashMap<String, u32>>(&path).unwrap(), Some(value));
+        assert!(!path.with_extension("json.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_file_is_set_aside_with_its_content() {
+        let dir = scratch_dir("corrupt");
+        let path = dir.join("state.json");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+
+        assert!(load::<HashMap<String, u32>>(&path).unwrap().is_none());
+        assert!(!path.exists());
+        let backups: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].file_name().unwrap().to_str().unwrap().starts_with("state.json.bak-"));
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), "{ not json");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,425 @@
+use qrcode::QrCode;
+use qrcode::render::unicode;
+use reqwest::{header, redirect, Client as HttpClient, Url};
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::api::{AppConfig, MiResult};
+use crate::api::cookie::{self, CookieJar};
+use crate::api::http::{HttpSettings, SessionJar};
+use crate::api::models::deserialize_opt_id;
+
+/// Account server responses are JSON behind this anti-hijacking prefix.
+const JSON_PREFIX: &str = "&&&START&&&";
+const SID: &str = "i.mi.com";
+const QR_POLL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Where the login flow talks to; override both to run against a local mock.
+#[derive(Debug, Clone)]
+pub struct LoginEndpoints {
+    pub account_base: String,
+    /// STS callback on the notes service that turns a login into `serviceToken`.
+    pub callback: String,
+}
+
+impl Default for LoginEndpoints {
+    fn default() -> Self {
+        Self {
+            account_base: "https://account.xiaomi.com".to_string(),
+            callback: "https://i.mi.com/sts".to_string(),
+        }
+    }
+}
+
+/// Something the account server wants done before it will issue tokens.
+/// Returned as the error of a login attempt so callers can downcast and react.
+#[derive(Debug)]
+pub enum Challenge {
+    /// Two-factor verification (SMS/e-mail) to complete at `url` before retrying.
+    Verification { url: String },
+    /// A captcha is required; solve it in a browser or use QR login instead.
+    Captcha { url: String },
+}
+
+impl std::fmt::Display for Challenge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Challenge::Verification { url } => write!(f, "Verification required: {}", url),
+            Challenge::Captcha { url } => write!(f, "Captcha required: {}", url),
+        }
+    }
+}
+
+impl std::error::Error for Challenge {}
+
+#[derive(Deserialize)]
+struct ServiceLogin {
+    #[serde(rename = "_sign", default)]
+    sign: String,
+    #[serde(default)]
+    qs: String,
+    #[serde(default)]
+    callback: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthResult {
+    #[serde(default)]
+    code: i64,
+    desc: Option<String>,
+    pass_token: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_opt_id")]
+    user_id: Option<String>,
+    location: Option<String>,
+    notification_url: Option<String>,
+    captcha_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QrStart {
+    login_url: String,
+    qr: Option<String>,
+    lp: String,
+    timeout: Option<u64>,
+}
+
+/// A pending QR login: show `login_url` as a QR code, then `Login::wait_qr`.
+pub struct QrLogin {
+    pub login_url: String,
+    /// Hosted PNG of the same code, for terminals that can't render it.
+    pub image_url: Option<String>,
+    lp: String,
+    timeout: Duration,
+}
+
+/// Headless Xiaomi account login producing the cookie string `Client::new` takes.
+pub struct Login {
+    http: HttpClient,
+    jar: Arc<SessionJar>,
+    endpoints: LoginEndpoints,
+    device_id: String,
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+fn parse_prefixed<T: serde::de::DeserializeOwned>(body: &str) -> MiResult<T> {
+    let json = body.trim_start().strip_prefix(JSON_PREFIX).unwrap_or(body);
+    serde_json::from_str(json).map_err(|e| format!("Unexpected account server response: {}", e).into())
+}
+
+impl Login {
+    /// Signs in with the user agent and connection settings from `config`.
+    pub fn new(endpoints: LoginEndpoints, config: &AppConfig) -> MiResult<Self> {
+        let jar = Arc::new(SessionJar::default());
+        let ua = config.user_agent.clone().unwrap_or_else(|| "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/128.0.0.0 Safari/537.36".to_string());
+        // Redirects are followed by hand so the cookies set along the way land in the jar
+        let http = HttpSettings::from_config(config).builder()?
+            .cookie_provider(jar.clone())
+            .redirect(redirect::Policy::none())
+            .user_agent(ua)
+            .build()?;
+
+        let mut hasher = DefaultHasher::new();
+        now_ms().hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+
+        let mut login = Self { http, jar, endpoints, device_id: String::new() };
+        login.set_device_id(format!("wb_{:016x}", hasher.finish()))?;
+        Ok(login)
+    }
+
+    /// Reuse the device id of an earlier login so the account doesn't see a new device
+    /// (and ask for verification) every time.
+    pub fn with_device_id(mut self, device_id: &str) -> MiResult<Self> {
+        self.set_device_id(device_id.to_string())?;
+        Ok(self)
+    }
+
+    fn set_device_id(&mut self, device_id: String) -> MiResult<()> {
+        let base = Url::parse(&self.endpoints.account_base)?;
+        self.jar.add(&format!("deviceId={}; sdkVersion=accountsdk-18.8.15", device_id), &base);
+        self.device_id = device_id;
+        Ok(())
+    }
+
+    fn account_url(&self, path: &str) -> String {
+        format!("{}{}", self.endpoints.account_base.trim_end_matches('/'), path)
+    }
+
+    async fn service_login(&self) -> MiResult<ServiceLogin> {
+        let resp = self.http.get(self.account_url("/pass/serviceLogin"))
+            .query(&[("sid", SID), ("_json", "true")])
+            .send().await?;
+        parse_prefixed(&resp.text().await?)
+    }
+
+    /// Password login. The password is sent as its uppercase MD5, as the web login does.
+    pub async fn password(&self, user: &str, password: &str) -> MiResult<String> {
+        let start = self.service_login().await?;
+        let callback = if start.callback.is_empty() { self.endpoints.callback.clone() } else { start.callback };
+        let hash = format!("{:X}", md5::compute(password.as_bytes()));
+
+        let form = [
+            ("_json", "true"),
+            ("sid", SID),
+            ("user", user),
+            ("hash", hash.as_str()),
+            ("_sign", start.sign.as_str()),
+            ("qs", start.qs.as_str()),
+            ("callback", callback.as_str()),
+        ];
+        let resp = self.http.post(self.account_url("/pass/serviceLoginAuth2"))
+            .form(&form)
+            .send().await?;
+        let auth: AuthResult = parse_prefixed(&resp.text().await?)?;
+
+        if let Some(url) = auth.notification_url.as_deref().filter(|u| !u.is_empty()) {
+            return Err(Challenge::Verification { url: url.to_string() }.into());
+        }
+        if let Some(url) = auth.captcha_url.as_deref().filter(|u| !u.is_empty()) {
+            let url = if url.starts_with("http") { url.to_string() } else { self.account_url(url) };
+            return Err(Challenge::Captcha { url }.into());
+        }
+        if auth.code != 0 {
+            return Err(match auth.code {
+                70016 => "Wrong account or password".to_string(),
+                code => format!("Login failed ({}): {}", code, auth.desc.unwrap_or_default()),
+            }.into());
+        }
+        self.finish(auth).await
+    }
+
+    /// Starts a QR login; the code encodes `login_url` for the Xiaomi app to scan.
+    pub async fn start_qr(&self) -> MiResult<QrLogin> {
+        let start = self.service_login().await?;
+        let callback = if start.callback.is_empty() { self.endpoints.callback.clone() } else { start.callback };
+        let dc = now_ms().to_string();
+
+        let resp = self.http.get(self.account_url("/longPolling/loginUrl"))
+            .query(&[
+                ("sid", SID),
+                ("callback", callback.as_str()),
+                ("qs", start.qs.as_str()),
+                ("_sign", start.sign.as_str()),
+                ("_qrsize", "240"),
+                ("_hasLogo", "false"),
+                ("_dc", dc.as_str()),
+            ])
+            .send().await?;
+        let qr: QrStart = parse_prefixed(&resp.text().await?)?;
+
+        Ok(QrLogin {
+            login_url: qr.login_url,
+            image_url: qr.qr,
+            lp: qr.lp,
+            timeout: qr.timeout.map(Duration::from_secs).unwrap_or(QR_POLL_TIMEOUT),
+        })
+    }
+
+    /// Long-polls until the QR code is scanned and confirmed, or it expires.
+    pub async fn wait_qr(&self, qr: &QrLogin) -> MiResult<String> {
+        let deadline = Instant::now() + qr.timeout;
+        while Instant::now() < deadline {
+            let resp = match self.http.get(&qr.lp).timeout(Duration::from_secs(60)).send().await {
+                Ok(resp) => resp,
+                // The poll is held open until something happens; just ask again
+                Err(e) if e.is_timeout() => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let auth: AuthResult = match parse_prefixed(&resp.text().await?) {
+                Ok(auth) => auth,
+                Err(_) => continue,
+            };
+            if auth.code == 0 && auth.pass_token.is_some() {
+                return self.finish(auth).await;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Err("QR code expired before it was confirmed".into())
+    }
+
+    /// Follows the STS location to obtain `serviceToken` and assembles the cookie string.
+    async fn finish(&self, auth: AuthResult) -> MiResult<String> {
+        let pass_token = auth.pass_token.ok_or("Login succeeded but no passToken was issued")?;
+        let user_id = auth.user_id.ok_or("Login succeeded but no userId was issued")?;
+        let base = Url::parse(&self.endpoints.account_base)?;
+        let mut cookies = CookieJar::from_header(&self.jar.header(&base), &base);
+        cookies.add_header(&format!("userId={}; passToken={}; deviceId={}", user_id, pass_token, self.device_id), &base);
+
+        if let Some(location) = auth.location.filter(|l| !l.is_empty()) {
+            let url = Url::parse(&location)?;
+            let resp = self.http.get(url.clone()).header(header::COOKIE, cookies.to_cookie_string()).send().await?;
+            crate::dprintln!("STS Resp Status: {}", resp.status());
+            let service = self.jar.header(&url);
+            if cookie::value(&service, "serviceToken").is_some() {
+                cookies.add_header(&service, &url);
+            } else {
+                // The client can still exchange the passToken itself on first use
+                crate::dprintln!("STS did not issue a serviceToken; storing the account cookie only");
+            }
+        }
+        Ok(cookies.to_cookie_string())
+    }
+}
+
+/// Renders a QR code with half-block characters, light on dark for terminals.
+pub fn render_qr(data: &str) -> MiResult<String> {
+    let code = QrCode::new(data.as_bytes())?;
+    Ok(code.render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock_server::{MockServer, Response};
+
+    fn prefixed(json: &str) -> String {
+        format!("{}{}", JSON_PREFIX, json)
+    }
+
+    /// An account server whose `serviceLoginAuth2` answers with `auth`. Successful
+    /// logins are sent on to `/sts`, which issues the `serviceToken`.
+    fn account_server(auth: &'static str) -> MockServer {
+        MockServer::start(move |req| match req.path() {
+            "/pass/serviceLogin" => Response::ok(prefixed(r#"{"_sign":"SIGN","qs":"%3Fsid%3Di.mi.com","callback":""}"#)),
+            "/pass/serviceLoginAuth2" => Response::ok(prefixed(&auth.replace("{host}", req.header("host").unwrap()))),
+            "/sts" => Response::ok("ok").header("Set-Cookie", "serviceToken=ST; Path=/"),
+            _ => Response::status(404),
+        })
+    }
+
+    fn login(server: &MockServer) -> Login {
+        let endpoints = LoginEndpoints { account_base: server.url.clone(), callback: format!("{}/sts", server.url) };
+        Login::new(endpoints, &AppConfig::default()).unwrap().with_device_id("wb_test").unwrap()
+    }
+
+    #[tokio::test]
+    async fn password_login_sends_the_md5_and_sign_and_collects_the_cookies() {
+        let server = account_server(r#"{"code":0,"passToken":"PT","userId":123,"location":"http://{host}/sts?auth=1"}"#);
+        let cookie = login(&server).password("user@example.com", "secret").await.unwrap();
+
+        assert_eq!(cookie::value(&cookie, "userId"), Some("123"));
+        assert_eq!(cookie::value(&cookie, "passToken"), Some("PT"));
+        assert_eq!(cookie::value(&cookie, "serviceToken"), Some("ST"));
+        assert_eq!(cookie::value(&cookie, "deviceId"), Some("wb_test"));
+
+        let start = &server.hits("/pass/serviceLogin")[0];
+        assert_eq!(start.query()["sid"], SID);
+        let auth = &server.hits("/pass/serviceLoginAuth2")[0];
+        assert_eq!(auth.method, "POST");
+        let form = auth.form();
+        assert_eq!(form["user"], "user@example.com");
+        assert_eq!(form["hash"], "5EBE2294ECD0E0F08EAB7690D2A6EE69");
+        assert_eq!(form["_sign"], "SIGN");
+        // Passed on exactly as the server sent it
+        assert_eq!(form["qs"], "%3Fsid%3Di.mi.com");
+        assert_eq!(form["callback"], format!("{}/sts", server.url));
+        assert!(auth.header("cookie").unwrap().contains("deviceId=wb_test"));
+        // The STS step carries the new passToken
+        assert!(server.hits("/sts")[0].header("cookie").unwrap().contains("passToken=PT"));
+    }
+
+    #[tokio::test]
+    async fn password_login_surfaces_the_captcha() {
+        let server = account_server(r#"{"code":87001,"captchaUrl":"/pass/getCode?icodeType=login"}"#);
+        let err = login(&server).password("user", "secret").await.unwrap_err();
+        match err.downcast_ref::<Challenge>() {
+            Some(Challenge::Captcha { url }) => assert_eq!(*url, format!("{}/pass/getCode?icodeType=login", server.url)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn password_login_surfaces_two_factor_verification() {
+        let server = account_server(r#"{"code":0,"notificationUrl":"https://account.xiaomi.com/identity/authStart?sid=i.mi.com"}"#);
+        let err = login(&server).password("user", "secret").await.unwrap_err();
+        match err.downcast_ref::<Challenge>() {
+            Some(Challenge::Verification { url }) => assert!(url.ends_with("/identity/authStart?sid=i.mi.com")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(server.hits("/sts").is_empty());
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_reported() {
+        let server = account_server(r#"{"code":70016,"desc":"登录验证失败"}"#);
+        let err = login(&server).password("user", "wrong").await.unwrap_err();
+        assert_eq!(err.to_string(), "Wrong account or password");
+    }
+
+    #[test]
+    fn responses_may_come_with_or_without_the_prefix() {
+        let with: ServiceLogin = parse_prefixed(&format!("  {}{{\"_sign\":\"a\"}}", JSON_PREFIX)).unwrap();
+        let without: ServiceLogin = parse_prefixed("{\"_sign\":\"b\"}").unwrap();
+        assert_eq!((with.sign, without.sign), ("a".to_string(), "b".to_string()));
+        assert!(parse_prefixed::<ServiceLogin>("<html>").is_err());
+    }
+
+    /// A QR login whose long poll reports "not scanned yet" `pending` times, then success.
+    fn qr_server(pending: usize, timeout: u64) -> MockServer {
+        let polls = std::sync::atomic::AtomicUsize::new(0);
+        MockServer::start(move |req| {
+            let host = req.header("host").unwrap();
+            match req.path() {
+                "/pass/serviceLogin" => Response::ok(prefixed(r#"{"_sign":"SIGN","qs":"%3Fsid","callback":""}"#)),
+                "/longPolling/loginUrl" => Response::ok(prefixed(&format!(
+                    r#"{{"loginUrl":"https://account.xiaomi.com/longPolling/login?ticket=T","qr":"https://account.xiaomi.com/qr.png","lp":"http://{}/lp","timeout":{}}}"#,
+                    host, timeout,
+                ))),
+                "/lp" if polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < pending => Response::ok(prefixed(r#"{"code":700,"desc":"waiting"}"#)),
+                "/lp" => Response::ok(prefixed(&format!(r#"{{"code":0,"passToken":"QPT","userId":"456","location":"http://{}/sts"}}"#, host))),
+                "/sts" => Response::ok("ok").header("Set-Cookie", "serviceToken=QST; Path=/"),
+                _ => Response::status(404),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn qr_login_polls_until_confirmed() {
+        let server = qr_server(1, 30);
+        let login = login(&server);
+        let qr = login.start_qr().await.unwrap();
+        assert_eq!(qr.login_url, "https://account.xiaomi.com/longPolling/login?ticket=T");
+        assert_eq!(qr.image_url.as_deref(), Some("https://account.xiaomi.com/qr.png"));
+        assert_eq!(qr.timeout, Duration::from_secs(30));
+        let start = &server.hits("/longPolling/loginUrl")[0].query();
+        assert_eq!((start["sid"].as_str(), start["_sign"].as_str()), (SID, "SIGN"));
+        assert_eq!(start["callback"], format!("{}/sts", server.url));
+
+        let cookie = login.wait_qr(&qr).await.unwrap();
+        assert_eq!(server.hits("/lp").len(), 2);
+        assert_eq!(cookie::value(&cookie, "userId"), Some("456"));
+        assert_eq!(cookie::value(&cookie, "passToken"), Some("QPT"));
+        assert_eq!(cookie::value(&cookie, "serviceToken"), Some("QST"));
+    }
+
+    #[tokio::test]
+    async fn qr_login_gives_up_when_the_code_expires() {
+        let server = qr_server(usize::MAX, 0);
+        let login = login(&server);
+        let qr = login.start_qr().await.unwrap();
+        let err = login.wait_qr(&qr).await.unwrap_err();
+        assert_eq!(err.to_string(), "QR code expired before it was confirmed");
+        assert!(server.hits("/sts").is_empty());
+    }
+
+    #[test]
+    fn qr_codes_render_as_half_blocks() {
+        let rendered = render_qr("https://account.xiaomi.com/longPolling/login?ticket=T").unwrap();
+        assert!(rendered.lines().count() > 10);
+        assert!(rendered.chars().all(|c| matches!(c, ' ' | '█' | '▀' | '▄' | '\n')));
+    }
+}
@@ -0,0 +1,669 @@
+use serde::{Deserialize, Serialize};
+
+/// Output format used when a note is pasted or copied from the launch bar.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasteFormat {
+    #[default]
+    Plain,
+    Markdown,
+    Html,
+    /// CF_HTML for rich editors plus a plain-text fallback.
+    Rich,
+}
+
+impl PasteFormat {
+    pub const ALL: [PasteFormat; 4] = [PasteFormat::Plain, PasteFormat::Markdown, PasteFormat::Html, PasteFormat::Rich];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PasteFormat::Plain => "Plain",
+            PasteFormat::Markdown => "Markdown",
+            PasteFormat::Html => "Html",
+            PasteFormat::Rich => "Rich",
+        }
+    }
+
+    /// Per-paste override: Shift+Enter pastes plain text, Ctrl+Shift+Enter
+    /// Markdown and Ctrl+Enter rich text. Plain Enter keeps the configured default.
+    pub fn with_modifiers(self, ctrl: bool, shift: bool) -> PasteFormat {
+        match (ctrl, shift) {
+            (false, false) => self,
+            (false, true) => PasteFormat::Plain,
+            (true, true) => PasteFormat::Markdown,
+            (true, false) => PasteFormat::Rich,
+        }
+    }
+
+    pub fn render(&self, content: &str) -> PastePayload {
+        self.render_lines(&parse(content))
+    }
+
+    pub fn render_lines(&self, doc: &[Line]) -> PastePayload {
+        match self {
+            PasteFormat::Plain => PastePayload { text: to_plain(doc), html: None },
+            PasteFormat::Markdown => PastePayload { text: to_markdown(doc), html: None },
+            PasteFormat::Html => PastePayload { text: to_html(doc), html: None },
+            PasteFormat::Rich => PastePayload { text: to_plain(doc), html: Some(to_html(doc)) },
+        }
+    }
+}
+
+/// What ends up on the clipboard: always a text flavour, optionally an HTML fragment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PastePayload {
+    pub text: String,
+    pub html: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineKind {
+    Text,
+    Heading(u8),
+    Bullet,
+    Ordered(u32),
+    Checkbox(bool),
+    Quote,
+    Rule,
+    Image(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Span {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strike: bool,
+    pub highlight: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub kind: LineKind,
+    pub indent: u32,
+    pub spans: Vec<Span>,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    attrs: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+    }
+}
+
+fn parse_tag(raw: &str) -> Tag {
+    let inner = raw.trim_start_matches('<').trim_end_matches('>').trim();
+    let closing = inner.starts_with('/');
+    let inner = inner.trim_start_matches('/').trim_end_matches('/').trim();
+
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = inner[..name_end].to_lowercase();
+
+    let mut attrs = Vec::new();
+    let mut rest = inner[name_end..].trim_start();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let (value, remaining) = if let Some(q) = after.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let body = &after[1..];
+            match body.find(q) {
+                Some(end) => (&body[..end], &body[end + 1..]),
+                None => (body, ""),
+            }
+        } else {
+            let end = after.find(char::is_whitespace).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        attrs.push((key, decode_entities(value)));
+        rest = remaining.trim_start();
+    }
+
+    Tag { name, closing, attrs }
+}
+
+pub fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn escape_entities(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Default, Clone, Copy)]
+struct Style {
+    bold: u32,
+    italic: u32,
+    underline: u32,
+    strike: u32,
+    highlight: u32,
+}
+
+fn push_text(spans: &mut Vec<Span>, text: &str, style: &Style) {
+    if text.is_empty() {
+        return;
+    }
+    let span = Span {
+        text: decode_entities(text),
+        bold: style.bold > 0,
+        italic: style.italic > 0,
+        underline: style.underline > 0,
+        strike: style.strike > 0,
+        highlight: style.highlight > 0,
+    };
+    if let Some(last) = spans.last_mut()
+        && last.bold == span.bold && last.italic == span.italic && last.underline == span.underline
+        && last.strike == span.strike && last.highlight == span.highlight
+    {
+        last.text.push_str(&span.text);
+        return;
+    }
+    spans.push(span);
+}
+
+fn parse_line(raw: &str, in_quote: &mut bool) -> Line {
+    let mut line = Line { kind: LineKind::Text, indent: 1, spans: Vec::new() };
+    if *in_quote {
+        line.kind = LineKind::Quote;
+    }
+    let mut style = Style::default();
+    let mut heading: Option<u8> = None;
+    let mut rest = raw;
+
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            push_text(&mut line.spans, rest, &style);
+            break;
+        };
+        let Some(gt) = rest[lt..].find('>').map(|i| lt + i) else {
+            push_text(&mut line.spans, rest, &style);
+            break;
+        };
+        push_text(&mut line.spans, &rest[..lt], &style);
+
+        let tag = parse_tag(&rest[lt..=gt]);
+        let delta: i32 = if tag.closing { -1 } else { 1 };
+        let bump = |v: &mut u32| *v = (*v as i32 + delta).max(0) as u32;
+        match tag.name.as_str() {
+            "text" => {
+                if let Some(i) = tag.attr("indent").and_then(|v| v.parse().ok()) {
+                    line.indent = i;
+                }
+            }
+            "b" => bump(&mut style.bold),
+            "i" => bump(&mut style.italic),
+            "u" => bump(&mut style.underline),
+            "delete" | "strike" | "s" => bump(&mut style.strike),
+            "background" => bump(&mut style.highlight),
+            "size" if !tag.closing => heading = Some(1),
+            "mid-size" if !tag.closing => heading = Some(2),
+            "h3-size" if !tag.closing => heading = Some(3),
+            "quote" => {
+                *in_quote = !tag.closing;
+                line.kind = LineKind::Quote;
+            }
+            "hr" => line.kind = LineKind::Rule,
+            "bullet" => {
+                line.kind = LineKind::Bullet;
+                if let Some(i) = tag.attr("indent").and_then(|v| v.parse().ok()) { line.indent = i; }
+            }
+            "order" => {
+                let n = tag.attr("inputNumber").and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+                line.kind = LineKind::Ordered(n + 1);
+                if let Some(i) = tag.attr("indent").and_then(|v| v.parse().ok()) { line.indent = i; }
+            }
+            "input" if tag.attr("type") == Some("checkbox") => {
+                line.kind = LineKind::Checkbox(tag.attr("checked") == Some("true"));
+                if let Some(i) = tag.attr("indent").and_then(|v| v.parse().ok()) { line.indent = i; }
+            }
+            "img" => {
+                if let Some(id) = tag.attr("fileid") {
+                    line.kind = LineKind::Image(id.to_string());
+                }
+            }
+            _ => {}
+        }
+        rest = &rest[gt + 1..];
+    }
+
+    // Legacy image references look like "☺ <fileid><0/></>"
+    if let Some(first) = line.spans.first()
+        && let Some(id) = first.text.trim().strip_prefix('☺')
+    {
+        let id = id.trim().to_string();
+        if !id.is_empty() {
+            line.kind = LineKind::Image(id);
+            line.spans.clear();
+        }
+    }
+
+    if let (Some(level), LineKind::Text) = (heading, &line.kind) {
+        line.kind = LineKind::Heading(level);
+    }
+    line
+}
+
+/// Parses Xiaomi note markup (`<text indent="1">`, `<b>`, `<input type="checkbox" />`, ...)
+/// into a line-oriented document.
+pub fn parse(content: &str) -> Vec<Line> {
+    // Snippets sometimes arrive with the markup itself entity-encoded.
+    let decoded;
+    let src = if !content.contains('<') && content.contains("&lt;") {
+        decoded = decode_entities(content);
+        decoded.as_str()
+    } else {
+        content
+    };
+
+    // <quote> may span several lines, so its state is carried across them.
+    let mut in_quote = false;
+    src.split('\n')
+        .map(|l| parse_line(l.trim_end_matches('\r'), &mut in_quote))
+        .collect()
+}
+
+fn indent_prefix(line: &Line) -> String {
+    "  ".repeat(line.indent.saturating_sub(1) as usize)
+}
+
+pub fn to_plain(doc: &[Line]) -> String {
+    doc.iter()
+        .map(|line| {
+            let text = line.text();
+            let pad = indent_prefix(line);
+            match &line.kind {
+                LineKind::Text | LineKind::Heading(_) | LineKind::Quote => format!("{}{}", pad, text),
+                LineKind::Bullet => format!("{}• {}", pad, text),
+                LineKind::Ordered(n) => format!("{}{}. {}", pad, n, text),
+                LineKind::Checkbox(true) => format!("{}☑ {}", pad, text),
+                LineKind::Checkbox(false) => format!("{}☐ {}", pad, text),
+                LineKind::Rule => "----------".to_string(),
+                LineKind::Image(_) => "[image]".to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '~' | '[' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn spans_to_markdown(spans: &[Span]) -> String {
+    spans.iter()
+        .map(|s| {
+            let body = escape_markdown(&s.text);
+            if body.trim().is_empty() {
+                return body;
+            }
+            let mut wrapped = body;
+            if s.strike { wrapped = format!("~~{}~~", wrapped); }
+            if s.italic { wrapped = format!("*{}*", wrapped); }
+            if s.bold { wrapped = format!("**{}**", wrapped); }
+            if s.highlight { wrapped = format!("=={}==", wrapped); }
+            wrapped
+        })
+        .collect()
+}
+
+pub fn to_markdown(doc: &[Line]) -> String {
+    doc.iter()
+        .map(|line| {
+            let text = spans_to_markdown(&line.spans);
+            let pad = indent_prefix(line);
+            match &line.kind {
+                LineKind::Text => format!("{}{}", pad, text),
+                LineKind::Heading(level) => format!("{} {}", "#".repeat(*level as usize), text),
+                LineKind::Bullet => format!("{}- {}", pad, text),
+                LineKind::Ordered(n) => format!("{}{}. {}", pad, n, text),
+                LineKind::Checkbox(checked) => format!("{}- [{}] {}", pad, if *checked { "x" } else { " " }, text),
+                LineKind::Quote => format!("> {}", text),
+                LineKind::Rule => "---".to_string(),
+                LineKind::Image(id) => format!("![image]({})", id),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn spans_to_html(spans: &[Span]) -> String {
+    spans.iter()
+        .map(|s| {
+            let mut out = escape_entities(&s.text);
+            if s.highlight { out = format!("<mark>{}</mark>", out); }
+            if s.strike { out = format!("<s>{}</s>", out); }
+            if s.underline { out = format!("<u>{}</u>", out); }
+            if s.italic { out = format!("<i>{}</i>", out); }
+            if s.bold { out = format!("<b>{}</b>", out); }
+            out
+        })
+        .collect()
+}
+
+pub fn to_html(doc: &[Line]) -> String {
+    let mut out = String::new();
+    let mut open_list: Option<&'static str> = None;
+
+    for line in doc {
+        let list = match line.kind {
+            LineKind::Bullet | LineKind::Checkbox(_) => Some("ul"),
+            LineKind::Ordered(_) => Some("ol"),
+            _ => None,
+        };
+        if open_list != list {
+            if let Some(tag) = open_list {
+                out.push_str(&format!("</{}>", tag));
+            }
+            if let Some(tag) = list {
+                out.push_str(&format!("<{}>", tag));
+            }
+            open_list = list;
+        }
+
+        let body = spans_to_html(&line.spans);
+        match &line.kind {
+            LineKind::Text if body.is_empty() => out.push_str("<p><br></p>"),
+            LineKind::Text => out.push_str(&format!("<p>{}</p>", body)),
+            LineKind::Heading(level) => out.push_str(&format!("<h{0}>{1}</h{0}>", level, body)),
+            LineKind::Bullet | LineKind::Ordered(_) => out.push_str(&format!("<li>{}</li>", body)),
+            LineKind::Checkbox(checked) => out.push_str(&format!(
+                "<li><input type=\"checkbox\" disabled{}> {}</li>",
+                if *checked { " checked" } else { "" },
+                body
+            )),
+            LineKind::Quote => out.push_str(&format!("<blockquote>{}</blockquote>", body)),
+            LineKind::Rule => out.push_str("<hr>"),
+            LineKind::Image(id) => out.push_str(&format!("<p>[image {}]</p>", escape_entities(id))),
+        }
+    }
+    if let Some(tag) = open_list {
+        out.push_str(&format!("</{}>", tag));
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentKind {
+    Paragraph,
+    Heading,
+    ListItem,
+    Checkbox,
+    Code,
+}
+
+/// A pasteable piece of a note, as listed when drilling into it from the launch bar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub kind: FragmentKind,
+    pub lines: Vec<Line>,
+}
+
+impl Fragment {
+    pub fn label(&self) -> String {
+        let text = self.lines.iter()
+            .map(|l| l.text().trim().to_string())
+            .collect::<Vec<_>>()
+            .join(" ↵ ");
+        match self.kind {
+            FragmentKind::Checkbox => match self.lines.first().map(|l| &l.kind) {
+                Some(LineKind::Checkbox(true)) => format!("☑ {}", text),
+                _ => format!("☐ {}", text),
+            },
+            FragmentKind::ListItem => format!("• {}", text),
+            FragmentKind::Code => format!("› {}", text),
+            _ => text,
+        }
+    }
+}
+
+const COMMAND_PREFIXES: [&str; 12] = [
+    "$ ", "sudo ", "ssh ", "git ", "cd ", "curl ", "docker ", "kubectl ", "npm ", "cargo ", "pip ", "scp ",
+];
+
+/// Heuristic for lines worth pasting on their own: URLs, addresses, commands and
+/// single tokens with path- or key-like punctuation.
+pub fn looks_like_code(text: &str) -> bool {
+    let t = text.trim();
+    if t.is_empty() {
+        return false;
+    }
+    if t.contains("://") || COMMAND_PREFIXES.iter().any(|p| t.starts_with(p)) {
+        return true;
+    }
+    let dotted_digits = t.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .any(|tok| tok.split('.').filter(|p| !p.is_empty()).count() >= 3);
+    if dotted_digits {
+        return true;
+    }
+    !t.contains(char::is_whitespace) && t.contains(['/', '\\', ':', '=', '@'])
+}
+
+/// Splits a parsed note into paragraphs, headings, list/checkbox items and
+/// code-like lines. Code-like lines inside a multi-line paragraph are also
+/// listed on their own right after the paragraph.
+pub fn fragments(doc: &[Line]) -> Vec<Fragment> {
+    fn flush(out: &mut Vec<Fragment>, para: &mut Vec<Line>) {
+        if para.is_empty() {
+            return;
+        }
+        let lines = std::mem::take(para);
+        let code: Vec<Line> = if lines.len() > 1 {
+            lines.iter().filter(|l| looks_like_code(&l.text())).cloned().collect()
+        } else {
+            Vec::new()
+        };
+        let kind = if lines.len() == 1 && looks_like_code(&lines[0].text()) {
+            FragmentKind::Code
+        } else {
+            FragmentKind::Paragraph
+        };
+        out.push(Fragment { kind, lines });
+        out.extend(code.into_iter().map(|l| Fragment { kind: FragmentKind::Code, lines: vec![l] }));
+    }
+
+    let mut out = Vec::new();
+    let mut para: Vec<Line> = Vec::new();
+    for line in doc {
+        let single = |kind| Fragment { kind, lines: vec![line.clone()] };
+        match line.kind {
+            LineKind::Text | LineKind::Quote => {
+                if line.text().trim().is_empty() {
+                    flush(&mut out, &mut para);
+                } else {
+                    para.push(line.clone());
+                }
+            }
+            LineKind::Heading(_) => {
+                flush(&mut out, &mut para);
+                out.push(single(FragmentKind::Heading));
+            }
+            LineKind::Bullet | LineKind::Ordered(_) => {
+                flush(&mut out, &mut para);
+                out.push(single(FragmentKind::ListItem));
+            }
+            LineKind::Checkbox(_) => {
+                flush(&mut out, &mut para);
+                out.push(single(FragmentKind::Checkbox));
+            }
+            LineKind::Rule | LineKind::Image(_) => flush(&mut out, &mut para),
+        }
+    }
+    flush(&mut out, &mut para);
+    out
+}
+
+/// Converts plain text (e.g. typed into the launch bar) into Xiaomi note markup.
+pub fn from_plain(text: &str) -> String {
+    text.lines()
+        .map(|l| format!("<text indent=\"1\">{}</text>", escape_entities(l)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A journal line such as `[2024-05-01 09:30] text`, as markup.
+pub fn timestamped_line(text: &str, stamp: &str) -> String {
+    from_plain(&format!("[{}] {}", stamp, text))
+}
+
+/// Wraps an HTML fragment in the Windows "HTML Format" (CF_HTML) envelope.
+/// Offsets are byte positions into the UTF-8 payload, as the format requires.
+pub fn cf_html(fragment: &str) -> String {
+    const HEADER: &str = "Version:0.9\r\nStartHTML:{sh}\r\nEndHTML:{eh}\r\nStartFragment:{sf}\r\nEndFragment:{ef}\r\n";
+    const PREFIX: &str = "<html><body>\r\n<!--StartFragment-->";
+    const SUFFIX: &str = "<!--EndFragment-->\r\n</body></html>";
+
+    // Every placeholder is rendered as 10 digits, so the header length is fixed.
+    let header_len = HEADER.len() - "{sh}{eh}{sf}{ef}".len() + 4 * 10;
+    let start_html = header_len;
+    let start_fragment = start_html + PREFIX.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + SUFFIX.len();
+
+    let header = HEADER
+        .replace("{sh}", &format!("{:010}", start_html))
+        .replace("{eh}", &format!("{:010}", end_html))
+        .replace("{sf}", &format!("{:010}", start_fragment))
+        .replace("{ef}", &format!("{:010}", end_fragment));
+
+    format!("{}{}{}{}", header, PREFIX, fragment, SUFFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "<size>Plan</size>\n\
+        <text indent=\"1\">Buy <b>milk</b> &amp; <i>eggs</i></text>\n\
+        <bullet indent=\"2\" />first\n\
+        <order indent=\"1\" inputNumber=\"0\" />one\n\
+        <input type=\"checkbox\" indent=\"1\" checked=\"true\" />done\n\
+        <input type=\"checkbox\" indent=\"1\" />todo\n\
+        <hr />\n\
+        <quote><text indent=\"1\">quoted</text></quote>\n\
+        ☺ abc123<0/></>";
+
+    #[test]
+    fn parses_line_kinds() {
+        let doc = parse(NOTE);
+        let kinds: Vec<_> = doc.iter().map(|l| l.kind.clone()).collect();
+        assert_eq!(kinds, vec![
+            LineKind::Heading(1),
+            LineKind::Text,
+            LineKind::Bullet,
+            LineKind::Ordered(1),
+            LineKind::Checkbox(true),
+            LineKind::Checkbox(false),
+            LineKind::Rule,
+            LineKind::Quote,
+            LineKind::Image("abc123".to_string()),
+        ]);
+        assert_eq!(doc[1].text(), "Buy milk & eggs");
+        assert!(doc[1].spans.iter().any(|s| s.bold && s.text == "milk"));
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(to_plain(&parse(NOTE)), "Plan\nBuy milk & eggs\n  • first\n1. one\n☑ done\n☐ todo\n----------\nquoted\n[image]");
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            to_markdown(&parse(NOTE)),
+            "# Plan\nBuy **milk** & *eggs*\n  - first\n1. one\n- [x] done\n- [ ] todo\n---\n> quoted\n![image](abc123)"
+        );
+        // Markdown syntax in note text is escaped
+        assert_eq!(to_markdown(&parse("<text indent=\"1\">a_b*c</text>")), "a\\_b\\*c");
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(
+            to_html(&parse(NOTE)),
+            "<h1>Plan</h1><p>Buy <b>milk</b> &amp; <i>eggs</i></p><ul><li>first</li></ul><ol><li>one</li></ol>\
+             <ul><li><input type=\"checkbox\" disabled checked> done</li><li><input type=\"checkbox\" disabled> todo</li></ul>\
+             <hr><blockquote>quoted</blockquote><p>[image abc123]</p>"
+        );
+        assert_eq!(to_html(&parse("<text indent=\"1\"></text>")), "<p><br></p>");
+    }
+
+    #[test]
+    fn cf_html_offsets_point_at_the_fragment() {
+        let fragment = "<p>héllo</p>";
+        let out = cf_html(fragment);
+        let offset = |key: &str| -> usize {
+            let start = out.find(key).unwrap() + key.len();
+            out[start..start + 10].parse().unwrap()
+        };
+        assert_eq!(&out[offset("StartFragment:")..offset("EndFragment:")], fragment);
+        assert!(out[offset("StartHTML:")..].starts_with("<html>"));
+        assert_eq!(offset("EndHTML:"), out.len());
+    }
+
+    #[test]
+    fn code_like_lines() {
+        for code in ["https://example.com/x", "ssh root@10.0.0.1", "192.168.1.10", "C:\\Users\\me", "API_KEY=abc", "git status"] {
+            assert!(looks_like_code(code), "{}", code);
+        }
+        for text in ["", "   ", "call the plumber", "version 2.0 is out"] {
+            assert!(!looks_like_code(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn fragments_split_paragraphs_items_and_code() {
+        let doc = parse("<size>Server</size>\n\
+            <text indent=\"1\">Log in with</text>\n\
+            <text indent=\"1\">ssh root@10.0.0.1</text>\n\
+            <text indent=\"1\"></text>\n\
+            <text indent=\"1\">https://example.com/x</text>\n\
+            <bullet indent=\"1\" />first\n\
+            <input type=\"checkbox\" indent=\"1\" checked=\"true\" />done\n\
+            <hr />\n\
+            <text indent=\"1\">after the rule</text>");
+        let found: Vec<(FragmentKind, String)> = fragments(&doc).iter().map(|f| (f.kind, f.label())).collect();
+        assert_eq!(found, vec![
+            (FragmentKind::Heading, "Server".to_string()),
+            (FragmentKind::Paragraph, "Log in with ↵ ssh root@10.0.0.1".to_string()),
+            // The command inside the paragraph is offered on its own as well
+            (FragmentKind::Code, "› ssh root@10.0.0.1".to_string()),
+            (FragmentKind::Code, "› https://example.com/x".to_string()),
+            (FragmentKind::ListItem, "• first".to_string()),
+            (FragmentKind::Checkbox, "☑ done".to_string()),
+            (FragmentKind::Paragraph, "after the rule".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn plain_text_round_trips_through_markup() {
+        let text = "line <one> & \"two\"\nsecond";
+        let markup = from_plain(text);
+        assert_eq!(markup, "<text indent=\"1\">line &lt;one&gt; &amp; &quot;two&quot;</text>\n<text indent=\"1\">second</text>");
+        assert_eq!(to_plain(&parse(&markup)), text);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::api::markup::escape_entities;
+
+/// Result of a three-way merge. `content` carries conflict markers when
+/// `conflicts > 0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    pub content: String,
+    pub conflicts: usize,
+}
+
+impl Merge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+/// For each line of `a`, the index of the line of `b` it is paired with in a
+/// longest common subsequence.
+fn lcs_map(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    let mut map = vec![None; a.len()];
+    pair_lines(a, b, (0, 0), &mut map);
+    map
+}
+
+/// Myers' diff in linear space: pairs the lines of one LCS of `a` and `b` into
+/// `map`, with `a` and `b` starting at `offset` in the full inputs. Unchanged lines
+/// at either end are paired directly; the rest is split at the middle snake. Takes
+/// O((N+M)·D) time for D inserted and removed lines, so small edits to long notes
+/// stay cheap.
+fn pair_lines(a: &[&str], b: &[&str], offset: (usize, usize), map: &mut [Option<usize>]) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    for k in 0..prefix {
+        map[offset.0 + k] = Some(offset.1 + k);
+    }
+    for k in 1..=suffix {
+        map[offset.0 + a.len() - k] = Some(offset.1 + b.len() - k);
+    }
+
+    let (a, b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let offset = (offset.0 + prefix, offset.1 + prefix);
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    // With both ends trimmed and both sides non-empty there are at least two edits,
+    // so the snake splits this into two strictly smaller problems
+    let ((x, y), (u, v)) = middle_snake(a, b);
+    for k in 0..u - x {
+        map[offset.0 + x + k] = Some(offset.1 + y + k);
+    }
+    pair_lines(&a[..x], &b[..y], offset, map);
+    pair_lines(&a[u..], &b[v..], (offset.0 + u, offset.1 + v), map);
+}
+
+/// Start and end of the diagonal run of equal lines in the middle of a shortest
+/// edit script, found by searching from both ends until the paths overlap.
+fn middle_snake(a: &[&str], b: &[&str]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let off = max + 1;
+    // Furthest x reached on each diagonal k = x - y; backwards in reversed coordinates
+    let mut forward = vec![0isize; 2 * off as usize + 1];
+    let mut backward = forward.clone();
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let i = (k + off) as usize;
+            let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) { forward[i + 1] } else { forward[i - 1] + 1 };
+            let mut y = x - k;
+            let start = (x as usize, y as usize);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+            let reverse_k = delta - k;
+            if odd && (-(d - 1)..=d - 1).contains(&reverse_k) && x + backward[(reverse_k + off) as usize] >= n {
+                return (start, (x as usize, y as usize));
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let i = (k + off) as usize;
+            let mut x = if k == -d || (k != d && backward[i - 1] < backward[i + 1]) { backward[i + 1] } else { backward[i - 1] + 1 };
+            let mut y = x - k;
+            let start = (x, y);
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+            let forward_k = delta - k;
+            if !odd && (-d..=d).contains(&forward_k) && x + forward[(forward_k + off) as usize] >= n {
+                return (((n - x) as usize, (m - y) as usize), ((n - start.0) as usize, (m - start.1) as usize));
+            }
+        }
+    }
+    unreachable!("the forward and backward paths always meet")
+}
+
+/// Line-based diff3. Lines changed on one side only are taken from that side;
+/// lines changed differently on both sides become a conflict block rendered by
+/// `marker`.
+fn merge_lines(base: &[&str], local: &[&str], remote: &[&str], marker: impl Fn(&str) -> String) -> Merge {
+    let to_local = lcs_map(base, local);
+    let to_remote = lcs_map(base, remote);
+
+    let mut out: Vec<String> = Vec::new();
+    let mut conflicts = 0;
+    let mut emit = |o: &[&str], l: &[&str], r: &[&str], out: &mut Vec<String>| {
+        let pick: Option<&[&str]> = if l == o {
+            Some(r)
+        } else if r == o || l == r {
+            Some(l)
+        } else {
+            None
+        };
+        match pick {
+            Some(lines) => out.extend(lines.iter().map(|s| s.to_string())),
+            None => {
+                conflicts += 1;
+                out.push(marker("<<<<<<< local"));
+                out.extend(l.iter().map(|s| s.to_string()));
+                out.push(marker("======="));
+                out.extend(r.iter().map(|s| s.to_string()));
+                out.push(marker(">>>>>>> remote"));
+            }
+        }
+    };
+
+    let (mut i, mut a, mut b) = (0, 0, 0);
+    loop {
+        // Next base line kept by both sides; both maps are monotonic
+        let stable = (i..base.len()).find_map(|k| match (to_local[k], to_remote[k]) {
+            (Some(x), Some(y)) => Some((k, x, y)),
+            _ => None,
+        });
+        match stable {
+            Some((k, x, y)) => {
+                emit(&base[i..k], &local[a..x], &remote[b..y], &mut out);
+                out.push(base[k].to_string());
+                i = k + 1;
+                a = x + 1;
+                b = y + 1;
+            }
+            None => {
+                emit(&base[i..], &local[a..], &remote[b..], &mut out);
+                break;
+            }
+        }
+    }
+
+    Merge { content: out.join("\n"), conflicts }
+}
+
+/// Three-way merge of plain text.
+pub fn merge_text(base: &str, local: &str, remote: &str) -> Merge {
+    let (base, local, remote): (Vec<&str>, Vec<&str>, Vec<&str>) =
+        (base.lines().collect(), local.lines().collect(), remote.lines().collect());
+    merge_lines(&base, &local, &remote, |m| m.to_string())
+}
+
+/// Three-way merge of Xiaomi note markup. Each markup line is one block
+/// (paragraph, list item, checkbox...), so blocks are merged as units and
+/// conflict markers are emitted as their own text lines.
+pub fn merge_markup(base: &str, local: &str, remote: &str) -> Merge {
+    let (base, local, remote): (Vec<&str>, Vec<&str>, Vec<&str>) =
+        (base.split('\n').collect(), local.split('\n').collect(), remote.split('\n').collect());
+    merge_lines(&base, &local, &remote, |m| format!("<text indent=\"1\">{}</text>", escape_entities(m)))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Line diff of `old` against `new`, built on the same LCS as the merge.
+pub fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let map = lcs_map(old, new);
+    let mut out = Vec::new();
+    let mut j = 0;
+    for (i, line) in old.iter().enumerate() {
+        match map[i] {
+            Some(k) => {
+                out.extend(new[j..k].iter().map(|l| DiffLine::Added(l)));
+                out.push(DiffLine::Same(line));
+                j = k + 1;
+            }
+            None => out.push(DiffLine::Removed(line)),
+        }
+    }
+    out.extend(new[j..].iter().map(|l| DiffLine::Added(l)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pairs of `lcs_map` must be a common subsequence: increasing and equal lines.
+    fn assert_valid(a: &[&str], b: &[&str], map: &[Option<usize>]) -> usize {
+        let pairs: Vec<(usize, usize)> = map.iter().enumerate().filter_map(|(i, j)| j.map(|j| (i, j))).collect();
+        for w in pairs.windows(2) {
+            assert!(w[0].1 < w[1].1, "not increasing: {:?}", pairs);
+        }
+        for &(i, j) in &pairs {
+            assert_eq!(a[i], b[j]);
+        }
+        pairs.len()
+    }
+
+    #[test]
+    fn lcs_map_finds_a_longest_common_subsequence() {
+        let cases: [(&str, &str, usize); 5] = [
+            ("a b c d e", "a x c d y e", 4),
+            ("a b c", "c b a", 1),
+            ("x a b c y", "z a b c w", 3),
+            ("a a b a", "a b a a", 3),
+            ("", "a b", 0),
+        ];
+        for (a, b, len) in cases {
+            let (a, b): (Vec<&str>, Vec<&str>) = (a.split_whitespace().collect(), b.split_whitespace().collect());
+            assert_eq!(assert_valid(&a, &b, &lcs_map(&a, &b)), len, "{:?} / {:?}", a, b);
+        }
+    }
+
+    /// LCS length from the full table, to check `lcs_map` against.
+    fn lcs_len(a: &[&str], b: &[&str]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] { table[i + 1][j + 1] + 1 } else { table[i + 1][j].max(table[i][j + 1]) };
+            }
+        }
+        table[0][0]
+    }
+
+    #[test]
+    fn lcs_map_matches_the_full_table_on_random_inputs() {
+        let words = ["a", "b", "c", "d"];
+        let mut seed = 0x2545_f491_u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+        for _ in 0..500 {
+            let a: Vec<&str> = (0..next(12)).map(|_| words[next(4)]).collect();
+            let b: Vec<&str> = (0..next(12)).map(|_| words[next(4)]).collect();
+            assert_eq!(assert_valid(&a, &b, &lcs_map(&a, &b)), lcs_len(&a, &b), "{:?} / {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn long_notes_with_a_small_edit_diff_quickly() {
+        let base: Vec<String> = (0..50_000).map(|i| format!("line {}", i)).collect();
+        let old: Vec<&str> = base.iter().map(String::as_str).collect();
+        let mut new = old.clone();
+        new[25_000] = "changed";
+        new.insert(40_000, "inserted");
+
+        let diff = diff_lines(&old, &new);
+        assert_eq!(diff.iter().filter(|l| !matches!(l, DiffLine::Same(_))).count(), 3);
+        assert!(diff.contains(&DiffLine::Removed("line 25000")));
+        assert!(diff.contains(&DiffLine::Added("inserted")));
+    }
+
+    #[test]
+    fn disjoint_edits_merge_cleanly() {
+        let merged = merge_text("a\nb\nc\nd", "a\nB\nc\nd", "a\nb\nc\nD");
+        assert!(merged.is_clean());
+        assert_eq!(merged.content, "a\nB\nc\nD");
+    }
+
+    #[test]
+    fn overlapping_edits_conflict() {
+        let merged = merge_text("a\nb\nc", "a\nlocal\nc", "a\nremote\nc");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(merged.content, "a\n<<<<<<< local\nlocal\n=======\nremote\n>>>>>>> remote\nc");
+    }
+}
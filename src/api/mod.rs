@@ -1,7 +1,28 @@
 pub mod models;
 pub mod client;
 pub mod auth;
+pub mod backup;
 pub mod config;
+pub mod config_service;
+pub mod cookie;
+pub mod error;
+pub mod history;
+pub mod hotkey;
+pub mod http;
+pub mod import;
+pub mod json_file;
+pub mod login;
+pub mod merge;
+#[cfg(test)]
+pub mod mock_server;
+pub mod markup;
+pub mod outbox;
+pub mod overrides;
+pub mod profile;
+pub mod schedule;
+pub mod secrets;
+pub mod session;
+pub mod status;
 
 pub use client::Client;
 pub use config::AppConfig;
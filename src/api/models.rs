@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize, Deserializer};
 use serde_json::Value;
+use crate::api::MiResult;
+use crate::api::error::ApiError;
 
 pub fn deserialize_id<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
@@ -34,8 +36,11 @@ pub struct Note {
     pub id: String,
     #[serde(default, deserialize_with = "deserialize_opt_id")]
     pub folder_id: Option<String>,
+    #[serde(default)]
     pub color_id: i32,
+    #[serde(default)]
     pub create_date: i64,
+    #[serde(default)]
     pub modify_date: i64,
     #[serde(default)]
     pub subject: String,
@@ -47,6 +52,7 @@ pub struct Note {
     pub extra_info: Option<String>,
     #[serde(deserialize_with = "deserialize_id")]
     pub tag: String,
+    #[serde(default)]
     pub status: String,
     pub setting: Option<Setting>,
 }
@@ -86,6 +92,11 @@ impl Note {
     pub fn clean_snippet(&self) -> String {
         strip_tags(&self.snippet)
     }
+
+    /// Notes in the recycle bin have status "deleted"; live ones "normal".
+    pub fn is_deleted(&self) -> bool {
+        self.status == "deleted"
+    }
 }
 
 pub fn strip_tags(text: &str) -> String {
@@ -125,8 +136,11 @@ pub struct Folder {
     pub id: String,
     #[serde(default, deserialize_with = "deserialize_opt_id")]
     pub folder_id: Option<String>,
+    #[serde(default)]
     pub create_date: i64,
+    #[serde(default)]
     pub modify_date: i64,
+    #[serde(default)]
     pub subject: String,
     #[serde(deserialize_with = "deserialize_id")]
     pub tag: String,
@@ -162,14 +176,177 @@ pub struct NotesResponse {
     pub sync_tag: Option<String>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EntryResponse {
     pub entry: Note,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FolderResponse {
+    #[serde(alias = "entry")]
     pub folder: Folder,
 }
+
+/// Envelope around every i.mi.com response, e.g.
+/// `{"result": "ok", "retriable": false, "code": 0, "data": {...}, "description": "成功", "ts": 1700000000000}`.
+/// Failures use the same shape with a non-zero `code` and usually `"result": "error"`.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ApiEnvelope {
+    #[serde(default)]
+    pub result: Option<String>,
+    #[serde(default)]
+    pub code: i64,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+impl ApiEnvelope {
+    pub fn is_success(&self) -> bool {
+        self.code == 0 && self.result.as_deref().is_none_or(|r| r.eq_ignore_ascii_case("ok"))
+    }
+
+    fn error(&self) -> ApiError {
+        let description = self.description.clone()
+            .or_else(|| self.reason.clone())
+            .or_else(|| self.result.clone())
+            .unwrap_or_default();
+        ApiError::Api { code: self.code, description }
+    }
+}
+
+/// Decodes a raw response into its `data`, turning HTTP errors and
+/// non-success envelopes (which Xiaomi returns with HTTP 200) into `ApiError`s.
+pub fn decode_response(status: u16, body: &[u8]) -> MiResult<Option<Value>> {
+    let success_status = (200..300).contains(&status);
+
+    match serde_json::from_slice::<ApiEnvelope>(body) {
+        Ok(env) if !env.is_success() => Err(env.error().into()),
+        Ok(env) if success_status => Ok(env.data),
+        Err(e) if success_status => Err(ApiError::UnexpectedResponse(format!("{} in body: {}", e, String::from_utf8_lossy(body))).into()),
+        _ => Err(ApiError::Http {
+            status,
+            body: String::from_utf8_lossy(body).chars().take(500).collect(),
+        }.into()),
+    }
+}
+
+/// The note in `data`, which is `{"entry": {...}}` for note endpoints.
+pub fn decode_entry(data: Option<Value>) -> MiResult<Note> {
+    let data = data.ok_or_else(|| ApiError::UnexpectedResponse("no data in response".to_string()))?;
+    if let Ok(res) = serde_json::from_value::<EntryResponse>(data.clone()) {
+        return Ok(res.entry);
+    }
+    serde_json::from_value::<Note>(data.clone())
+        .map_err(|e| ApiError::UnexpectedResponse(format!("could not parse note ({}): {}", e, data)).into())
+}
+
+/// The folder in `data`, sent as `{"folder": {...}}` or `{"entry": {...}}`.
+pub fn decode_folder(data: Option<Value>) -> MiResult<Folder> {
+    let data = data.ok_or_else(|| ApiError::UnexpectedResponse("no data in response".to_string()))?;
+    if let Ok(res) = serde_json::from_value::<FolderResponse>(data.clone()) {
+        return Ok(res.folder);
+    }
+    serde_json::from_value::<Folder>(data.clone())
+        .map_err(|e| ApiError::UnexpectedResponse(format!("could not parse folder ({}): {}", e, data)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::api::MiError;
+
+    fn api_error(e: MiError) -> ApiError {
+        match e.downcast::<ApiError>() {
+            Ok(e) => *e,
+            Err(e) => panic!("not an ApiError: {}", e),
+        }
+    }
+
+    const NOTE: &str = r#"{"result":"ok","code":0,"description":"成功","ts":1700000000000,
+        "data":{"entry":{"id":38120134540730848,"folderId":0,"createDate":1700000000000,"modifyDate":1700000001000,
+        "subject":"","snippet":"Groceries","content":"<text>Groceries</text>","tag":"38120134540730849","status":"normal"}}}"#;
+
+    #[test]
+    fn success_envelope_yields_its_data() {
+        let note = decode_entry(decode_response(200, NOTE.as_bytes()).unwrap()).unwrap();
+        // Numeric ids are kept as strings
+        assert_eq!(note.id, "38120134540730848");
+        assert_eq!(note.folder_id.as_deref(), Some("0"));
+        assert_eq!(note.tag, "38120134540730849");
+        assert_eq!(note.content.as_deref(), Some("<text>Groceries</text>"));
+    }
+
+    #[test]
+    fn nonzero_code_is_an_api_error_even_with_http_200() {
+        let body = br#"{"result":"error","code":10017,"description":"entry not exist","data":null}"#;
+        match api_error(decode_response(200, body).unwrap_err()) {
+            ApiError::Api { code, description } => {
+                assert_eq!(code, 10017);
+                assert_eq!(description, "entry not exist");
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn result_other_than_ok_is_an_api_error() {
+        let body = br#"{"result":"failed","code":0,"reason":"tag mismatch"}"#;
+        match api_error(decode_response(200, body).unwrap_err()) {
+            ApiError::Api { code: 0, description } => assert_eq!(description, "tag mismatch"),
+            e => panic!("unexpected {:?}", e),
+        }
+        // Without a description or reason the result itself is reported
+        let body = br#"{"result":"failed","code":0}"#;
+        assert!(matches!(api_error(decode_response(200, body).unwrap_err()), ApiError::Api { description, .. } if description == "failed"));
+    }
+
+    #[test]
+    fn missing_data_is_an_unexpected_response() {
+        let data = decode_response(200, br#"{"result":"ok","code":0}"#).unwrap();
+        assert!(data.is_none());
+        assert!(matches!(api_error(decode_entry(data).unwrap_err()), ApiError::UnexpectedResponse(_)));
+        assert!(matches!(api_error(decode_folder(None).unwrap_err()), ApiError::UnexpectedResponse(_)));
+        assert!(matches!(api_error(decode_entry(Some(json!({"entry": {"subject": "no id"}}))).unwrap_err()), ApiError::UnexpectedResponse(_)));
+    }
+
+    #[test]
+    fn unreadable_success_body_is_an_unexpected_response() {
+        assert!(matches!(api_error(decode_response(200, b"<html>login</html>").unwrap_err()), ApiError::UnexpectedResponse(_)));
+    }
+
+    #[test]
+    fn http_error_without_an_envelope_keeps_status_and_body() {
+        match api_error(decode_response(502, b"<html>Bad Gateway</html>").unwrap_err()) {
+            ApiError::Http { status, body } => {
+                assert_eq!(status, 502);
+                assert_eq!(body, "<html>Bad Gateway</html>");
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        // Long bodies are cut short
+        let long = "x".repeat(2000);
+        assert!(matches!(api_error(decode_response(500, long.as_bytes()).unwrap_err()), ApiError::Http { body, .. } if body.len() == 500));
+    }
+
+    #[test]
+    fn http_error_with_an_envelope_reports_the_envelope() {
+        let body = br#"{"result":"error","code":401,"description":"unauthorized"}"#;
+        assert!(matches!(api_error(decode_response(401, body).unwrap_err()), ApiError::Api { code: 401, .. }));
+        // A success envelope on an error status still counts as an HTTP error
+        let body = br#"{"result":"ok","code":0,"data":{}}"#;
+        assert!(matches!(api_error(decode_response(503, body).unwrap_err()), ApiError::Http { status: 503, .. }));
+    }
+
+    #[test]
+    fn folders_decode_from_either_wrapper() {
+        let folder = json!({"id": 7, "subject": "Work", "tag": "8"});
+        assert_eq!(decode_folder(Some(json!({"folder": folder.clone()}))).unwrap().id, "7");
+        assert_eq!(decode_folder(Some(json!({"entry": folder.clone()}))).unwrap().subject, "Work");
+        assert_eq!(decode_folder(Some(folder)).unwrap().tag, "8");
+    }
+}
@@ -0,0 +1,629 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::api::{Client, MiError, MiResult};
+use crate::api::json_file;
+use crate::api::error::{ApiError, as_conflict};
+use crate::api::merge::merge_markup;
+use crate::api::models::{Note, strip_tags_multiline};
+
+const LOCAL_ID_PREFIX: &str = "local-";
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How far the server's `createDate` may lag the local clock when looking for a
+/// note that an unconfirmed create already made.
+const CLOCK_SKEW_MS: i64 = 10 * 60 * 1000;
+
+/// A mutating call that could not be sent yet. Ids may be local ids of entities
+/// created earlier in the queue; they are swapped for server ids before sending.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum PendingOp {
+    #[serde(rename_all = "camelCase")]
+    CreateNote { folder_id: String, subject: String, content: String },
+    /// An empty `base_tag` skips the conflict check (e.g. notes created offline).
+    /// With `base_content` a conflicting remote edit is three-way merged when possible.
+    #[serde(rename_all = "camelCase")]
+    UpdateNote {
+        note_id: String,
+        base_tag: String,
+        #[serde(default)]
+        base_content: Option<String>,
+        subject: String,
+        content: String,
+        folder_id: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    DeleteNote { note_id: String, tag: String, purge: bool },
+    /// `line` is already rendered markup so the timestamp reflects when it was written.
+    #[serde(rename_all = "camelCase")]
+    AppendNote { note_id: String, line: String },
+    #[serde(rename_all = "camelCase")]
+    CreateFolder { subject: String },
+    #[serde(rename_all = "camelCase")]
+    UpdateFolder { folder_id: String, tag: String, subject: String },
+    #[serde(rename_all = "camelCase")]
+    DeleteFolder { folder_id: String, tag: String },
+}
+
+enum Outcome {
+    /// Sent; carries the server id for creates.
+    Done(Option<String>),
+    Conflict { reason: String, remote_tag: Option<String> },
+}
+
+fn is_local_id(id: &str) -> bool {
+    id.starts_with(LOCAL_ID_PREFIX)
+}
+
+impl PendingOp {
+    fn ids_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            PendingOp::CreateNote { folder_id, .. } => vec![folder_id],
+            PendingOp::UpdateNote { note_id, folder_id, .. } => {
+                let mut ids = vec![note_id];
+                if let Some(f) = folder_id { ids.push(f); }
+                ids
+            }
+            PendingOp::DeleteNote { note_id, .. } | PendingOp::AppendNote { note_id, .. } => vec![note_id],
+            PendingOp::CreateFolder { .. } => vec![],
+            PendingOp::UpdateFolder { folder_id, .. } | PendingOp::DeleteFolder { folder_id, .. } => vec![folder_id],
+        }
+    }
+
+    /// Rewrites local ids to server ids; returns the first local id with no mapping.
+    fn resolve_ids(&mut self, id_map: &HashMap<String, String>) -> Option<String> {
+        let mut unresolved = None;
+        for id in self.ids_mut() {
+            if !is_local_id(id) { continue; }
+            match id_map.get(id.as_str()) {
+                Some(server_id) => *id = server_id.clone(),
+                None => if unresolved.is_none() { unresolved = Some(id.clone()) },
+            }
+        }
+        unresolved
+    }
+
+    /// Whether sending this twice would repeat its effect (a second note, a second line).
+    fn is_repeatable(&self) -> bool {
+        matches!(self, PendingOp::CreateNote { .. } | PendingOp::AppendNote { .. } | PendingOp::CreateFolder { .. })
+    }
+
+    /// For an operation that was sent before without a confirmed answer: its outcome
+    /// if it reached the server after all, so it isn't applied a second time.
+    async fn find_applied(&self, client: &Client, queued_at: i64) -> MiResult<Option<Outcome>> {
+        match self {
+            PendingOp::CreateNote { content, .. } => {
+                let (notes, _) = client.list_all().await?;
+                for candidate in notes.iter().filter(|n| n.create_date >= queued_at - CLOCK_SKEW_MS) {
+                    let note = client.get_note(&candidate.id).await?;
+                    if note.content.as_deref() == Some(content.as_str()) {
+                        return Ok(Some(Outcome::Done(Some(note.id))));
+                    }
+                }
+                Ok(None)
+            }
+            PendingOp::AppendNote { note_id, line } => {
+                let note = client.get_note(note_id).await?;
+                let appended = note.content.is_some_and(|c| c.contains(line.as_str()));
+                Ok(appended.then_some(Outcome::Done(None)))
+            }
+            PendingOp::CreateFolder { subject } => {
+                let (_, folders) = client.list_all().await?;
+                Ok(folders.into_iter().find(|f| f.subject == *subject).map(|f| Outcome::Done(Some(f.id))))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn apply(&self, client: &Client) -> MiResult<Outcome> {
+        match self {
+            PendingOp::CreateNote { folder_id, subject, content } => {
+                let note = client.create_note(folder_id, subject, content).await?;
+                Ok(Outcome::Done(Some(note.id)))
+            }
+            PendingOp::UpdateNote { note_id, base_tag, base_content, subject, content, folder_id } => {
+                let remote = client.get_note(note_id).await?;
+                let mut to_send = content.clone();
+                if !base_tag.is_empty() && remote.tag != *base_tag {
+                    let merged = match (base_content, &remote.content) {
+                        (Some(base), Some(theirs)) => Some(merge_markup(base, content, theirs)).filter(|m| m.is_clean()),
+                        _ => None,
+                    };
+                    match merged {
+                        Some(m) => to_send = m.content,
+                        None => return Ok(Outcome::Conflict {
+                            reason: format!("Note {} was changed elsewhere (expected tag {}, server has {})", note_id, base_tag, remote.tag),
+                            remote_tag: Some(remote.tag),
+                        }),
+                    }
+                }
+                match client.update_note(note_id, &remote.tag, subject, &to_send, folder_id.as_deref()).await {
+                    Ok(_) => Ok(Outcome::Done(None)),
+                    Err(e) => match as_conflict(&e) {
+                        Some(newer) => Ok(Outcome::Conflict { reason: e.to_string(), remote_tag: Some(newer.tag.clone()) }),
+                        None => Err(e),
+                    },
+                }
+            }
+            PendingOp::DeleteNote { note_id, tag, purge } => {
+                let remote = client.get_note(note_id).await?;
+                if !tag.is_empty() && remote.tag != *tag {
+                    return Ok(Outcome::Conflict {
+                        reason: format!("Note {} was changed elsewhere since it was deleted locally", note_id),
+                        remote_tag: Some(remote.tag),
+                    });
+                }
+                client.delete_note(note_id, &remote.tag, *purge).await?;
+                Ok(Outcome::Done(None))
+            }
+            PendingOp::AppendNote { note_id, line } => {
+                client.append_to_note(note_id, line).await?;
+                Ok(Outcome::Done(None))
+            }
+            PendingOp::CreateFolder { subject } => {
+                let folder = client.create_folder(subject).await?;
+                Ok(Outcome::Done(Some(folder.id)))
+            }
+            PendingOp::UpdateFolder { folder_id, tag, subject } => {
+                client.update_folder(folder_id, tag, subject).await?;
+                Ok(Outcome::Done(None))
+            }
+            PendingOp::DeleteFolder { folder_id, tag } => {
+                client.delete_folder(folder_id, tag).await?;
+                Ok(Outcome::Done(None))
+            }
+        }
+    }
+}
+
+/// Transport, session and rate-limit errors leave the queue untouched; anything
+/// else counts as a failed attempt for the entry.
+fn is_transient(e: &MiError) -> bool {
+    if let Some(re) = e.downcast_ref::<reqwest::Error>() {
+        return re.is_connect() || re.is_timeout() || re.is_request();
+    }
+    matches!(e.downcast_ref::<ApiError>(), Some(ApiError::SessionExpired(_) | ApiError::RateLimited { .. }))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEntry {
+    pub id: String,
+    pub queued_at: i64,
+    #[serde(default)]
+    pub attempts: u32,
+    /// Set before a repeatable operation is sent and cleared with the entry, so a
+    /// lost answer (or a crash) makes the next replay check the server first.
+    #[serde(default)]
+    pub unconfirmed: bool,
+    pub op: PendingOp,
+}
+
+impl OutboxEntry {
+    /// Placeholder shown in the launch bar until a queued note reaches the server.
+    pub fn local_note(&self) -> Option<Note> {
+        match &self.op {
+            PendingOp::CreateNote { folder_id, subject, content } => Some(Note {
+                id: self.id.clone(),
+                folder_id: Some(folder_id.clone()),
+                create_date: self.queued_at,
+                modify_date: self.queued_at,
+                subject: subject.clone(),
+                snippet: strip_tags_multiline(content),
+                content: Some(content.clone()),
+                status: "normal".to_string(),
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// An entry that was taken out of the queue because it could not be applied.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxConflict {
+    pub entry: OutboxEntry,
+    pub reason: String,
+    pub remote_tag: Option<String>,
+    pub detected_at: i64,
+}
+
+/// Write operations persisted to disk and replayed in order by the background sync loop.
+/// Functions take the data directory of the profile they work on, resolved once by
+/// the caller (`AppConfig::data_dir`, `Client::data_dir`), so a profile switch
+/// halfway through never moves entries into the other profile's queue.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Outbox {
+    pub entries: Vec<OutboxEntry>,
+    /// Local id -> server id, for entries created while offline.
+    #[serde(default)]
+    pub id_map: HashMap<String, String>,
+    #[serde(default)]
+    pub conflicts: Vec<OutboxConflict>,
+}
+
+static LOCAL_ID_SEQ: AtomicU32 = AtomicU32::new(0);
+
+fn new_local_id() -> String {
+    let seq = LOCAL_ID_SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("{}{}-{}", LOCAL_ID_PREFIX, chrono::Utc::now().timestamp_millis(), seq)
+}
+
+/// An exclusive OS lock on a file next to the outbox, released when dropped. The app
+/// and the CLI share the queue, so an in-process mutex isn't enough; each lock opens
+/// its own handle, which also keeps other threads out.
+struct FileLock(Option<File>);
+
+impl FileLock {
+    fn open(dir: Option<&Path>, name: &str) -> MiResult<Option<File>> {
+        let Some(dir) = dir else { return Ok(None) };
+        fs::create_dir_all(dir)?;
+        let file = File::options().create(true).truncate(false).write(true).open(dir.join(name))?;
+        Ok(Some(file))
+    }
+
+    /// Waits for the lock.
+    fn acquire(dir: Option<&Path>, name: &str) -> MiResult<Self> {
+        let file = Self::open(dir, name)?;
+        if let Some(ref file) = file {
+            file.lock().map_err(|e| format!("Can't lock {}: {}", name, e))?;
+        }
+        Ok(Self(file))
+    }
+
+    /// `None` if someone else holds the lock.
+    fn try_acquire(dir: Option<&Path>, name: &str) -> MiResult<Option<Self>> {
+        let file = Self::open(dir, name)?;
+        match file.as_ref().map(File::try_lock) {
+            Some(Err(TryLockError::WouldBlock)) => Ok(None),
+            Some(Err(TryLockError::Error(e))) => Err(format!("Can't lock {}: {}", name, e).into()),
+            _ => Ok(Some(Self(file))),
+        }
+    }
+}
+
+impl Outbox {
+    fn path_in(dir: Option<&Path>) -> Option<PathBuf> {
+        dir.map(|dir| dir.join("outbox.json"))
+    }
+
+    /// Guards every read-modify-write of `outbox.json`.
+    fn lock(dir: Option<&Path>) -> MiResult<FileLock> {
+        FileLock::acquire(dir, "outbox.lock")
+    }
+
+    /// The queue on disk; an error (rather than an empty queue) if it can't be read,
+    /// so a save never replaces writes that are still queued.
+    fn read(dir: Option<&Path>) -> MiResult<Self> {
+        match Self::path_in(dir) {
+            Some(path) => Ok(json_file::load(&path)?.unwrap_or_default()),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn load(dir: Option<&Path>) -> Self {
+        Self::lock(dir).and_then(|_lock| Self::read(dir)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Self::default()
+        })
+    }
+
+    fn save(&self, dir: Option<&Path>) -> MiResult<()> {
+        if let Some(path) = Self::path_in(dir) {
+            json_file::save(&path, self)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` on the outbox under the file lock and saves the result.
+    fn modify<T>(dir: Option<&Path>, f: impl FnOnce(&mut Outbox) -> T) -> MiResult<T> {
+        let _lock = Self::lock(dir)?;
+        let mut outbox = Self::read(dir)?;
+        let result = f(&mut outbox);
+        outbox.save(dir)?;
+        Ok(result)
+    }
+
+    /// Queues an operation and returns its local id (which doubles as the
+    /// temporary id of the entity it creates).
+    pub fn enqueue(dir: Option<&Path>, op: PendingOp) -> MiResult<String> {
+        let id = new_local_id();
+        let entry = OutboxEntry {
+            id: id.clone(),
+            queued_at: chrono::Utc::now().timestamp_millis(),
+            attempts: 0,
+            unconfirmed: false,
+            op,
+        };
+        let _pending = Self::modify(dir, |outbox| {
+            outbox.entries.push(entry);
+            outbox.entries.len()
+        })?;
+        crate::dprintln!("[Outbox] Queued {} ({} pending)", id, _pending);
+        Ok(id)
+    }
+
+    pub fn pending(dir: Option<&Path>) -> usize {
+        Self::load(dir).entries.len()
+    }
+
+    /// Notes created offline that have not reached the server yet.
+    pub fn pending_notes(dir: Option<&Path>) -> Vec<Note> {
+        Self::load(dir).entries.iter().filter_map(|e| e.local_note()).collect()
+    }
+
+    /// Puts a conflicted entry back at the end of the queue.
+    pub fn retry_conflict(dir: Option<&Path>, id: &str) -> MiResult<bool> {
+        Self::modify(dir, |outbox| {
+            let Some(pos) = outbox.conflicts.iter().position(|c| c.entry.id == id) else { return false };
+            let mut entry = outbox.conflicts.remove(pos).entry;
+            entry.attempts = 0;
+            outbox.entries.push(entry);
+            true
+        })
+    }
+
+    /// Drops a queued or conflicted entry.
+    pub fn discard(dir: Option<&Path>, id: &str) -> MiResult<bool> {
+        Self::modify(dir, |outbox| {
+            let before = outbox.entries.len() + outbox.conflicts.len();
+            outbox.entries.retain(|e| e.id != id);
+            outbox.conflicts.retain(|c| c.entry.id != id);
+            before != outbox.entries.len() + outbox.conflicts.len()
+        })
+    }
+
+    /// Drops the mappings no queued or set-aside entry refers to any more.
+    fn prune_id_map(&mut self) {
+        let mut referenced = HashSet::new();
+        let ops = self.entries.iter_mut().map(|e| &mut e.op).chain(self.conflicts.iter_mut().map(|c| &mut c.entry.op));
+        for op in ops {
+            referenced.extend(op.ids_mut().into_iter().map(|id| id.clone()));
+        }
+        self.id_map.retain(|local_id, _| referenced.contains(local_id));
+    }
+
+    fn set_aside(&mut self, entry: OutboxEntry, reason: String, remote_tag: Option<String>) {
+        eprintln!("[Outbox] Conflict on {}: {}", entry.id, reason);
+        self.entries.retain(|e| e.id != entry.id);
+        self.conflicts.push(OutboxConflict {
+            entry,
+            reason,
+            remote_tag,
+            detected_at: chrono::Utc::now().timestamp_millis(),
+        });
+    }
+
+    /// Replays queued operations in order. Network and session errors stop the
+    /// replay with the entry still queued; conflicts and repeatedly failing
+    /// entries are set aside so they don't block the rest of the queue. Only one
+    /// process replays at a time; while another one is at it this sends nothing.
+    pub async fn flush(client: &Client) -> MiResult<usize> {
+        let dir = client.data_dir();
+        let Some(_replaying) = FileLock::try_acquire(dir, "outbox.flush.lock")? else {
+            crate::dprintln!("[Outbox] Another process is replaying the queue");
+            return Ok(0);
+        };
+        let mut sent = 0;
+        loop {
+            let next = {
+                let outbox = Self::load(dir);
+                outbox.entries.first().cloned().map(|e| (e, outbox.id_map))
+            };
+            let Some((entry, id_map)) = next else { break };
+
+            // Set-aside entries keep the server ids resolved so far, so a retry still
+            // finds them once their mappings are gone
+            let mut op = entry.op.clone();
+            if let Some(missing) = op.resolve_ids(&id_map) {
+                let entry = OutboxEntry { op, ..entry };
+                Self::modify(dir, |outbox| outbox.set_aside(entry, format!("Depends on {}, which never reached the server", missing), None))?;
+                continue;
+            }
+
+            let applied = if entry.unconfirmed { op.find_applied(client, entry.queued_at).await } else { Ok(None) };
+            let result = match applied {
+                Ok(Some(outcome)) => {
+                    crate::dprintln!("[Outbox] {} already reached the server", entry.id);
+                    Ok(outcome)
+                }
+                Ok(None) => {
+                    if op.is_repeatable() && !entry.unconfirmed {
+                        Self::modify(dir, |outbox| {
+                            if let Some(queued) = outbox.entries.iter_mut().find(|q| q.id == entry.id) {
+                                queued.unconfirmed = true;
+                            }
+                        })?;
+                    }
+                    crate::dprintln!("[Outbox] Sending {}", entry.id);
+                    op.apply(client).await
+                }
+                Err(e) => Err(e),
+            };
+            let outcome = match result {
+                Ok(outcome) => outcome,
+                Err(e) if is_transient(&e) => return Err(e),
+                Err(e) => {
+                    let gave_up = Self::modify(dir, |outbox| {
+                        let Some(queued) = outbox.entries.iter_mut().find(|q| q.id == entry.id) else { return true };
+                        queued.attempts += 1;
+                        if queued.attempts < MAX_ATTEMPTS {
+                            return false;
+                        }
+                        let queued = OutboxEntry { op: op.clone(), ..queued.clone() };
+                        outbox.set_aside(queued, format!("Gave up after {} attempts: {}", MAX_ATTEMPTS, e), None);
+                        true
+                    })?;
+                    if gave_up { continue; }
+                    return Err(e);
+                }
+            };
+
+            Self::modify(dir, |outbox| {
+                match outcome {
+                    Outcome::Done(server_id) => {
+                        outbox.entries.retain(|e| e.id != entry.id);
+                        if let Some(server_id) = server_id {
+                            outbox.id_map.insert(entry.id.clone(), server_id);
+                        }
+                    }
+                    Outcome::Conflict { reason, remote_tag } => outbox.set_aside(OutboxEntry { op, ..entry }, reason, remote_tag),
+                }
+                outbox.prune_id_map();
+            })?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Queues `op` and replays the queue right away, for one-shot callers like the
+    /// CLI. `Ok(false)` if it couldn't be sent yet and stays queued for the sync loop;
+    /// an error if it was set aside as a conflict.
+    pub async fn submit(client: &Client, op: PendingOp) -> MiResult<bool> {
+        let id = Self::enqueue(client.data_dir(), op)?;
+        let flushed = Self::flush(client).await;
+        let outbox = {
+            let _lock = Self::lock(client.data_dir())?;
+            Self::read(client.data_dir())?
+        };
+        if let Some(conflict) = outbox.conflicts.iter().find(|c| c.entry.id == id) {
+            return Err(format!("{} (kept as {}; see `minote outbox list`)", conflict.reason, id).into());
+        }
+        if outbox.entries.iter().any(|e| e.id == id) {
+            if let Err(e) = flushed {
+                eprintln!("Not sent yet: {}", e);
+            }
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_and_rate_limit_errors_keep_entries_queued() {
+        assert!(is_transient(&ApiError::SessionExpired("HTTP 401".to_string()).into()));
+        assert!(is_transient(&ApiError::RateLimited { retry_after: None }.into()));
+        assert!(!is_transient(&ApiError::Api { code: 10017, description: "bad entry".to_string() }.into()));
+        assert!(!is_transient(&ApiError::Http { status: 500, body: String::new() }.into()));
+        // Messages that merely mention a session don't count
+        assert!(!is_transient(&"Authentication error: STS error".into()));
+    }
+
+    #[test]
+    fn only_creates_and_appends_are_checked_before_replaying() {
+        let append = PendingOp::AppendNote { note_id: "1".to_string(), line: "x".to_string() };
+        let delete = PendingOp::DeleteNote { note_id: "1".to_string(), tag: "t".to_string(), purge: false };
+        assert!(append.is_repeatable());
+        assert!(PendingOp::CreateFolder { subject: "Work".to_string() }.is_repeatable());
+        assert!(!delete.is_repeatable());
+    }
+
+    #[test]
+    fn entries_queued_by_older_versions_are_not_unconfirmed() {
+        let json = r#"{"id":"local-1-0","queuedAt":1,"attempts":2,"op":{"op":"createFolder","subject":"Work"}}"#;
+        let entry: OutboxEntry = serde_json::from_str(json).unwrap();
+        assert!(!entry.unconfirmed);
+        assert_eq!(entry.attempts, 2);
+    }
+
+    #[test]
+    fn local_ids_are_swapped_for_server_ids() {
+        let mut op = PendingOp::UpdateNote {
+            note_id: "local-1-0".to_string(),
+            base_tag: String::new(),
+            base_content: None,
+            subject: String::new(),
+            content: String::new(),
+            folder_id: Some("local-1-1".to_string()),
+        };
+        let id_map = HashMap::from([("local-1-0".to_string(), "42".to_string())]);
+        assert_eq!(op.resolve_ids(&id_map), Some("local-1-1".to_string()));
+        let PendingOp::UpdateNote { note_id, .. } = &op else { unreachable!() };
+        assert_eq!(note_id, "42");
+    }
+
+    #[test]
+    fn mappings_are_kept_while_a_set_aside_entry_refers_to_them() {
+        let append = |note_id: &str| PendingOp::AppendNote { note_id: note_id.to_string(), line: "x".to_string() };
+        let entry = |id: &str, op| OutboxEntry { id: id.to_string(), queued_at: 0, attempts: 0, unconfirmed: false, op };
+        let mut outbox = Outbox {
+            entries: vec![entry("local-1-2", append("local-1-0"))],
+            id_map: HashMap::from([
+                ("local-1-0".to_string(), "40".to_string()),
+                ("local-1-1".to_string(), "41".to_string()),
+                ("local-1-9".to_string(), "49".to_string()),
+            ]),
+            conflicts: vec![OutboxConflict {
+                entry: entry("local-1-3", append("local-1-1")),
+                reason: String::new(),
+                remote_tag: None,
+                detected_at: 0,
+            }],
+        };
+        outbox.prune_id_map();
+        let mut kept: Vec<_> = outbox.id_map.keys().cloned().collect();
+        kept.sort();
+        assert_eq!(kept, ["local-1-0", "local-1-1"]);
+    }
+
+    #[test]
+    fn a_held_lock_keeps_other_handles_out() {
+        let dir = std::env::temp_dir().join(format!("minote-outbox-lock-{}", std::process::id()));
+        let held = FileLock::acquire(Some(&dir), "outbox.lock").unwrap();
+        assert!(FileLock::try_acquire(Some(&dir), "outbox.lock").unwrap().is_none());
+        drop(held);
+        assert!(FileLock::try_acquire(Some(&dir), "outbox.lock").unwrap().is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_conflict_on_a_note_created_offline_can_be_retried() {
+        use crate::api::AppConfig;
+        use crate::api::mock_server::{MockServer, Response};
+
+        let server = MockServer::start(|req| match req.path() {
+            "/note/full/post" => Response::ok(r#"{"code":0,"result":"ok","data":{"entry":{"id":"99","tag":"t1"}}}"#),
+            "/note/note/99" => Response::ok(r#"{"code":0,"result":"ok","data":{"entry":{"id":"99","tag":"t2","content":"edited elsewhere"}}}"#),
+            _ => Response::status(404),
+        });
+        let dir = std::env::temp_dir().join(format!("minote-outbox-retry-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = AppConfig { api_base_url: server.url.clone(), cache_dir: Some(dir.to_string_lossy().into_owned()), ..AppConfig::default() };
+        let client = Client::new("userId=1; passToken=p; serviceToken=s", &config).unwrap();
+        let data_dir = client.data_dir();
+
+        let local_id = Outbox::enqueue(data_dir, PendingOp::CreateNote { folder_id: "0".to_string(), subject: String::new(), content: "draft".to_string() }).unwrap();
+        let update = Outbox::enqueue(data_dir, PendingOp::UpdateNote {
+            note_id: local_id,
+            base_tag: "t1".to_string(),
+            base_content: None,
+            subject: String::new(),
+            content: "draft, edited".to_string(),
+            folder_id: None,
+        }).unwrap();
+        assert_eq!(Outbox::flush(&client).await.unwrap(), 2);
+
+        let outbox = Outbox::load(data_dir);
+        assert!(outbox.entries.is_empty());
+        let PendingOp::UpdateNote { note_id, .. } = &outbox.conflicts[0].entry.op else { panic!("not the update") };
+        assert_eq!(note_id, "99");
+
+        // The retry reaches the server note instead of failing on the local id
+        assert!(Outbox::retry_conflict(data_dir, &update).unwrap());
+        Outbox::flush(&client).await.unwrap();
+        let outbox = Outbox::load(data_dir);
+        assert_eq!(outbox.conflicts.len(), 1);
+        assert!(!outbox.conflicts[0].reason.starts_with("Depends on"), "{}", outbox.conflicts[0].reason);
+        assert_eq!(server.hits("/note/note/99").len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
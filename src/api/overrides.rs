@@ -0,0 +1,101 @@
+use once_cell::sync::OnceCell;
+use std::fmt;
+use std::path::PathBuf;
+use crate::api::MiResult;
+use crate::api::profile;
+
+/// Prefix of the environment variables that override settings, e.g. `MINOTE_SYNC_INTERVAL_SECS`.
+const ENV_PREFIX: &str = "MINOTE_";
+
+/// Replaces the startup profile's `config.json`, like `--config`.
+pub const CONFIG_ENV: &str = "MINOTE_CONFIG";
+
+/// Where a setting's effective value came from, lowest precedence first.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    SecretStore,
+    Env(String),
+    Flag,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::SecretStore => f.write_str("secret store"),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Flag => f.write_str("--set"),
+        }
+    }
+}
+
+/// `--config` and `--set key=value` from the command line; set once at startup.
+#[derive(Default)]
+struct Flags {
+    config_path: Option<PathBuf>,
+    set: Vec<(String, String)>,
+}
+
+static FLAGS: OnceCell<Flags> = OnceCell::new();
+
+/// The profile `--config` stands in for: the one active when the overrides were
+/// recorded (or first looked at).
+static CONFIG_PROFILE: OnceCell<String> = OnceCell::new();
+
+/// Records the command-line overrides. Keys are checked against `known`;
+/// values are checked when the settings are loaded.
+pub fn init(config_path: Option<PathBuf>, set: &[String], known: &[String]) -> MiResult<()> {
+    let mut pairs = Vec::new();
+    for item in set {
+        let (key, value) = item.split_once('=').ok_or_else(|| format!("--set expects key=value, got '{}'", item))?;
+        let key = key.trim().to_string();
+        check_key(&key, known)?;
+        pairs.push((key, value.to_string()));
+    }
+    FLAGS.set(Flags { config_path, set: pairs }).map_err(|_| "Overrides were already initialized")?;
+    CONFIG_PROFILE.get_or_init(profile::active);
+    Ok(())
+}
+
+pub fn check_key(key: &str, known: &[String]) -> MiResult<()> {
+    if known.iter().any(|k| k == key) {
+        return Ok(());
+    }
+    Err(format!("Unknown setting '{}'; known settings: {}", key, known.join(", ")).into())
+}
+
+/// The file `--config` or `MINOTE_CONFIG` names instead of `profile`'s `config.json`.
+/// Only the profile in use at startup is replaced; after switching to another
+/// profile its own file is used, so the two never share one.
+pub fn config_path(profile: &str) -> Option<PathBuf> {
+    if CONFIG_PROFILE.get_or_init(profile::active) != profile {
+        return None;
+    }
+    FLAGS.get().and_then(|flags| flags.config_path.clone())
+        .or_else(|| std::env::var_os(CONFIG_ENV).filter(|v| !v.is_empty()).map(PathBuf::from))
+}
+
+pub fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+/// Overrides for the given settings in the order they apply, so later ones win:
+/// environment variables first, then `--set`.
+pub fn layers(known: &[String]) -> Vec<(String, String, Source)> {
+    let mut layers = Vec::new();
+    for key in known {
+        let var = env_var(key);
+        if let Ok(value) = std::env::var(&var) {
+            layers.push((key.clone(), value, Source::Env(var)));
+        }
+    }
+    if let Some(flags) = FLAGS.get() {
+        layers.extend(flags.set.iter()
+            .filter(|(key, _)| known.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone(), Source::Flag)));
+    }
+    layers
+}
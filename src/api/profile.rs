@@ -0,0 +1,160 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use directories::ProjectDirs;
+use crate::api::MiResult;
+
+/// The profile that existed before profiles did; it keeps using the top-level directories.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Which profile the GUI and CLI use unless told otherwise (`--profile`).
+#[derive(Serialize, Deserialize, Default)]
+struct ProfilesFile {
+    #[serde(default)]
+    active: Option<String>,
+}
+
+static ACTIVE: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(read_active()));
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("com", "minote", "MiNoteWebView")
+}
+
+pub fn base_config_dir() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+pub fn base_data_dir() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.data_dir().to_path_buf())
+}
+
+fn get_profiles_path() -> Option<PathBuf> {
+    base_config_dir().map(|dir| dir.join("profiles.json"))
+}
+
+fn read_active() -> String {
+    get_profiles_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str::<ProfilesFile>(&content).ok())
+        .and_then(|file| file.active)
+        .filter(|name| exists(name))
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+/// Config directory of a profile: the top-level one for the default profile,
+/// `profiles/<name>` below it for the others.
+pub fn config_dir(name: &str) -> Option<PathBuf> {
+    let base = base_config_dir()?;
+    Some(if name == DEFAULT_PROFILE { base } else { base.join("profiles").join(name) })
+}
+
+/// Data directory (outbox, history, backups) of a profile, laid out like `config_dir`.
+pub fn data_dir(name: &str) -> Option<PathBuf> {
+    let base = base_data_dir()?;
+    Some(if name == DEFAULT_PROFILE { base } else { base.join("profiles").join(name) })
+}
+
+pub fn active() -> String {
+    ACTIVE.read().unwrap().clone()
+}
+
+/// All profiles, the default one first.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = base_config_dir()
+        .and_then(|dir| fs::read_dir(dir.join("profiles")).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| validate_name(name).is_ok() && name != DEFAULT_PROFILE)
+        .collect();
+    names.sort();
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    names
+}
+
+pub fn exists(name: &str) -> bool {
+    name == DEFAULT_PROFILE || config_dir(name).is_some_and(|dir| dir.is_dir())
+}
+
+/// Profile names become directory names and secret keys, so keep them plain.
+pub fn validate_name(name: &str) -> MiResult<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid profile name '{}': use letters, digits, '-' and '_'", name).into())
+    }
+}
+
+pub fn create(name: &str) -> MiResult<()> {
+    validate_name(name)?;
+    if exists(name) {
+        return Err(format!("Profile '{}' already exists", name).into());
+    }
+    let dir = config_dir(name).ok_or("No config directory available")?;
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+/// Deletes a profile's settings, local state and stored cookie.
+pub fn remove(name: &str) -> MiResult<()> {
+    if name == DEFAULT_PROFILE {
+        return Err("The default profile can't be removed".into());
+    }
+    if !exists(name) {
+        return Err(format!("No profile named '{}'", name).into());
+    }
+    if name == active() {
+        return Err(format!("Profile '{}' is in use; switch to another one first", name).into());
+    }
+
+    let config = crate::api::AppConfig::load_profile(name);
+    if let Some(ref cookie) = config.account_cookie {
+        crate::api::session::Session::clear(cookie);
+    }
+    config.delete_secrets();
+    if let Some(dir) = data_dir(name) {
+        fs::remove_dir_all(dir).ok();
+    }
+    if let Some(dir) = config_dir(name) {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// Makes `name` the active profile for this process and for future launches.
+pub fn switch(name: &str) -> MiResult<()> {
+    use_for_process(name)?;
+    let path = get_profiles_path().ok_or("No config directory available")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = ProfilesFile { active: Some(name.to_string()) };
+    fs::write(path, serde_json::to_string_pretty(&file)?)?;
+    Ok(())
+}
+
+/// Makes `name` the active profile for this process only (`--profile`).
+pub fn use_for_process(name: &str) -> MiResult<()> {
+    validate_name(name)?;
+    if !exists(name) {
+        return Err(format!("No profile named '{}'. Create it with `minote profile add {}`", name, name).into());
+    }
+    *ACTIVE.write().unwrap() = name.to_string();
+    Ok(())
+}
+
+/// Secret store key for a profile; the default profile keeps the keys it always had.
+pub fn secret_key(key: &str, profile: &str) -> String {
+    if profile == DEFAULT_PROFILE {
+        key.to_string()
+    } else {
+        format!("{}@{}", key, profile)
+    }
+}
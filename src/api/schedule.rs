@@ -0,0 +1,333 @@
+use std::future::Future;
+use std::time::Duration;
+use crate::api::{AppConfig, MiError};
+use crate::api::error::ApiError;
+
+/// First retry delay after a failed sync; doubles with every further failure.
+const BACKOFF_BASE: Duration = Duration::from_secs(15);
+
+/// Consecutive syncs without changes before the idle interval applies.
+const IDLE_AFTER_ROUNDS: u32 = 3;
+
+/// Regular intervals vary by this fraction either way so clients don't sync in step.
+const INTERVAL_JITTER: f64 = 0.1;
+
+/// How often a connection to the server is tried while offline.
+const NETWORK_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a sync round amounted to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Changed,
+    Unchanged,
+    Failed {
+        /// The server asked to wait this long (HTTP 429 with `Retry-After`).
+        retry_after: Option<Duration>,
+        /// The server couldn't be reached at all.
+        offline: bool,
+    },
+}
+
+impl Outcome {
+    pub fn failed(e: &MiError) -> Self {
+        let retry_after = match e.downcast_ref::<ApiError>() {
+            Some(ApiError::RateLimited { retry_after }) => Some(retry_after.unwrap_or(BACKOFF_BASE)),
+            _ => None,
+        };
+        let offline = e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect() || e.is_timeout());
+        Outcome::Failed { retry_after, offline }
+    }
+}
+
+/// Why `Scheduler::wait` returned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wake {
+    Timer,
+    /// Something asked for a sync now, e.g. a note was queued.
+    Notified,
+    /// The server could be reached again after being offline.
+    NetworkUp,
+    SettingsChanged,
+}
+
+/// Decides when the background loop syncs next: the configured interval while
+/// notes change, the idle interval once they stop, and exponential backoff with
+/// jitter after failures, never sooner than the server's `Retry-After`.
+pub struct Scheduler {
+    failures: u32,
+    quiet_rounds: u32,
+    retry_after: Option<Duration>,
+    offline: bool,
+    /// From 0 to 1; picks the delay between the bounds.
+    random: fn() -> f64,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::with_random(random_fraction)
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A scheduler drawing its jitter from `random`, which returns values from 0 to 1.
+    pub fn with_random(random: fn() -> f64) -> Self {
+        Self { failures: 0, quiet_rounds: 0, retry_after: None, offline: false, random }
+    }
+
+    pub fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Changed => {
+                *self = Self::with_random(self.random);
+            }
+            Outcome::Unchanged => {
+                *self = Self { quiet_rounds: self.quiet_rounds.saturating_add(1), ..Self::with_random(self.random) };
+            }
+            Outcome::Failed { retry_after, offline } => {
+                self.failures = self.failures.saturating_add(1);
+                self.retry_after = retry_after;
+                self.offline = offline;
+            }
+        }
+    }
+
+    /// Shortest and longest wait before the next sync; `next_delay` picks one in between.
+    pub fn delay_bounds(&self, config: &AppConfig) -> (Duration, Duration) {
+        if self.failures > 0 {
+            // "Equal jitter": anywhere between half and all of the exponential delay
+            let exp = backoff_ceiling(BACKOFF_BASE, self.failures, Duration::from_secs(config.max_backoff_secs));
+            let retry_after = self.retry_after.unwrap_or_default();
+            return ((exp / 2).max(retry_after), exp.max(retry_after));
+        }
+        let mut interval = Duration::from_secs(config.sync_interval_secs);
+        if self.is_idle() {
+            interval = interval.max(Duration::from_secs(config.idle_sync_interval_secs));
+        }
+        (interval.mul_f64(1.0 - INTERVAL_JITTER), interval.mul_f64(1.0 + INTERVAL_JITTER))
+    }
+
+    pub fn next_delay(&self, config: &AppConfig) -> Duration {
+        let (shortest, longest) = self.delay_bounds(config);
+        shortest + (longest - shortest).mul_f64((self.random)())
+    }
+
+    /// Sleeps `delay`, returning early when `notified` (unless rate limited), when
+    /// `network_up` resolves (only while offline) or when `settings_changed` does.
+    pub async fn wait(
+        &self,
+        delay: Duration,
+        notified: impl Future<Output = ()>,
+        network_up: impl Future<Output = ()>,
+        settings_changed: impl Future,
+    ) -> Wake {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => Wake::Timer,
+            // While rate limited, user actions wait for the server's Retry-After too
+            _ = notified, if !self.is_rate_limited() => Wake::Notified,
+            _ = network_up, if self.is_offline() => Wake::NetworkUp,
+            _ = settings_changed => Wake::SettingsChanged,
+        }
+    }
+
+    /// The last sync couldn't reach the server; worth syncing as soon as it can.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The server asked to wait, so early wake-ups should be ignored.
+    pub fn is_rate_limited(&self) -> bool {
+        self.retry_after.is_some()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.quiet_rounds >= IDLE_AFTER_ROUNDS
+    }
+}
+
+/// `base` doubled for every attempt after the first, capped at `max`.
+fn backoff_ceiling(base: Duration, attempt: u32, max: Duration) -> Duration {
+    base.saturating_mul(1u32 << attempt.saturating_sub(1).min(20)).min(max)
+}
+
+/// `backoff_ceiling` with "equal jitter": the delay is anywhere between half and all of it.
+pub fn backoff(base: Duration, attempt: u32, max: Duration) -> Duration {
+    let exp = backoff_ceiling(base, attempt, max);
+    exp / 2 + (exp / 2).mul_f64(random_fraction())
+}
+
+/// Uniform in [0, 1). `RandomState` is seeded randomly per instance, which is
+/// plenty for spreading out retries.
+fn random_fraction() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = at.signed_duration_since(chrono::Utc::now());
+    Some(wait.to_std().unwrap_or_default())
+}
+
+/// Resolves once `url` (the server, or the proxy in front of it) accepts connections again.
+pub async fn network_up(url: &str) {
+    let Some(address) = host_and_port(url) else {
+        return std::future::pending().await;
+    };
+    loop {
+        tokio::time::sleep(NETWORK_PROBE_INTERVAL).await;
+        let connect = tokio::net::TcpStream::connect(address.as_str());
+        if let Ok(Ok(_)) = tokio::time::timeout(NETWORK_PROBE_INTERVAL, connect).await {
+            return;
+        }
+    }
+}
+
+fn host_and_port(url: &str) -> Option<String> {
+    // `HTTPS_PROXY` is often given without a scheme
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    // Proxy URLs may carry credentials
+    let host = rest.split('/').next()?.rsplit('@').next().filter(|h| !h.is_empty())?;
+    if host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+        return Some(host.to_string());
+    }
+    let port = match scheme {
+        "http" => 80,
+        "socks5" | "socks5h" => 1080,
+        _ => 443,
+    };
+    Some(format!("{}:{}", host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    fn config() -> AppConfig {
+        AppConfig { sync_interval_secs: 60, idle_sync_interval_secs: 600, max_backoff_secs: 120, ..AppConfig::default() }
+    }
+
+    fn failed() -> Outcome {
+        Outcome::Failed { retry_after: None, offline: false }
+    }
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    /// Sleeps the scheduler's next delay and returns how long that took.
+    async fn waited(scheduler: &Scheduler) -> Duration {
+        let start = Instant::now();
+        let wake = scheduler.wait(scheduler.next_delay(&config()), std::future::pending(), std::future::pending(), std::future::pending::<()>()).await;
+        assert_eq!(wake, Wake::Timer);
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_doubles_up_to_the_cap() {
+        let mut shortest = Scheduler::with_random(|| 0.0);
+        let mut longest = Scheduler::with_random(|| 1.0);
+        let expected = [(7.5, 15.0), (15.0, 30.0), (30.0, 60.0), (60.0, 120.0), (60.0, 120.0), (60.0, 120.0)];
+        for (low, high) in expected {
+            shortest.record(failed());
+            longest.record(failed());
+            assert_eq!(shortest.delay_bounds(&config()), (secs(low), secs(high)));
+            assert_eq!(waited(&shortest).await, secs(low));
+            assert_eq!(waited(&longest).await, secs(high));
+        }
+
+        // One success is enough to go back to the regular interval
+        shortest.record(Outcome::Changed);
+        assert_eq!(shortest.delay_bounds(&config()), (secs(54.0), secs(66.0)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_interval_applies_after_quiet_rounds() {
+        let mut scheduler = Scheduler::with_random(|| 0.5);
+        for _ in 1..IDLE_AFTER_ROUNDS {
+            scheduler.record(Outcome::Unchanged);
+            assert_eq!(waited(&scheduler).await, secs(60.0));
+        }
+        scheduler.record(Outcome::Unchanged);
+        assert!(scheduler.is_idle());
+        assert_eq!(scheduler.delay_bounds(&config()), (secs(540.0), secs(660.0)));
+        assert_eq!(waited(&scheduler).await, secs(600.0));
+
+        // A failure keeps the quiet streak; a change ends it
+        scheduler.record(failed());
+        scheduler.record(Outcome::Changed);
+        assert!(!scheduler.is_idle());
+        assert_eq!(waited(&scheduler).await, secs(60.0));
+    }
+
+    #[test]
+    fn retry_after_is_seconds_or_an_http_date() {
+        assert_eq!(parse_retry_after("120"), Some(secs(120.0)));
+        assert_eq!(parse_retry_after(" 5 "), Some(secs(5.0)));
+
+        let at = chrono::Utc::now() + chrono::Duration::seconds(300);
+        let wait = parse_retry_after(&at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).unwrap();
+        assert!(wait > secs(295.0) && wait <= secs(300.0), "{:?}", wait);
+        // A date in the past means now
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_outlasts_the_backoff() {
+        let error: MiError = Box::new(ApiError::RateLimited { retry_after: Some(secs(300.0)) });
+        let outcome = Outcome::failed(&error);
+        assert_eq!(outcome, Outcome::Failed { retry_after: Some(secs(300.0)), offline: false });
+
+        let mut scheduler = Scheduler::with_random(|| 0.0);
+        scheduler.record(outcome);
+        assert!(scheduler.is_rate_limited());
+        assert_eq!(scheduler.delay_bounds(&config()), (secs(300.0), secs(300.0)));
+        assert_eq!(waited(&scheduler).await, secs(300.0));
+
+        // Without a Retry-After header the first backoff step is used
+        let error: MiError = Box::new(ApiError::RateLimited { retry_after: None });
+        assert_eq!(Outcome::failed(&error), Outcome::Failed { retry_after: Some(BACKOFF_BASE), offline: false });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wake_ups_are_ignored_while_rate_limited() {
+        let delay = secs(30.0);
+        let mut scheduler = Scheduler::new();
+        let start = Instant::now();
+        let wake = scheduler.wait(delay, std::future::ready(()), std::future::pending(), std::future::pending::<()>()).await;
+        assert_eq!((wake, start.elapsed()), (Wake::Notified, Duration::ZERO));
+
+        scheduler.record(Outcome::Failed { retry_after: Some(delay), offline: false });
+        let start = Instant::now();
+        let wake = scheduler.wait(delay, std::future::ready(()), std::future::pending(), std::future::pending::<()>()).await;
+        assert_eq!((wake, start.elapsed()), (Wake::Timer, delay));
+
+        // Settings changes still get through
+        let wake = scheduler.wait(delay, std::future::ready(()), std::future::pending(), std::future::ready(())).await;
+        assert_eq!(wake, Wake::SettingsChanged);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnecting_only_wakes_an_offline_scheduler() {
+        let delay = secs(30.0);
+        let mut scheduler = Scheduler::new();
+        let start = Instant::now();
+        let wake = scheduler.wait(delay, std::future::pending(), std::future::ready(()), std::future::pending::<()>()).await;
+        assert_eq!((wake, start.elapsed()), (Wake::Timer, delay));
+
+        scheduler.record(Outcome::Failed { retry_after: None, offline: true });
+        let wake = scheduler.wait(delay, std::future::pending(), std::future::ready(()), std::future::pending::<()>()).await;
+        assert_eq!(wake, Wake::NetworkUp);
+    }
+}
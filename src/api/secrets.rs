@@ -0,0 +1,460 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as B64;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use crate::api::{json_file, MiResult};
+use crate::api::profile;
+
+/// Set to encrypt the secrets file with a passphrase instead of a machine-bound key.
+pub const PASSPHRASE_ENV: &str = "MINOTE_PASSPHRASE";
+const PBKDF2_ROUNDS: u32 = 200_000;
+/// Cookie names whose values never appear in logs.
+const SENSITIVE: &[&str] = &["serviceToken", "yetAnotherServiceToken", "passToken", "i.mi.com_ph", "i.mi.com_slh", "ssecurity"];
+const REDACTED: &str = "<redacted>";
+
+/// Where secrets (the account cookie, refreshed sessions) are kept.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum SecretBackend {
+    /// OS keyring when it accepts the value, the encrypted file otherwise.
+    #[default]
+    Auto,
+    Keyring,
+    EncryptedFile,
+}
+
+pub trait SecretStore {
+    fn get(&self, key: &str) -> MiResult<Option<String>>;
+    fn set(&self, key: &str, value: &str) -> MiResult<()>;
+    fn delete(&self, key: &str) -> MiResult<()>;
+}
+
+pub fn open(backend: SecretBackend) -> Box<dyn SecretStore> {
+    match backend {
+        SecretBackend::Auto => Box::new(AutoStore { keyring: KeyringStore, file: EncryptedFileStore::default() }),
+        SecretBackend::Keyring => Box::new(KeyringStore),
+        SecretBackend::EncryptedFile => Box::new(EncryptedFileStore::default()),
+    }
+}
+
+/// `keyring` for every value it accepts, `file` for the rest.
+struct AutoStore<K, F> {
+    keyring: K,
+    file: F,
+}
+
+impl<K: SecretStore, F: SecretStore> SecretStore for AutoStore<K, F> {
+    fn get(&self, key: &str) -> MiResult<Option<String>> {
+        match self.keyring.get(key) {
+            Ok(Some(value)) => Ok(Some(value)),
+            _ => self.file.get(key),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> MiResult<()> {
+        // Credential stores cap value sizes, and long cookies can exceed them
+        match self.keyring.set(key, value) {
+            Ok(()) => self.file.delete(key),
+            Err(_e) => {
+                crate::dprintln!("Keyring unavailable ({}), using the encrypted file", _e);
+                self.file.set(key, value)?;
+                self.keyring.delete(key).ok();
+                Ok(())
+            }
+        }
+    }
+
+    fn delete(&self, key: &str) -> MiResult<()> {
+        self.keyring.delete(key).ok();
+        self.file.delete(key)
+    }
+}
+
+/// Windows Credential Manager via the `keyring` crate.
+struct KeyringStore;
+
+#[cfg(windows)]
+impl KeyringStore {
+    const SERVICE: &'static str = "MiNoteWebView";
+}
+
+#[cfg(windows)]
+impl SecretStore for KeyringStore {
+    fn get(&self, key: &str) -> MiResult<Option<String>> {
+        match keyring::Entry::new(Self::SERVICE, key)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> MiResult<()> {
+        keyring::Entry::new(Self::SERVICE, key)?.set_password(value)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> MiResult<()> {
+        match keyring::Entry::new(Self::SERVICE, key)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl SecretStore for KeyringStore {
+    fn get(&self, _key: &str) -> MiResult<Option<String>> {
+        Err("The OS keyring is not supported on this platform".into())
+    }
+
+    fn set(&self, _key: &str, _value: &str) -> MiResult<()> {
+        Err("The OS keyring is not supported on this platform".into())
+    }
+
+    fn delete(&self, _key: &str) -> MiResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum KeySource {
+    /// PBKDF2 over `MINOTE_PASSPHRASE`.
+    Passphrase,
+    /// A random key wrapped with DPAPI for the current Windows user (elsewhere:
+    /// derived from the machine id, which only obfuscates).
+    Machine,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecretFile {
+    key_source: KeySource,
+    salt: String,
+    #[serde(default)]
+    wrapped_key: Option<String>,
+    #[serde(default)]
+    entries: BTreeMap<String, Sealed>,
+}
+
+type CachedKey = (String, [u8; 32]);
+/// Derived keys are cached per salt; PBKDF2 is deliberately slow and the config is loaded often.
+static KEY_CACHE: Lazy<Mutex<Option<CachedKey>>> = Lazy::new(|| Mutex::new(None));
+static FILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// AES-256-GCM sealed values in a JSON file. By default that is `secrets.json` under
+/// the top-level data directory, shared by all profiles (their keys are namespaced by
+/// `profile::secret_key`).
+struct EncryptedFileStore {
+    path: Option<PathBuf>,
+}
+
+impl Default for EncryptedFileStore {
+    fn default() -> Self {
+        Self { path: profile::base_data_dir().map(|dir| dir.join("secrets.json")) }
+    }
+}
+
+impl EncryptedFileStore {
+    fn read(&self) -> MiResult<Option<SecretFile>> {
+        let Some(ref path) = self.path else { return Ok(None) };
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, file: &SecretFile) -> MiResult<()> {
+        let path = self.path.as_ref().ok_or("No data directory available for secrets")?;
+        json_file::save(path, file)
+    }
+
+    fn create() -> MiResult<SecretFile> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let key_source = if std::env::var(PASSPHRASE_ENV).is_ok() { KeySource::Passphrase } else { KeySource::Machine };
+
+        let wrapped_key = if key_source == KeySource::Machine && cfg!(windows) {
+            let mut key = [0u8; 32];
+            OsRng.fill_bytes(&mut key);
+            Some(B64.encode(dpapi::protect(&key)?))
+        } else {
+            None
+        };
+        Ok(SecretFile { key_source, salt: B64.encode(salt), wrapped_key, entries: BTreeMap::new() })
+    }
+
+    fn key(file: &SecretFile) -> MiResult<[u8; 32]> {
+        let mut cache = KEY_CACHE.lock().unwrap();
+        if let Some((_, key)) = cache.as_ref().filter(|(salt, _)| *salt == file.salt) {
+            return Ok(*key);
+        }
+
+        let salt = B64.decode(&file.salt)?;
+        let mut key = [0u8; 32];
+        match (file.key_source, &file.wrapped_key) {
+            (KeySource::Passphrase, _) => {
+                let passphrase = std::env::var(PASSPHRASE_ENV)
+                    .map_err(|_| format!("Secrets are passphrase-protected; set {}", PASSPHRASE_ENV))?;
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), &salt, PBKDF2_ROUNDS, &mut key);
+            }
+            (KeySource::Machine, Some(wrapped)) => {
+                let unwrapped = dpapi::unprotect(&B64.decode(wrapped)?)?;
+                key.copy_from_slice(unwrapped.get(..32).ok_or("Wrapped key is too short")?);
+            }
+            (KeySource::Machine, None) => {
+                let machine = fs::read_to_string("/etc/machine-id")
+                    .or_else(|_| std::env::var("HOSTNAME"))
+                    .unwrap_or_default();
+                let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default();
+                let material = format!("minote|{}|{}", machine.trim(), user);
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(material.as_bytes(), &salt, PBKDF2_ROUNDS, &mut key);
+            }
+        }
+        *cache = Some((file.salt.clone(), key));
+        Ok(key)
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, key: &str) -> MiResult<Option<String>> {
+        let _guard = FILE_LOCK.lock().unwrap();
+        let Some(file) = self.read()? else { return Ok(None) };
+        let Some(sealed) = file.entries.get(key) else { return Ok(None) };
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&Self::key(&file)?));
+        let nonce = B64.decode(&sealed.nonce)?;
+        if nonce.len() != 12 {
+            return Err("Corrupt secrets file (bad nonce)".into());
+        }
+        let plain = cipher.decrypt(Nonce::from_slice(&nonce), B64.decode(&sealed.data)?.as_slice())
+            .map_err(|_| "Could not decrypt secrets (wrong passphrase or a different machine?)")?;
+        Ok(Some(String::from_utf8(plain)?))
+    }
+
+    fn set(&self, key: &str, value: &str) -> MiResult<()> {
+        let _guard = FILE_LOCK.lock().unwrap();
+        let mut file = match self.read()? {
+            Some(file) => file,
+            None => Self::create()?,
+        };
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&Self::key(&file)?));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let data = cipher.encrypt(&nonce, value.as_bytes()).map_err(|_| "Encryption failed")?;
+        file.entries.insert(key.to_string(), Sealed { nonce: B64.encode(nonce), data: B64.encode(data) });
+        self.write(&file)
+    }
+
+    fn delete(&self, key: &str) -> MiResult<()> {
+        let _guard = FILE_LOCK.lock().unwrap();
+        let Some(mut file) = self.read()? else { return Ok(()) };
+        if file.entries.remove(key).is_some() {
+            self.write(&file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod dpapi {
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Cryptography::{CryptProtectData, CryptUnprotectData, CRYPT_INTEGER_BLOB};
+    use crate::api::MiResult;
+
+    fn call(data: &[u8], protect: bool) -> MiResult<Vec<u8>> {
+        let input = CRYPT_INTEGER_BLOB { cbData: data.len() as u32, pbData: data.as_ptr() as *mut u8 };
+        let mut output = CRYPT_INTEGER_BLOB { cbData: 0, pbData: std::ptr::null_mut() };
+        unsafe {
+            let ok = if protect {
+                CryptProtectData(&input, std::ptr::null(), std::ptr::null(), std::ptr::null(), std::ptr::null(), 0, &mut output)
+            } else {
+                CryptUnprotectData(&input, std::ptr::null_mut(), std::ptr::null(), std::ptr::null(), std::ptr::null(), 0, &mut output)
+            };
+            if ok == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let bytes = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+            LocalFree(output.pbData as _);
+            Ok(bytes)
+        }
+    }
+
+    pub fn protect(data: &[u8]) -> MiResult<Vec<u8>> {
+        call(data, true)
+    }
+
+    pub fn unprotect(data: &[u8]) -> MiResult<Vec<u8>> {
+        call(data, false)
+    }
+}
+
+#[cfg(not(windows))]
+mod dpapi {
+    use crate::api::MiResult;
+
+    pub fn protect(_data: &[u8]) -> MiResult<Vec<u8>> {
+        Err("DPAPI is only available on Windows".into())
+    }
+
+    pub fn unprotect(_data: &[u8]) -> MiResult<Vec<u8>> {
+        Err("DPAPI is only available on Windows".into())
+    }
+}
+
+/// Masks the values of session cookies (`name=value` or `"name":"value"`) in text
+/// headed for logs.
+pub fn redact(text: &str) -> String {
+    SENSITIVE.iter().fold(text.to_string(), |acc, key| redact_key(&acc, key))
+}
+
+fn redact_key(text: &str, key: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(key) {
+        let (before, after) = rest.split_at(pos + key.len());
+        out.push_str(before);
+
+        let sep = ["=", "\":\"", "\": \""].into_iter().find(|s| after.starts_with(s));
+        let Some(sep) = sep else {
+            rest = after;
+            continue;
+        };
+        out.push_str(sep);
+        let mut value = &after[sep.len()..];
+        if let Some(unquoted) = value.strip_prefix('"') {
+            out.push('"');
+            value = unquoted;
+        }
+        let end = value.find([';', '&', '"', ' ', ',', '\n', '\r']).unwrap_or(value.len());
+        if end > 0 {
+            out.push_str(REDACTED);
+        }
+        rest = &value[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    fn scratch_store(name: &str) -> EncryptedFileStore {
+        let dir = std::env::temp_dir().join(format!("minote-secrets-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        EncryptedFileStore { path: Some(dir.join("secrets.json")) }
+    }
+
+    /// A keyring that refuses values longer than `cap`, like Credential Manager does.
+    struct CappedKeyring {
+        cap: usize,
+        values: RefCell<HashMap<String, String>>,
+    }
+
+    impl SecretStore for CappedKeyring {
+        fn get(&self, key: &str) -> MiResult<Option<String>> {
+            Ok(self.values.borrow().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, value: &str) -> MiResult<()> {
+            if value.len() > self.cap {
+                return Err("value too large".into());
+            }
+            self.values.borrow_mut().insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> MiResult<()> {
+            self.values.borrow_mut().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn redact_masks_session_cookies_only() {
+        assert_eq!(redact("serviceToken=abc; userId=42; passToken=xyz"), "serviceToken=<redacted>; userId=42; passToken=<redacted>");
+        assert_eq!(redact(r#"{"ssecurity":"s3cr3t","userId":"42"}"#), r#"{"ssecurity":"<redacted>","userId":"42"}"#);
+        assert_eq!(redact(r#""i.mi.com_ph": "ph", "code": 0"#), r#""i.mi.com_ph": "<redacted>", "code": 0"#);
+        assert_eq!(redact("GET /note/full/page/?limit=200&ts=1"), "GET /note/full/page/?limit=200&ts=1");
+    }
+
+    #[test]
+    fn redact_key_leaves_bare_names_and_empty_values() {
+        assert_eq!(redact_key("serviceToken expired", "serviceToken"), "serviceToken expired");
+        assert_eq!(redact_key("serviceToken=; Max-Age=0", "serviceToken"), "serviceToken=; Max-Age=0");
+        assert_eq!(redact_key("a=serviceToken=t&b=1", "serviceToken"), "a=serviceToken=<redacted>&b=1");
+        assert_eq!(redact_key("passToken=p", "serviceToken"), "passToken=p");
+    }
+
+    #[test]
+    fn encrypted_file_round_trips_and_deletes() {
+        let store = scratch_store("round-trip");
+        assert_eq!(store.get("cookie").unwrap(), None);
+
+        store.set("cookie", "serviceToken=abc").unwrap();
+        store.set("other", "x").unwrap();
+        assert_eq!(store.get("cookie").unwrap().as_deref(), Some("serviceToken=abc"));
+        let path = store.path.clone().unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("serviceToken=abc"));
+        assert!(!path.with_extension("json.tmp").exists());
+
+        store.delete("cookie").unwrap();
+        assert_eq!(store.get("cookie").unwrap(), None);
+        assert_eq!(store.get("other").unwrap().as_deref(), Some("x"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn wrong_key_fails_to_decrypt() {
+        let store = scratch_store("wrong-key");
+        store.set("cookie", "serviceToken=abc").unwrap();
+
+        // A different salt derives a different key for the same sealed entries
+        let mut file = store.read().unwrap().unwrap();
+        file.salt = B64.encode([7u8; 16]);
+        store.write(&file).unwrap();
+
+        let err = store.get("cookie").unwrap_err();
+        assert!(err.to_string().contains("Could not decrypt"), "{}", err);
+        fs::remove_dir_all(store.path.unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn auto_store_falls_back_to_the_file_for_values_the_keyring_refuses() {
+        let store = AutoStore {
+            keyring: CappedKeyring { cap: 8, values: RefCell::default() },
+            file: scratch_store("auto"),
+        };
+
+        store.set("cookie", "serviceToken=a-long-cookie").unwrap();
+        assert_eq!(store.keyring.get("cookie").unwrap(), None);
+        assert_eq!(store.file.get("cookie").unwrap().as_deref(), Some("serviceToken=a-long-cookie"));
+        assert_eq!(store.get("cookie").unwrap().as_deref(), Some("serviceToken=a-long-cookie"));
+
+        // Once the keyring takes the value, the file copy goes
+        store.set("cookie", "short").unwrap();
+        assert_eq!(store.keyring.get("cookie").unwrap().as_deref(), Some("short"));
+        assert_eq!(store.file.get("cookie").unwrap(), None);
+        assert_eq!(store.get("cookie").unwrap().as_deref(), Some("short"));
+
+        store.delete("cookie").unwrap();
+        assert_eq!(store.get("cookie").unwrap(), None);
+        fs::remove_dir_all(store.file.path.unwrap().parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use crate::api::{AppConfig, MiResult};
+use crate::api::cookie::value as cookie_value;
+use crate::api::secrets::redact;
+
+/// Sessions are stored per account, so profiles signed in to the same account share one.
+const SESSION_KEY_PREFIX: &str = "session:";
+/// Single slot used before sessions were keyed by account.
+const LEGACY_SESSION_KEY: &str = "session";
+
+/// Lifetime assumed when the STS response doesn't say when `serviceToken` expires.
+const DEFAULT_LIFETIME_MS: i64 = 12 * 60 * 60 * 1000;
+/// Refresh this long before the recorded expiry.
+const REFRESH_MARGIN_MS: i64 = 30 * 60 * 1000;
+
+/// A micloud cookie obtained through the STS exchange, persisted (in the secret
+/// store) so the GUI and CLI reuse it instead of re-exchanging the passToken each time.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    /// Identifies the account cookie the session was derived from.
+    pub account: String,
+    pub cookie: String,
+    pub obtained_at: i64,
+    pub expires_at: i64,
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("account", &self.account)
+            .field("cookie", &redact(&self.cookie))
+            .field("obtained_at", &self.obtained_at)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl Session {
+    pub fn new(account_cookie: &str, cookie: String, expires_at: Option<i64>) -> Self {
+        let now = now_ms();
+        Self {
+            account: Self::account_key(account_cookie),
+            cookie,
+            obtained_at: now,
+            expires_at: expires_at.unwrap_or(now + DEFAULT_LIFETIME_MS),
+        }
+    }
+
+    /// Stable key for an account cookie: the user id plus a SHA-256 prefix of the
+    /// passToken, so logging in as someone else (or again) invalidates the stored
+    /// session. Unlike `DefaultHasher` it stays the same across Rust releases.
+    pub fn account_key(account_cookie: &str) -> String {
+        let digest = Sha256::digest(cookie_value(account_cookie, "passToken").unwrap_or(account_cookie).as_bytes());
+        let hash: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}:{}", cookie_value(account_cookie, "userId").unwrap_or(""), hash)
+    }
+
+    fn store_key(account: &str) -> String {
+        format!("{}{}", SESSION_KEY_PREFIX, account)
+    }
+
+    /// The stored session for this account, if it hasn't expired yet.
+    pub fn load_for(account_cookie: &str) -> Option<Self> {
+        let content = AppConfig::secret_store().get(&Self::store_key(&Self::account_key(account_cookie))).ok()??;
+        let session: Self = serde_json::from_str(&content).ok()?;
+        (session.account == Self::account_key(account_cookie) && !session.is_expired()).then_some(session)
+    }
+
+    /// Stores the session; `data_dir` is the data directory of the profile it was
+    /// refreshed for, where earlier versions may have left a plaintext copy.
+    pub fn save(&self, data_dir: Option<&Path>) -> MiResult<()> {
+        let store = AppConfig::secret_store();
+        store.set(&Self::store_key(&self.account), &serde_json::to_string(self)?)?;
+        store.delete(LEGACY_SESSION_KEY).ok();
+        // Earlier versions kept the session in plaintext next to the outbox
+        if let Some(dir) = data_dir {
+            std::fs::remove_file(dir.join("session.json")).ok();
+        }
+        Ok(())
+    }
+
+    /// Drops the stored session of an account cookie.
+    pub fn clear(account_cookie: &str) {
+        AppConfig::secret_store().delete(&Self::store_key(&Self::account_key(account_cookie))).ok();
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now_ms() >= self.expires_at
+    }
+
+    pub fn needs_refresh(&self) -> bool {
+        now_ms() >= self.expires_at - REFRESH_MARGIN_MS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_key_is_the_user_id_and_a_fixed_passtoken_digest() {
+        let key = Session::account_key("userId=123; passToken=abc");
+        // SHA-256("abc"), first 16 bytes
+        assert_eq!(key, "123:ba7816bf8f01cfea414140de5dae2223");
+        assert_eq!(Session::account_key("passToken=abc; userId=123; serviceToken=x"), key);
+    }
+
+    #[test]
+    fn account_key_changes_with_the_passtoken() {
+        assert_ne!(Session::account_key("userId=123; passToken=abc"), Session::account_key("userId=123; passToken=abd"));
+        // Without a passToken the whole cookie is hashed
+        assert_ne!(Session::account_key("userId=123; a=1"), Session::account_key("userId=123; a=2"));
+    }
+}
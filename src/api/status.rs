@@ -0,0 +1,72 @@
+use once_cell::sync::Lazy;
+use std::fmt;
+use tokio::sync::watch;
+use crate::api::MiError;
+
+/// Whether the signed-in account's session still works.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// Requests are accepted (or nothing has said otherwise yet).
+    Valid,
+    /// The passToken is being exchanged for a new `serviceToken`.
+    Refreshing,
+    /// The passToken was rejected; only signing in again helps.
+    Expired,
+    /// No answer came back from the server (or the proxy) to tell.
+    Offline,
+}
+
+impl SessionStatus {
+    /// Status after a session refresh or request failed with `e`: without a
+    /// response from the server nothing is known about the session.
+    pub fn after_failure(e: &MiError) -> Self {
+        if e.downcast_ref::<reqwest::Error>().is_some() {
+            SessionStatus::Offline
+        } else {
+            SessionStatus::Expired
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SessionStatus::Valid => "signed in",
+            SessionStatus::Refreshing => "refreshing the session",
+            SessionStatus::Expired => "session expired, sign in again",
+            SessionStatus::Offline => "offline",
+        }
+    }
+}
+
+impl fmt::Display for SessionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionStatus::Valid => "valid",
+            SessionStatus::Refreshing => "refreshing",
+            SessionStatus::Expired => "expired",
+            SessionStatus::Offline => "offline",
+        })
+    }
+}
+
+/// The active profile's status as the sync loop last saw it; `None` while
+/// nobody is signed in.
+static ACTIVE: Lazy<watch::Sender<Option<SessionStatus>>> = Lazy::new(|| watch::channel(None).0);
+
+pub fn current() -> Option<SessionStatus> {
+    *ACTIVE.borrow()
+}
+
+/// A receiver that is marked changed whenever the active profile's status changes.
+pub fn subscribe() -> watch::Receiver<Option<SessionStatus>> {
+    ACTIVE.subscribe()
+}
+
+pub fn publish(status: Option<SessionStatus>) {
+    ACTIVE.send_if_modified(|current| {
+        if *current == status {
+            return false;
+        }
+        *current = status;
+        true
+    });
+}
@@ -0,0 +1,772 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use crate::api::{AppConfig, Client, MiResult};
+use crate::api::backup;
+use crate::api::error::ApiError;
+use crate::api::login::{self, Challenge, Login, LoginEndpoints};
+use crate::api::session::Session;
+use crate::api::status::SessionStatus;
+use crate::api::import::{self, ImportedSession};
+use crate::api::history::{NoteHistory, NoteVersion};
+use crate::api::markup;
+use crate::api::merge::{self, DiffLine};
+use crate::api::models::Note;
+use crate::api::outbox::{Outbox, PendingOp};
+use crate::api::profile;
+
+#[derive(Parser)]
+#[command(name = "minote", version, about = "Xiaomi Cloud Notes desktop client. Run without a command to start the GUI.")]
+pub struct Cli {
+    /// Profile (account) to use instead of the active one [env: MINOTE_PROFILE]
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Settings file to use instead of the profile's config.json [env: MINOTE_CONFIG]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Override a setting for this run only, e.g. --set sync_interval_secs=300 (repeatable)
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Append a timestamped line to a note
+    Append {
+        /// Note id, or a search query matched against note titles and contents
+        target: String,
+        /// Text to append
+        #[arg(required = true, num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// List, restore and purge notes in the recycle bin
+    Trash {
+        #[command(subcommand)]
+        action: Option<TrashAction>,
+    },
+    /// Inspect and replay writes queued while offline
+    Outbox {
+        #[command(subcommand)]
+        action: Option<OutboxAction>,
+    },
+    /// Browse, compare and restore locally recorded versions of a note
+    History {
+        /// Note id, or a search query matched against note titles and contents
+        target: String,
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+    /// Write a compressed backup archive now, or list existing ones
+    Backup {
+        #[command(subcommand)]
+        action: Option<BackupAction>,
+    },
+    /// Recreate folders and notes from a backup archive in the signed-in account, skipping ones that
+    /// already exist. Attachments are not uploaded again
+    Restore {
+        /// Archive path, or a file name in the backup directory
+        archive: PathBuf,
+    },
+    /// Search note titles and contents
+    Search {
+        query: String,
+        /// Search every profile that is signed in, labelling results by profile
+        #[arg(long)]
+        all_profiles: bool,
+    },
+    /// List, create, remove and switch profiles (one per Xiaomi account)
+    Profile {
+        #[command(subcommand)]
+        action: Option<ProfileAction>,
+    },
+    /// Show, read and change settings
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Check that the stored session still works and show when it expires
+    Status,
+    /// Sign in without the GUI (QR code by default) and store the session
+    Login {
+        /// Account (e-mail, phone or Xiaomi ID) for password login; the password is prompted for
+        #[arg(long, conflicts_with = "import")]
+        user: Option<String>,
+        /// Import the session from a browser "Copy as cURL" command ("-" reads it from stdin)
+        #[arg(long, group = "import")]
+        from_curl: Option<String>,
+        /// Import the session from a Netscape cookies.txt export
+        #[arg(long, group = "import")]
+        from_netscape: Option<PathBuf>,
+        /// Import the session from a HAR capture of i.mi.com
+        #[arg(long, group = "import")]
+        from_har: Option<PathBuf>,
+        /// Account server base URL, e.g. a local mock
+        #[arg(long, hide = true)]
+        account_base: Option<String>,
+        /// STS callback URL, e.g. a local mock
+        #[arg(long, hide = true)]
+        callback: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// List notes in the recycle bin (default)
+    List,
+    /// Move notes back out of the recycle bin
+    Restore {
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Permanently delete notes from the recycle bin
+    Purge {
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Permanently delete everything in the recycle bin
+    Empty {
+        /// Required confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum OutboxAction {
+    /// List queued operations and conflicts (default)
+    List,
+    /// Send queued operations now
+    Flush,
+    /// Re-queue a conflicted operation
+    Retry { id: String },
+    /// Drop a queued or conflicted operation
+    Discard { id: String },
+}
+
+#[derive(Subcommand)]
+pub enum HistoryAction {
+    /// List recorded versions, oldest first (default)
+    List,
+    /// Show a line diff between two versions
+    Diff {
+        from: usize,
+        /// Defaults to the latest version
+        to: Option<usize>,
+    },
+    /// Write a previous version back to the server
+    Restore { version: usize },
+}
+
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// Write a new archive and prune old ones (default)
+    Create,
+    /// List archives in the backup directory
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum ProfileAction {
+    /// List profiles, marking the active one (default)
+    List,
+    /// Create an empty profile; sign in with `minote --profile <name> login`
+    Add { name: String },
+    /// Delete a profile with its settings, stored cookie and local state
+    Remove { name: String },
+    /// Make a profile the active one for the GUI and later commands
+    Use { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print every effective setting and where its value comes from (default)
+    Show,
+    /// Print one effective setting
+    Get { key: String },
+    /// Change a setting in config.json; an empty value unsets optional ones
+    Set { key: String, value: String },
+}
+
+pub async fn run(command: Command) -> MiResult<()> {
+    match command {
+        Command::Append { target, text } => append(&target, &text.join(" ")).await,
+        Command::Trash { action } => trash(action.unwrap_or(TrashAction::List)).await,
+        Command::Outbox { action } => outbox(action.unwrap_or(OutboxAction::List)).await,
+        Command::History { target, action } => history(&target, action.unwrap_or(HistoryAction::List)).await,
+        Command::Backup { action } => backup(action.unwrap_or(BackupAction::Create)).await,
+        Command::Restore { archive } => restore(archive).await,
+        Command::Search { query, all_profiles } => search(&query, all_profiles).await,
+        Command::Profile { action } => profiles(action.unwrap_or(ProfileAction::List)),
+        Command::Config { action } => config(action.unwrap_or(ConfigAction::Show)),
+        Command::Status => status().await,
+        Command::Login { from_curl: Some(cmd), .. } => {
+            let cmd = if cmd == "-" { std::io::read_to_string(std::io::stdin())? } else { cmd };
+            import_login(import::from_curl(&cmd)?).await
+        }
+        Command::Login { from_netscape: Some(path), .. } => import_login(import::from_netscape(&std::fs::read_to_string(path)?)?).await,
+        Command::Login { from_har: Some(path), .. } => import_login(import::from_har(&std::fs::read_to_string(path)?)?).await,
+        Command::Login { user, account_base, callback, .. } => {
+            let mut endpoints = LoginEndpoints::default();
+            let probe = account_base.is_none();
+            if let Some(base) = account_base {
+                endpoints.account_base = base;
+            }
+            if let Some(callback) = callback {
+                endpoints.callback = callback;
+            }
+            login(user, endpoints, probe).await
+        }
+    }
+}
+
+fn client_from_config() -> MiResult<Client> {
+    client_for(&AppConfig::load())
+}
+
+fn client_for(config: &AppConfig) -> MiResult<Client> {
+    let cookie = config.account_cookie.as_deref().ok_or("Not logged in. Start the app and log in first.")?;
+    Client::new(cookie, config)
+}
+
+/// Resolves a note id, or a query that clearly matches one live note better than any other.
+async fn resolve_note(client: &Client, target: &str) -> MiResult<Note> {
+    if !target.is_empty() && target.bytes().all(|b| b.is_ascii_digit()) {
+        match client.get_note(target).await {
+            Ok(note) if note.is_deleted() => return Err(format!("Note {} is in the recycle bin", target).into()),
+            Ok(note) => return Ok(note),
+            // Not an id after all (a title made of digits); search for it instead
+            Err(e) if matches!(e.downcast_ref::<ApiError>(), Some(ApiError::Api { .. } | ApiError::Http { status: 404, .. })) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let (notes, _) = client.list_all().await?;
+    let matcher = SkimMatcherV2::default();
+    let scored = notes.into_iter()
+        .filter(|note| !note.is_deleted())
+        .filter_map(|note| match_score(&matcher, &note, target).map(|score| (score, note)))
+        .collect();
+    pick_match(scored, target)
+}
+
+/// How many candidates an ambiguous query lists.
+const MAX_CANDIDATES: usize = 10;
+
+/// The one note a query is about: its only exact title match, or a fuzzy match that
+/// scores clearly (a quarter) above the runner-up. Anything less lists the candidates.
+fn pick_match(mut scored: Vec<(i64, Note)>, target: &str) -> MiResult<Note> {
+    let exact: Vec<usize> = scored.iter().enumerate()
+        .filter(|(_, (_, note))| note.display_title().eq_ignore_ascii_case(target.trim()))
+        .map(|(i, _)| i)
+        .collect();
+    if let [i] = exact[..] {
+        return Ok(scored.swap_remove(i).1);
+    }
+
+    scored.sort_by_key(|(score, note)| (std::cmp::Reverse(*score), note.id.clone()));
+    match &scored[..] {
+        [] => Err(format!("No note matches '{}'", target).into()),
+        [_] => Ok(scored.remove(0).1),
+        [(best, _), (runner_up, _), ..] if exact.is_empty() && *runner_up * 4 < *best * 3 => Ok(scored.remove(0).1),
+        _ => {
+            let mut message = format!("'{}' matches {} notes; use an id or a more specific query:", target, scored.len());
+            for (_, note) in scored.iter().take(MAX_CANDIDATES) {
+                message.push_str(&format!("\n  {}  {}", note.id, note.display_title()));
+            }
+            if scored.len() > MAX_CANDIDATES {
+                message.push_str(&format!("\n  ... and {} more", scored.len() - MAX_CANDIDATES));
+            }
+            Err(message.into())
+        }
+    }
+}
+
+/// Fuzzy score of a note against a query, over its title and snippet.
+fn match_score(matcher: &SkimMatcherV2, note: &Note, query: &str) -> Option<i64> {
+    let title = matcher.fuzzy_match(&note.display_title(), query);
+    let snippet = matcher.fuzzy_match(&note.clean_snippet(), query);
+    std::cmp::max(title, snippet)
+}
+
+async fn search(query: &str, all_profiles: bool) -> MiResult<()> {
+    let names = if all_profiles { profile::list() } else { vec![profile::active()] };
+    let matcher = SkimMatcherV2::default();
+    let mut results = Vec::new();
+
+    for name in names {
+        let config = if name == profile::active() { AppConfig::load() } else { AppConfig::load_profile(&name) };
+        let Some(ref cookie) = config.account_cookie else {
+            if !all_profiles {
+                return Err("Not logged in. Start the app and log in first.".into());
+            }
+            continue;
+        };
+        let listed = match Client::new(cookie, &config) {
+            Ok(client) => client.list_all().await,
+            Err(e) => Err(e),
+        };
+        match listed {
+            Ok((notes, _)) => {
+                for note in notes {
+                    if let Some(score) = match_score(&matcher, &note, query) {
+                        results.push((score, name.clone(), note));
+                    }
+                }
+            }
+            Err(e) if all_profiles => eprintln!("Skipping profile '{}': {}", name, e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    results.sort_by_key(|r| std::cmp::Reverse(r.0));
+    for (_, name, note) in &results {
+        if all_profiles {
+            println!("[{}] {}  {}", name, note.id, note.display_title());
+        } else {
+            println!("{}  {}", note.id, note.display_title());
+        }
+    }
+    if results.is_empty() {
+        println!("No notes match '{}'", query);
+    }
+    Ok(())
+}
+
+fn profiles(action: ProfileAction) -> MiResult<()> {
+    match action {
+        ProfileAction::List => {
+            let active = profile::active();
+            for name in profile::list() {
+                let marker = if name == active { "*" } else { " " };
+                let status = if AppConfig::load_profile(&name).account_cookie.is_some() { "" } else { "  (not signed in)" };
+                println!("{} {}{}", marker, name, status);
+            }
+        }
+        ProfileAction::Add { name } => {
+            profile::create(&name)?;
+            println!("Created profile '{}'. Sign in with `minote --profile {} login`.", name, name);
+        }
+        ProfileAction::Remove { name } => {
+            profile::remove(&name)?;
+            println!("Removed profile '{}'", name);
+        }
+        ProfileAction::Use { name } => {
+            profile::switch(&name)?;
+            println!("Now using profile '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+/// Strings print without quotes and unset values as nothing, so `get` is easy to script.
+fn display_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn config(action: ConfigAction) -> MiResult<()> {
+    match action {
+        ConfigAction::Show => {
+            let config = AppConfig::load();
+            let path = AppConfig::get_config_path(&config.profile);
+            println!("Profile '{}', {}", config.profile, path.map(|p| p.display().to_string()).unwrap_or_else(|| "no config file".to_string()));
+            let sources = config.sources();
+            let width = sources.iter().map(|(key, _, _)| key.len()).max().unwrap_or(0);
+            for (key, value, source) in sources {
+                println!("{:width$}  {}  ({})", key, display_value(&value), source, width = width);
+            }
+        }
+        ConfigAction::Get { key } => {
+            println!("{}", display_value(&AppConfig::load().get_value(&key)?));
+        }
+        ConfigAction::Set { key, value } => {
+            // Start from the file alone so overrides of other settings aren't written into it
+            let mut config = AppConfig::load_profile(&profile::active());
+            config.set_value(&key, &value)?;
+            config.save()?;
+            println!("{} = {}", key, display_value(&config.get_value(&key)?));
+            if let Some((_, _, source)) = crate::api::overrides::layers(std::slice::from_ref(&key)).pop() {
+                println!("Note: {} overrides this setting", source);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Makes one small request, refreshing the session if needed, and reports the outcome.
+async fn status() -> MiResult<()> {
+    let config = AppConfig::load();
+    println!("Profile '{}', server {}", config.profile, config.api_base_url);
+    let Some(ref cookie) = config.account_cookie else {
+        return Err("Not logged in. Sign in with `minote login`.".into());
+    };
+    let client = Client::new(cookie, &config)?;
+    let checked = client.list_notes(1).await;
+    println!("Session: {}", client.status());
+    if let Some(session) = Session::load_for(cookie) {
+        println!("Refreshed: {}", format_millis(session.obtained_at));
+        println!("Expires:   {} (refreshed automatically before then)", format_millis(session.expires_at));
+    }
+    match (client.status(), checked) {
+        (SessionStatus::Expired, _) => Err("The session has expired. Sign in again with `minote login`.".into()),
+        (_, Err(e)) => Err(e),
+        (_, Ok(_)) => Ok(()),
+    }
+}
+
+async fn append(target: &str, text: &str) -> MiResult<()> {
+    let client = client_from_config()?;
+    let note = resolve_note(&client, target).await?;
+
+    let stamp = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
+    let op = PendingOp::AppendNote { note_id: note.id.clone(), line: markup::timestamped_line(text, &stamp) };
+    if Outbox::submit(&client, op).await? {
+        println!("Appended to \"{}\" ({})", note.display_title(), note.id);
+    } else {
+        println!("Queued an append to \"{}\" ({}); it is sent when the connection is back", note.display_title(), note.id);
+    }
+    Ok(())
+}
+
+/// Purges notes from the recycle bin through the outbox.
+async fn purge(client: &Client, notes: &[Note]) -> MiResult<()> {
+    for note in notes {
+        let op = PendingOp::DeleteNote { note_id: note.id.clone(), tag: note.tag.clone(), purge: true };
+        if Outbox::submit(client, op).await? {
+            println!("Purged \"{}\" ({})", note.display_title(), note.id);
+        } else {
+            println!("Queued purging \"{}\" ({})", note.display_title(), note.id);
+        }
+    }
+    Ok(())
+}
+
+async fn trash(action: TrashAction) -> MiResult<()> {
+    let client = client_from_config()?;
+    match action {
+        TrashAction::List => {
+            let trash = client.list_trash(200).await?;
+            println!("{} note(s) in the recycle bin", trash.len());
+            for note in &trash {
+                let deleted = chrono::DateTime::from_timestamp_millis(note.modify_date)
+                    .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                println!("  {}  {}  {}", note.id, deleted, note.display_title());
+            }
+        }
+        TrashAction::Restore { ids } => {
+            let trash = client.list_trash(200).await?;
+            for id in ids {
+                let note = trash.iter().find(|n| n.id == id).ok_or_else(|| format!("Note {} is not in the recycle bin", id))?;
+                client.restore_note(&note.id, &note.tag).await?;
+                println!("Restored \"{}\" ({})", note.display_title(), note.id);
+            }
+        }
+        TrashAction::Purge { ids } => {
+            let trash = client.list_trash(200).await?;
+            let notes = ids.iter()
+                .map(|id| trash.iter().find(|n| n.id == *id).cloned().ok_or_else(|| format!("Note {} is not in the recycle bin", id)))
+                .collect::<Result<Vec<_>, _>>()?;
+            purge(&client, &notes).await?;
+        }
+        TrashAction::Empty { yes } => {
+            if !yes {
+                return Err("Refusing to empty the recycle bin without --yes".into());
+            }
+            let trash = client.list_trash(200).await?;
+            purge(&client, &trash).await?;
+            println!("Emptied the recycle bin ({} note(s))", trash.len());
+        }
+    }
+    Ok(())
+}
+
+async fn outbox(action: OutboxAction) -> MiResult<()> {
+    let dir = AppConfig::data_dir();
+    let dir = dir.as_deref();
+    match action {
+        OutboxAction::List => {
+            let outbox = Outbox::load(dir);
+            println!("{} queued, {} conflict(s)", outbox.entries.len(), outbox.conflicts.len());
+            for entry in &outbox.entries {
+                println!("  {}  attempts={}  {:?}", entry.id, entry.attempts, entry.op);
+            }
+            for conflict in &outbox.conflicts {
+                println!("! {}  {}", conflict.entry.id, conflict.reason);
+            }
+        }
+        OutboxAction::Flush => {
+            let client = client_from_config()?;
+            let sent = Outbox::flush(&client).await?;
+            println!("Sent {} operation(s), {} still queued", sent, Outbox::pending(client.data_dir()));
+        }
+        OutboxAction::Retry { id } => {
+            if !Outbox::retry_conflict(dir, &id)? {
+                return Err(format!("No conflict with id {}", id).into());
+            }
+            println!("Re-queued {}", id);
+        }
+        OutboxAction::Discard { id } => {
+            if !Outbox::discard(dir, &id)? {
+                return Err(format!("No outbox entry with id {}", id).into());
+            }
+            println!("Discarded {}", id);
+        }
+    }
+    Ok(())
+}
+
+fn format_millis(ms: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(ms)
+        .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Picks a 1-based version number out of the recorded history.
+fn version_at(versions: &[NoteVersion], number: usize) -> MiResult<&NoteVersion> {
+    number.checked_sub(1)
+        .and_then(|i| versions.get(i))
+        .ok_or_else(|| format!("No version {} (have 1..{})", number, versions.len()).into())
+}
+
+async fn history(target: &str, action: HistoryAction) -> MiResult<()> {
+    let client = client_from_config()?;
+    let note = resolve_note(&client, target).await?;
+    let versions = NoteHistory::versions(client.data_dir(), &note.id);
+    if versions.is_empty() {
+        return Err(format!("No versions recorded for \"{}\" yet", note.display_title()).into());
+    }
+
+    match action {
+        HistoryAction::List => {
+            println!("{} version(s) of \"{}\" ({})", versions.len(), note.display_title(), note.id);
+            for (i, v) in versions.iter().enumerate() {
+                let current = if v.tag == note.tag { "  (current)" } else { "" };
+                println!("  {:>3}  {}  {} chars{}", i + 1, format_millis(v.modify_date), v.content.chars().count(), current);
+            }
+        }
+        HistoryAction::Diff { from, to } => {
+            let old = version_at(&versions, from)?;
+            let new = version_at(&versions, to.unwrap_or(versions.len()))?;
+            let old_text = markup::to_plain(&markup::parse(&old.content));
+            let new_text = markup::to_plain(&markup::parse(&new.content));
+            let old_lines: Vec<&str> = old_text.lines().collect();
+            let new_lines: Vec<&str> = new_text.lines().collect();
+            for line in merge::diff_lines(&old_lines, &new_lines) {
+                match line {
+                    DiffLine::Same(l) => println!("  {}", l),
+                    DiffLine::Removed(l) => println!("- {}", l),
+                    DiffLine::Added(l) => println!("+ {}", l),
+                }
+            }
+        }
+        HistoryAction::Restore { version } => {
+            let v = version_at(&versions, version)?;
+            // A new version on top of the one just read; edits made since are merged or set aside
+            let op = PendingOp::UpdateNote {
+                note_id: note.id.clone(),
+                base_tag: note.tag.clone(),
+                base_content: note.content.clone(),
+                subject: v.subject.clone(),
+                content: v.content.clone(),
+                folder_id: note.folder_id.clone(),
+            };
+            if Outbox::submit(&client, op).await? {
+                NoteHistory::record(client.data_dir(), &client.get_note(&note.id).await?).ok();
+                println!("Restored \"{}\" to version {}", note.display_title(), version);
+            } else {
+                println!("Queued restoring \"{}\" to version {}", note.display_title(), version);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn backup(action: BackupAction) -> MiResult<()> {
+    match action {
+        BackupAction::Create => {
+            let config = AppConfig::load();
+            let client = client_for(&config)?;
+            match backup::backup_now(&client, &config).await? {
+                Some(path) => println!("Backup written to {}", path.display()),
+                None => println!("A backup is already running"),
+            }
+        }
+        BackupAction::List => {
+            let archives = backup::list(AppConfig::data_dir().as_deref());
+            println!("{} backup(s)", archives.len());
+            for path in archives.iter().rev() {
+                let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                println!("  {}  {} KB", path.display(), size / 1024);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn restore(archive: PathBuf) -> MiResult<()> {
+    let path = if archive.exists() {
+        archive
+    } else {
+        backup::backup_dir(AppConfig::data_dir().as_deref())
+            .map(|dir| dir.join(&archive))
+            .filter(|p| p.exists())
+            .ok_or_else(|| format!("No backup archive at {}", archive.display()))?
+    };
+
+    let client = client_from_config()?;
+    let report = backup::restore(&client, &path).await?;
+    println!("Created {} folder(s) and {} note(s) from {}", report.folders, report.notes, path.display());
+    if report.skipped_folders + report.skipped_notes > 0 {
+        println!("Skipped {} folder(s) and {} note(s) that already exist", report.skipped_folders, report.skipped_notes);
+    }
+    if report.attachments_not_restored > 0 {
+        println!("Attachments are not restored: {} file(s) stay in the archive only.", report.attachments_not_restored);
+        if !report.notes_with_attachments.is_empty() {
+            println!("These notes show their images and recordings only while the originals still exist on the source account:");
+            for title in &report.notes_with_attachments {
+                println!("  {}", title);
+            }
+        }
+    }
+    for (what, error) in &report.failed {
+        println!("! {}: {}", what, error);
+    }
+    if !report.failed.is_empty() {
+        return Err(format!("{} item(s) could not be restored", report.failed.len()).into());
+    }
+    Ok(())
+}
+
+fn wait_for_enter() -> MiResult<()> {
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(())
+}
+
+async fn login(user: Option<String>, endpoints: LoginEndpoints, probe: bool) -> MiResult<()> {
+    let mut config = AppConfig::load();
+    let mut flow = Login::new(endpoints, &config)?;
+    // Keep the device id of the previous login so the account recognises this machine
+    let device_id = config.account_cookie.as_deref()
+        .and_then(|c| crate::api::cookie::value(c, "deviceId"))
+        .map(str::to_string);
+    if let Some(id) = device_id {
+        flow = flow.with_device_id(&id)?;
+    }
+
+    let cookie = match user {
+        Some(user) => {
+            let password = rpassword::prompt_password("Password: ")?;
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                match flow.password(&user, &password).await {
+                    Ok(cookie) => break cookie,
+                    Err(e) => match e.downcast_ref::<Challenge>() {
+                        Some(Challenge::Verification { url }) if attempt < 3 => {
+                            println!("This sign-in needs verification. Open the link below, complete it, then press Enter:");
+                            println!("{}", url);
+                            wait_for_enter()?;
+                        }
+                        Some(Challenge::Captcha { .. }) => {
+                            return Err("The account server asked for a captcha. Run `minote login` without --user to sign in with a QR code instead.".into());
+                        }
+                        _ => return Err(e),
+                    },
+                }
+            }
+        }
+        None => {
+            let qr = flow.start_qr().await?;
+            println!("Scan this code with the Xiaomi app and confirm the sign-in:\n");
+            match login::render_qr(&qr.login_url) {
+                Ok(code) => println!("{}", code),
+                Err(_) => println!("{}", qr.login_url),
+            }
+            if let Some(ref image) = qr.image_url {
+                println!("If the code doesn't scan, open {}", image);
+            }
+            flow.wait_qr(&qr).await?
+        }
+    };
+
+    // Stored sessions belong to the previous login
+    if let Some(ref previous) = config.account_cookie {
+        Session::clear(previous);
+    }
+    if probe {
+        Client::new(&cookie, &config)?.list_notes(1).await
+            .map_err(|e| format!("Signed in, but the notes service rejected the session: {}", e))?;
+    }
+
+    config.account_cookie = Some(cookie);
+    config.save()?;
+    println!("Logged in.");
+    Ok(())
+}
+
+/// Stores an imported browser session once a probe request confirms it works.
+async fn import_login(imported: ImportedSession) -> MiResult<()> {
+    let mut config = AppConfig::load();
+    if imported.user_agent.is_some() {
+        config.user_agent = imported.user_agent;
+    }
+
+    if let Some(ref previous) = config.account_cookie {
+        Session::clear(previous);
+    }
+    Client::new(&imported.cookie, &config)?.list_notes(1).await
+        .map_err(|e| format!("The imported cookies were rejected: {}", e))?;
+
+    config.account_cookie = Some(imported.cookie);
+    config.save()?;
+    println!("Imported session and logged in.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, subject: &str) -> Note {
+        Note { id: id.to_string(), subject: subject.to_string(), title: Some(subject.to_string()), ..Note::default() }
+    }
+
+    #[test]
+    fn unique_exact_title_wins_over_fuzzy_scores() {
+        let scored = vec![(120, note("1", "Groceries list")), (90, note("2", "groceries"))];
+        assert_eq!(pick_match(scored, "Groceries").unwrap().id, "2");
+    }
+
+    #[test]
+    fn clear_fuzzy_winner_is_picked() {
+        let scored = vec![(40, note("1", "Travel")), (100, note("2", "Tax return"))];
+        assert_eq!(pick_match(scored, "taxret").unwrap().id, "2");
+    }
+
+    #[test]
+    fn close_scores_list_the_candidates() {
+        let scored = vec![(100, note("1", "Meeting notes")), (90, note("2", "Meeting agenda"))];
+        let message = pick_match(scored, "meeting").unwrap_err().to_string();
+        assert!(message.contains("matches 2 notes"), "{}", message);
+        assert!(message.contains("1  Meeting notes") && message.contains("2  Meeting agenda"), "{}", message);
+    }
+
+    #[test]
+    fn several_exact_titles_are_ambiguous() {
+        let scored = vec![(100, note("1", "Todo")), (20, note("2", "todo")), (10, note("3", "Other"))];
+        assert!(pick_match(scored, "todo").is_err());
+    }
+
+    #[test]
+    fn no_candidates_is_an_error() {
+        assert_eq!(pick_match(Vec::new(), "x").unwrap_err().to_string(), "No note matches 'x'");
+    }
+}
@@ -7,7 +7,11 @@ use std::cell::RefCell;
 use crate::state;
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
-use crate::api::models::{Note, strip_tags_multiline};
+use crate::api::models::Note;
+use crate::api::config::Destination;
+use crate::api::config_service;
+use crate::api::markup::{self, Fragment, PasteFormat, PastePayload, cf_html};
+use crate::api::outbox::{Outbox, PendingOp};
 use winapi::um::winuser::*;
 use winapi::um::libloaderapi::GetModuleHandleW;
 
@@ -32,11 +36,20 @@ pub struct LaunchBar {
     results_list: nwg::ListBox<String>,
 
     matcher: SkimMatcherV2,
-    current_results: RefCell<Vec<Note>>,
+    /// Matches shown in the list; notes of other profiles carry the profile name.
+    current_results: RefCell<Vec<(Option<String>, Note)>>,
+    /// Profiles offered by an "@name" query.
+    current_profiles: RefCell<Vec<String>>,
+
+    // Set while drilled into a single note (Tab / Right arrow)
+    drill_note: RefCell<Option<Note>>,
+    drill_fragments: RefCell<Vec<Fragment>>,
+    current_fragments: RefCell<Vec<Fragment>>,
 }
 
 impl LaunchBar {
     pub fn show(&self) {
+        self.exit_drill_state();
         self.center_window();
         self.window.set_visible(true);
         self.input.set_focus();
@@ -44,6 +57,18 @@ impl LaunchBar {
         self.on_input_changed();
     }
 
+    /// Shows the launch bar with `query` already typed in.
+    pub fn show_with(&self, query: &str) {
+        self.exit_drill_state();
+        self.center_window();
+        self.window.set_visible(true);
+        self.input.set_text(query);
+        let end = query.encode_utf16().count() as i32;
+        self.set_input_sel(end, end);
+        self.input.set_focus();
+        self.on_input_changed();
+    }
+
     fn center_window(&self) {
         let (screen_w, screen_h) = unsafe {
             (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN))
@@ -58,21 +83,132 @@ impl LaunchBar {
         self.window.set_visible(false);
     }
 
+    fn enter_drill(&self) {
+        let note = match self.results_list.selection() {
+            Some(index) => self.current_results.borrow().get(index).map(|(_, n)| n.clone()),
+            None => None,
+        };
+        let Some(note) = note else { return; };
+
+        let raw_content = note.content.clone().unwrap_or_else(|| note.snippet.clone());
+        let fragments = markup::fragments(&markup::parse(&raw_content));
+        dprintln!("[LaunchBar] Drilling into '{}' ({} fragments)", note.display_title(), fragments.len());
+
+        self.window.set_text(&format!("MiNote Launch Bar - {}", note.display_title()));
+        *self.drill_fragments.borrow_mut() = fragments;
+        *self.drill_note.borrow_mut() = Some(note);
+        self.input.set_text("");
+        self.on_input_changed();
+    }
+
+    fn exit_drill_state(&self) {
+        self.drill_note.borrow_mut().take();
+        self.drill_fragments.borrow_mut().clear();
+        self.current_fragments.borrow_mut().clear();
+        self.window.set_text("MiNote Launch Bar");
+    }
+
+    fn exit_drill(&self) {
+        self.exit_drill_state();
+        self.input.set_text("");
+        self.on_input_changed();
+    }
+
+    fn is_drilling(&self) -> bool {
+        self.drill_note.borrow().is_some()
+    }
+
+    fn filter_fragments(&self, query: &str) {
+        let fragments = self.drill_fragments.borrow();
+        let mut matches: Vec<(i64, &Fragment)> = fragments.iter()
+            .filter_map(|f| {
+                if query.is_empty() {
+                    return Some((0, f));
+                }
+                self.matcher.fuzzy_match(&f.label(), query).map(|score| (score, f))
+            })
+            .collect();
+
+        if !query.is_empty() {
+            matches.sort_by(|a, b| b.0.cmp(&a.0));
+        }
+
+        let top: Vec<Fragment> = matches.into_iter().take(50).map(|(_, f)| f.clone()).collect();
+
+        self.results_list.clear();
+        for fragment in &top {
+            self.results_list.push(fragment.label());
+        }
+        if !top.is_empty() {
+            self.results_list.set_selection(Some(0));
+        }
+        *self.current_fragments.borrow_mut() = top;
+    }
+
     fn on_input_changed(&self) {
         let query = self.input.text();
-        let notes = state::get_notes();
+        if self.is_drilling() {
+            self.filter_fragments(&query);
+            return;
+        }
+
+        // "+text" switches to quick-capture
+        if let Some(text) = query.strip_prefix('+') {
+            let text = text.trim();
+            self.current_results.borrow_mut().clear();
+            self.results_list.clear();
+            self.results_list.push(if text.is_empty() {
+                "+ Create note from clipboard".to_string()
+            } else {
+                format!("+ Create note: {}", text)
+            });
+            self.results_list.set_selection(Some(0));
+            return;
+        }
+
+        // "@name" lists profiles to switch to
+        if let Some(prefix) = query.strip_prefix('@') {
+            let prefix = prefix.trim().to_lowercase();
+            let active = crate::api::profile::active();
+            let profiles: Vec<String> = crate::api::profile::list().into_iter()
+                .filter(|name| name.to_lowercase().starts_with(&prefix))
+                .collect();
+            self.current_results.borrow_mut().clear();
+            self.results_list.clear();
+            for name in &profiles {
+                self.results_list.push(if *name == active {
+                    format!("@ {} (active)", name)
+                } else {
+                    format!("@ Switch to profile: {}", name)
+                });
+            }
+            if !profiles.is_empty() {
+                self.results_list.set_selection(Some(0));
+            }
+            *self.current_profiles.borrow_mut() = profiles;
+            return;
+        }
+
+        // "query >> text" filters by the query and appends text to the chosen note
+        let (query, _) = split_append(&query);
+        let query = query.to_string();
+
+        let mut notes: Vec<(Option<String>, Note)> = state::get_notes().into_iter().map(|n| (None, n)).collect();
+        if config_service::current().search_all_profiles {
+            notes.extend(state::get_other_notes().into_iter().map(|(profile, n)| (Some(profile), n)));
+        }
 
         dprintln!("[LaunchBar] Input changed: query='{}', notes_in_cache={}", query, notes.len());
         if notes.len() > 0 {
-            dprintln!("[LaunchBar] Example note in cache: {}", notes[0].display_title());
+            dprintln!("[LaunchBar] Example note in cache: {}", notes[0].1.display_title());
         }
 
-        let mut matches: Vec<(i64, Note)> = if query.is_empty() {
+        let mut matches: Vec<(i64, (Option<String>, Note))> = if query.is_empty() {
             notes.into_iter().map(|n| (0, n)).collect()
         } else {
             let query_lower = query.to_lowercase();
             notes.into_iter()
-                .filter_map(|note| {
+                .filter_map(|(profile, note)| {
                     let clean_title = note.display_title();
                     let clean_snippet = note.clean_snippet();
 
@@ -84,7 +220,7 @@ impl LaunchBar {
                                          clean_snippet.to_lowercase().contains(&query_lower);
 
                     if score > 0 || contains_match {
-                        Some((if score > 0 { score } else { 1 }, note))
+                        Some((if score > 0 { score } else { 1 }, (profile, note)))
                     } else {
                         None
                     }
@@ -96,7 +232,7 @@ impl LaunchBar {
             matches.sort_by(|a, b| b.0.cmp(&a.0));
         }
 
-        let top_matches: Vec<Note> = matches.into_iter()
+        let top_matches: Vec<(Option<String>, Note)> = matches.into_iter()
             .take(20)
             .map(|(_, n)| n)
             .collect();
@@ -106,8 +242,11 @@ impl LaunchBar {
         dprintln!("Found {} matches. Pushing to listbox...", top_matches.len());
 
         self.results_list.clear();
-        for note in &top_matches {
-            self.results_list.push(note.display_title());
+        for (profile, note) in &top_matches {
+            self.results_list.push(match profile {
+                Some(profile) => format!("[{}] {}", profile, note.display_title()),
+                None => note.display_title(),
+            });
         }
 
         if !top_matches.is_empty() {
@@ -121,8 +260,26 @@ impl LaunchBar {
             let ctrl = unsafe { GetKeyState(VK_CONTROL) < 0 };
 
             match *key {
-                nwg::keys::ESCAPE => self.hide(),
+                nwg::keys::ESCAPE => {
+                    if self.is_drilling() { self.exit_drill(); } else { self.hide(); }
+                }
                 nwg::keys::RETURN => self.on_confirm(),
+                nwg::keys::TAB => {
+                    if !self.is_drilling() { self.enter_drill(); }
+                }
+                nwg::keys::RIGHT => {
+                    // Only drill when the caret is already at the end of the query
+                    let (start, end) = self.get_input_sel();
+                    let len = self.input.text().encode_utf16().count() as i32;
+                    if !self.is_drilling() && start == end && end >= len {
+                        self.enter_drill();
+                    }
+                }
+                nwg::keys::LEFT => {
+                    if self.is_drilling() && self.input.text().is_empty() {
+                        self.exit_drill();
+                    }
+                }
                 nwg::keys::DOWN => {
                     let sel = self.results_list.selection();
                     let count = self.results_list.len();
@@ -227,44 +384,131 @@ impl LaunchBar {
     }
 
     fn on_confirm(&self) {
+        let config = config_service::current();
+        let (ctrl, shift) = unsafe { (GetKeyState(VK_CONTROL) < 0, GetKeyState(VK_SHIFT) < 0) };
+        let format = config.paste_format.with_modifiers(ctrl, shift);
+
+        if !self.is_drilling() {
+            let query = self.input.text();
+            // Quick-capture: "+text", or Ctrl+Enter when nothing matched
+            let capture = match query.strip_prefix('+') {
+                Some(text) => Some(text.trim().to_string()),
+                None if ctrl && !shift && self.current_results.borrow().is_empty() => Some(query.trim().to_string()),
+                None => None,
+            };
+            if let Some(text) = capture {
+                self.capture(&text, &config);
+                self.hide();
+                return;
+            }
+
+            if query.starts_with('@') {
+                let target = self.results_list.selection()
+                    .and_then(|i| self.current_profiles.borrow().get(i).cloned());
+                if let Some(name) = target {
+                    crate::webview::switch_profile(&name);
+                }
+                self.hide();
+                return;
+            }
+
+            if let (_, Some(text)) = split_append(&query) {
+                // Appends go through this profile's outbox, so other profiles' notes are read-only
+                let target = self.results_list.selection()
+                    .and_then(|i| self.current_results.borrow().get(i).cloned());
+                if let Some((None, note)) = target {
+                    self.append(&note, text, &config);
+                }
+                self.hide();
+                return;
+            }
+        }
+
         if let Some(index) = self.results_list.selection() {
-            let matches = self.current_results.borrow();
-            if let Some(note) = matches.get(index) {
-                self.perform_action(note);
+            if self.is_drilling() {
+                let fragments = self.current_fragments.borrow();
+                if let Some(fragment) = fragments.get(index) {
+                    self.deliver(&format.render_lines(&fragment.lines));
+                }
+            } else {
+                let matches = self.current_results.borrow();
+                if let Some((_, note)) = matches.get(index) {
+                    self.perform_action(note, format);
+                }
             }
         }
         self.hide();
     }
 
-    fn perform_action(&self, note: &Note) {
-        let config = crate::api::AppConfig::load();
+    /// Queues a new note built from `text`, or from the clipboard when `text` is empty.
+    /// The sync loop pushes it, so capture works offline too.
+    fn capture(&self, text: &str, config: &crate::api::AppConfig) {
+        let body = if text.is_empty() { get_clipboard_text().unwrap_or_default() } else { text.to_string() };
+        if body.trim().is_empty() {
+            return;
+        }
+
+        let op = PendingOp::CreateNote {
+            folder_id: config.capture_folder_id.clone(),
+            subject: String::new(),
+            content: markup::from_plain(&body),
+        };
+        // The profile the bar was opened for, even if another one becomes active meanwhile
+        let dir = crate::api::AppConfig::data_dir_of(config);
+        match Outbox::enqueue(dir.as_deref(), op) {
+            Ok(local_id) => {
+                if let Some(note) = Outbox::pending_notes(dir.as_deref()).into_iter().find(|n| n.id == local_id) {
+                    state::add_note(note);
+                }
+                state::WAKE_UP_NOTIFY.notify_one();
+            }
+            Err(e) => eprintln!("[LaunchBar] Failed to queue note: {:?}", e),
+        }
+    }
+
+    fn append(&self, note: &Note, text: &str, config: &crate::api::AppConfig) {
+        if text.is_empty() {
+            return;
+        }
+        let stamp = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
+        let op = PendingOp::AppendNote {
+            note_id: note.id.clone(),
+            line: markup::timestamped_line(text, &stamp),
+        };
+        match Outbox::enqueue(crate::api::AppConfig::data_dir_of(config).as_deref(), op) {
+            Ok(_) => state::WAKE_UP_NOTIFY.notify_one(),
+            Err(e) => eprintln!("[LaunchBar] Failed to queue append: {:?}", e),
+        }
+    }
 
+    fn perform_action(&self, note: &Note, format: PasteFormat) {
         // Use full content if available, fallback to snippet
         let raw_content = note.content.clone().unwrap_or_else(|| note.snippet.clone());
+        self.deliver(&format.render(&raw_content));
+    }
 
-        // Decode HTML entities (e.g., &amp; -> &) and strip all <tags>
-        let clean_content = strip_tags_multiline(&raw_content);
+    fn deliver(&self, payload: &PastePayload) {
+        let config = config_service::current();
 
-        if config.destination == "Clipboard" {
-            set_clipboard_text(&clean_content);
+        if config.destination == Destination::Clipboard {
+            set_clipboard(payload);
         } else {
-            // Previous Program
             self.hide(); // Must hide first to return focus
             std::thread::sleep(std::time::Duration::from_millis(500)); // Longer wait for safety
-            type_text(&clean_content);
+            type_text(&payload.text);
         }
     }
 }
 
-fn set_clipboard_text(text: &str) {
+fn set_clipboard(payload: &PastePayload) {
     use widestring::U16String;
-    use winapi::um::winuser::{OpenClipboard, EmptyClipboard, SetClipboardData, CloseClipboard, CF_UNICODETEXT};
+    use winapi::um::winuser::{OpenClipboard, EmptyClipboard, SetClipboardData, CloseClipboard, RegisterClipboardFormatW, CF_UNICODETEXT};
     use winapi::um::winbase::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
 
     unsafe {
         if OpenClipboard(std::ptr::null_mut()) != 0 {
             EmptyClipboard();
-            let wide = U16String::from_str(text);
+            let wide = U16String::from_str(&payload.text);
             let size = (wide.len() + 1) * 2;
             let h_mem = GlobalAlloc(GMEM_MOVEABLE, size);
             if !h_mem.is_null() {
@@ -274,11 +518,55 @@ fn set_clipboard_text(text: &str) {
                 GlobalUnlock(h_mem);
                 SetClipboardData(CF_UNICODETEXT, h_mem);
             }
+
+            // CF_HTML is a registered format holding UTF-8 bytes
+            if let Some(ref fragment) = payload.html {
+                let format_name: Vec<u16> = "HTML Format".encode_utf16().chain(std::iter::once(0)).collect();
+                let cf_html_id = RegisterClipboardFormatW(format_name.as_ptr());
+                let bytes = cf_html(fragment).into_bytes();
+                let h_mem = GlobalAlloc(GMEM_MOVEABLE, bytes.len() + 1);
+                if cf_html_id != 0 && !h_mem.is_null() {
+                    let ptr = GlobalLock(h_mem) as *mut u8;
+                    std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+                    *ptr.add(bytes.len()) = 0;
+                    GlobalUnlock(h_mem);
+                    SetClipboardData(cf_html_id, h_mem);
+                }
+            }
             CloseClipboard();
         }
     }
 }
 
+fn split_append(input: &str) -> (&str, Option<&str>) {
+    match input.split_once(">>") {
+        Some((query, text)) => (query.trim(), Some(text.trim())),
+        None => (input, None),
+    }
+}
+
+fn get_clipboard_text() -> Option<String> {
+    use winapi::um::winuser::{OpenClipboard, GetClipboardData, CloseClipboard, CF_UNICODETEXT};
+    use winapi::um::winbase::{GlobalLock, GlobalUnlock};
+
+    unsafe {
+        if OpenClipboard(std::ptr::null_mut()) == 0 {
+            return None;
+        }
+        let mut text = None;
+        let h_mem = GetClipboardData(CF_UNICODETEXT);
+        if !h_mem.is_null() {
+            let ptr = GlobalLock(h_mem) as *const u16;
+            if !ptr.is_null() {
+                text = widestring::U16CStr::from_ptr_str(ptr).to_string().ok();
+                GlobalUnlock(h_mem);
+            }
+        }
+        CloseClipboard();
+        text
+    }
+}
+
 fn type_text(text: &str) {
     // Basic SendInput implementation for previous focus
     use winapi::um::winuser::{SendInput, INPUT, INPUT_KEYBOARD, KEYEVENTF_UNICODE, KEYEVENTF_KEYUP};
@@ -340,3 +628,30 @@ pub fn toggle_launch_bar() {
         }
     }
 }
+
+/// Opens the launch bar with `query` typed in, e.g. "+" for quick-capture.
+pub fn show_launch_bar_with(query: &str) {
+    unsafe {
+        if let Some(ref lb) = GLOBAL_LAUNCH_BAR {
+            if let Some(hwnd) = lb.window.handle.hwnd() {
+                SetForegroundWindow(hwnd as _);
+            }
+            lb.show_with(query);
+        }
+    }
+}
+
+/// Opens the launch bar searching for the first line of the clipboard text.
+pub fn search_clipboard() {
+    let text = get_clipboard_text().unwrap_or_default();
+    // A leading "+" or "@" would switch the launch bar into capture or profile mode
+    let query: String = text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("")
+        .trim_start_matches(['+', '@'])
+        .chars()
+        .take(100)
+        .collect();
+    show_launch_bar_with(&query);
+}
@@ -3,7 +3,10 @@ use native_windows_derive as nwd;
 
 use nwd::NwgUi;
 use nwg::NativeUi;
-use crate::api::AppConfig;
+use crate::api::config_service;
+use crate::api::config::Destination;
+use crate::api::hotkey::Hotkey;
+use crate::api::markup::PasteFormat;
 use winapi::um::winuser::*;
 use winapi::um::libloaderapi::GetModuleHandleW;
 
@@ -15,7 +18,7 @@ pub struct SettingsWindow {
     #[nwg_resource(source_file: Some("resources/icon.ico"))]
     icon: nwg::Icon,
 
-    #[nwg_control(size: (400, 300), position: (400, 400), title: "Settings - MiNote", flags: "WINDOW", icon: Some(&data.icon))]
+    #[nwg_control(size: (400, 530), position: (400, 400), title: "Settings - MiNote", flags: "WINDOW", icon: Some(&data.icon))]
     #[nwg_events( OnWindowClose: [SettingsWindow::hide] )]
     window: nwg::Window,
 
@@ -34,20 +37,68 @@ pub struct SettingsWindow {
     #[nwg_control(text: "Previous Program Focus (Type text)", position: (10, 140), size: (300, 25), font: Some(&data.font))]
     dest_type: nwg::RadioButton,
 
-    #[nwg_control(size: (100, 35), position: (280, 250), text: "Save", font: Some(&data.font))]
+    #[nwg_control(size: (380, 25), position: (10, 175), text: "Paste format (Shift/Ctrl+Enter override):", font: Some(&data.font))]
+    label3: nwg::Label,
+
+    #[nwg_control(collection: PasteFormat::ALL.iter().map(|f| f.label()).collect(), selected_index: Some(0), size: (200, 25), position: (10, 205), font: Some(&data.font))]
+    paste_format: nwg::ComboBox<&'static str>,
+
+    #[nwg_control(size: (380, 25), position: (10, 245), text: "Quick-capture folder id (0 = All notes):", font: Some(&data.font))]
+    label4: nwg::Label,
+
+    #[nwg_control(size: (200, 30), position: (10, 275), text: "0", font: Some(&data.font))]
+    capture_folder_input: nwg::TextInput,
+
+    #[nwg_control(size: (380, 25), position: (10, 315), text: "More hotkeys (leave empty for none):", font: Some(&data.font))]
+    label5: nwg::Label,
+
+    #[nwg_control(size: (170, 25), position: (10, 348), text: "Quick capture:", font: Some(&data.font))]
+    label6: nwg::Label,
+
+    #[nwg_control(size: (200, 28), position: (190, 345), text: "", font: Some(&data.font))]
+    quick_capture_hotkey_input: nwg::TextInput,
+
+    #[nwg_control(size: (170, 25), position: (10, 383), text: "Open main window:", font: Some(&data.font))]
+    label7: nwg::Label,
+
+    #[nwg_control(size: (200, 28), position: (190, 380), text: "", font: Some(&data.font))]
+    open_window_hotkey_input: nwg::TextInput,
+
+    #[nwg_control(size: (170, 25), position: (10, 418), text: "Search clipboard:", font: Some(&data.font))]
+    label8: nwg::Label,
+
+    #[nwg_control(size: (200, 28), position: (190, 415), text: "", font: Some(&data.font))]
+    search_clipboard_hotkey_input: nwg::TextInput,
+
+    #[nwg_control(text: "Search notes of all profiles in the launch bar", position: (10, 455), size: (380, 25), font: Some(&data.font))]
+    search_all_profiles: nwg::CheckBox,
+
+    #[nwg_control(size: (100, 35), position: (280, 485), text: "Save", font: Some(&data.font))]
     #[nwg_events( OnButtonClick: [SettingsWindow::save] )]
     save_button: nwg::Button,
 }
 
 impl SettingsWindow {
     pub fn show(&self) {
-        let config = AppConfig::load();
-        self.hotkey_input.set_text(&config.hotkey);
-        if config.destination == "Clipboard" {
+        let config = config_service::current();
+        self.hotkey_input.set_text(&config.hotkey.to_string());
+        let optional = |hotkey: Option<Hotkey>| hotkey.map(|h| h.to_string()).unwrap_or_default();
+        self.quick_capture_hotkey_input.set_text(&optional(config.quick_capture_hotkey));
+        self.open_window_hotkey_input.set_text(&optional(config.open_window_hotkey));
+        self.search_clipboard_hotkey_input.set_text(&optional(config.search_clipboard_hotkey));
+        if config.destination == Destination::Clipboard {
             self.dest_clipboard.set_check_state(nwg::RadioButtonState::Checked);
         } else {
             self.dest_type.set_check_state(nwg::RadioButtonState::Checked);
         }
+        let format_index = PasteFormat::ALL.iter().position(|f| *f == config.paste_format).unwrap_or(0);
+        self.paste_format.set_selection(Some(format_index));
+        self.capture_folder_input.set_text(&config.capture_folder_id);
+        self.search_all_profiles.set_check_state(if config.search_all_profiles {
+            nwg::CheckBoxState::Checked
+        } else {
+            nwg::CheckBoxState::Unchecked
+        });
         self.window.set_visible(true);
     }
 
@@ -55,15 +106,54 @@ impl SettingsWindow {
         self.window.set_visible(false);
     }
 
+    fn error(&self, message: &str) {
+        nwg::modal_error_message(&self.window, "Settings - MiNote", &format!("The settings were not saved:\n{}", message));
+    }
+
     fn save(&self) {
-        let mut config = AppConfig::load();
-        config.hotkey = self.hotkey_input.text();
+        let mut config = (*config_service::current()).clone();
+        config.hotkey = match self.hotkey_input.text().parse::<Hotkey>() {
+            Ok(hotkey) => hotkey,
+            Err(e) => return self.error(&e),
+        };
+        let optional = |input: &nwg::TextInput| -> Result<Option<Hotkey>, String> {
+            let text = input.text();
+            if text.trim().is_empty() { Ok(None) } else { text.parse().map(Some) }
+        };
+        let hotkeys = (
+            optional(&self.quick_capture_hotkey_input),
+            optional(&self.open_window_hotkey_input),
+            optional(&self.search_clipboard_hotkey_input),
+        );
+        match hotkeys {
+            (Ok(quick_capture), Ok(open_window), Ok(search_clipboard)) => {
+                config.quick_capture_hotkey = quick_capture;
+                config.open_window_hotkey = open_window;
+                config.search_clipboard_hotkey = search_clipboard;
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return self.error(&e),
+        }
         config.destination = if self.dest_clipboard.check_state() == nwg::RadioButtonState::Checked {
-            "Clipboard".to_string()
+            Destination::Clipboard
         } else {
-            "PreviousProgram".to_string()
+            Destination::PreviousProgram
+        };
+        if let Some(index) = self.paste_format.selection() {
+            config.paste_format = PasteFormat::ALL[index];
+        }
+        let folder = self.capture_folder_input.text();
+        config.capture_folder_id = if folder.trim().is_empty() { "0".to_string() } else { folder.trim().to_string() };
+        config.search_all_profiles = self.search_all_profiles.check_state() == nwg::CheckBoxState::Checked;
+        let saved = match config_service::update(move |current| *current = config) {
+            Ok(saved) => saved,
+            Err(e) => return self.error(&e.to_string()),
         };
-        config.save().ok();
+
+        // Takes effect immediately; a taken hotkey is reported but the settings stay saved
+        let failures = crate::webview::apply_hotkeys(&saved);
+        if !failures.is_empty() {
+            nwg::modal_info_message(&self.window, "Settings - MiNote", &format!("Saved, but some hotkeys are unavailable:\n{}", failures.join("\n")));
+        }
         self.hide();
     }
 }
@@ -5,7 +5,8 @@ macro_rules! dprintln {
     ($($arg:tt)*) => {
         #[cfg(debug_assertions)]
         {
-            println!($($arg)*);
+            // Cookies and tokens end up in request logs; never print them
+            println!("{}", $crate::api::secrets::redact(&format!($($arg)*)));
         }
     };
 }
@@ -14,14 +15,49 @@ mod api;
 mod webview;
 mod state;
 mod gui;
+mod cli;
 
-use tokio::time::{sleep, Duration};
+use clap::Parser;
+use std::collections::HashMap;
+use tokio::time::Duration;
 use crate::api::Client;
 use crate::webview::WebViewManager;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Release builds use the GUI subsystem; borrow the terminal's console for CLI output
+    if std::env::args().len() > 1 {
+        unsafe {
+            use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+            AttachConsole(ATTACH_PARENT_PROCESS);
+        }
+    }
+
+    let args = cli::Cli::parse();
+    let profile = args.profile.or_else(|| std::env::var("MINOTE_PROFILE").ok().filter(|p| !p.is_empty()));
+    if let Some(ref name) = profile {
+        if let Err(e) = api::profile::use_for_process(name) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    // Bad overrides should stop a headless run rather than be ignored
+    let overrides = api::overrides::init(args.config, &args.set, &api::AppConfig::setting_keys())
+        .and_then(|_| api::AppConfig::check_overrides());
+    if let Err(e) = overrides {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(command) = args.command {
+        if let Err(e) = cli::run(command).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     dprintln!("Starting MiNote WebView...");
+    api::config_service::watch();
 
     let mut manager = WebViewManager::new();
     let cookies_arc = manager.cookies.clone();
@@ -30,51 +66,149 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tokio::spawn(async move {
         dprintln!("Background API monitor started.");
         let mut client: Option<Client> = None;
+        // Publishes the current client's session status for the tray and settings
+        let mut status_reporter: Option<tokio::task::JoinHandle<()>> = None;
         let mut last_cookies: Option<String> = None;
-        let mut last_ua: Option<String> = None;
+        let mut last_config: Option<std::sync::Arc<api::AppConfig>> = None;
+        let mut current_profile = api::profile::active();
+        let mut other_clients: HashMap<String, (String, Client)> = HashMap::new();
+        let mut config_changes = api::config_service::subscribe();
+        let mut scheduler = api::schedule::Scheduler::new();
+        let mut last_fingerprint: Option<u64> = None;
 
         loop {
-            let (cookie_opt, current_ua) = {
-                let config = api::AppConfig::load();
-                let guard = cookies_arc.lock().unwrap();
-                (guard.clone(), config.user_agent.clone())
-            };
+            // One snapshot per round: the client, its data directory and the backup
+            // all belong to the profile this config was loaded for, even if the
+            // user switches profiles while the round is running
+            let config = api::config_service::current();
+            // Switching profiles drops everything tied to the previous account
+            if config.profile != current_profile {
+                dprintln!("[Background API] Profile switched to '{}'", config.profile);
+                *cookies_arc.lock().unwrap() = None;
+                client = None;
+                if let Some(task) = status_reporter.take() {
+                    task.abort();
+                }
+                api::status::publish(None);
+                last_cookies = None;
+                other_clients.clear();
+                state::clear_notes();
+                scheduler = api::schedule::Scheduler::new();
+                last_fingerprint = None;
+                current_profile = config.profile.clone();
+            }
+
+            let cookie_opt = cookies_arc.lock().unwrap().clone();
+            // Nothing to sync until signed in; a login wakes the loop
+            let mut outcome = api::schedule::Outcome::Unchanged;
 
             if let Some(cookies) = cookie_opt {
-                let ua_changed = current_ua != last_ua;
-                let cookie_changed = Some(cookies.clone()) != last_cookies;
-
-                if client.is_none() || ua_changed || cookie_changed {
-                    dprintln!("[Background API] Initializing/Updating API client (UA changed: {}, Cookie changed: {})", ua_changed, cookie_changed);
-                    client = Some(Client::new(&cookies, current_ua.clone()));
-                    last_cookies = Some(cookies.clone());
-                    last_ua = current_ua;
+                // Only the server and connection settings need a new client (and connection pool);
+                // the cookie and user agent are swapped in place
+                let connection_changed = last_config.as_ref().is_none_or(|last| {
+                    (&last.api_base_url, api::http::HttpSettings::from_config(last))
+                        != (&config.api_base_url, api::http::HttpSettings::from_config(&config))
+                });
+                let credentials_changed = Some(cookies.clone()) != last_cookies
+                    || last_config.as_ref().is_some_and(|last| last.user_agent != config.user_agent);
+
+                if client.is_none() || connection_changed {
+                    dprintln!("[Background API] Initializing API client (Settings changed: {})", connection_changed);
+                    client = Client::new(&cookies, &config)
+                        .map_err(|e| eprintln!("[Background API Error] Can't set up the connection: {}", e))
+                        .ok();
+                    if let Some(task) = status_reporter.take() {
+                        task.abort();
+                    }
+                    match client {
+                        Some(ref c) => status_reporter = Some(report_status(c)),
+                        None => outcome = api::schedule::Outcome::Failed { retry_after: None, offline: false },
+                    }
+                } else if credentials_changed {
+                    dprintln!("[Background API] Updating API client credentials");
+                    if let Some(ref c) = client {
+                        c.set_credentials(&cookies, config.user_agent.clone()).await;
+                    }
                 }
+                last_cookies = Some(cookies.clone());
+                last_config = Some(config.clone());
 
                 if let Some(ref c) = client {
                     dprintln!("--- Background API Operation ---");
+                    // Push queued writes (quick-capture etc.) before refreshing the list
+                    let mut flush_failed = None;
+                    match api::outbox::Outbox::flush(c).await {
+                        Ok(0) => {}
+                        Ok(_n) => {
+                            dprintln!("[Background API] Pushed {} queued operation(s).", _n);
+                            outcome = api::schedule::Outcome::Changed;
+                        }
+                        Err(e) => {
+                            eprintln!("[Background API Error] Outbox flush stopped: {:?}", e);
+                            flush_failed = Some(api::schedule::Outcome::failed(&e));
+                        }
+                    }
+
                     // Add a timeout to the future itself just in case
                     let list_future = c.list_notes(100);
                     match tokio::time::timeout(Duration::from_secs(45), list_future).await {
                         Ok(Ok(notes)) => {
                             dprintln!("Found {} notes in background.", notes.entries.len());
-                            state::update_notes(notes.entries);
+                            // Keep notes created offline visible until they are pushed
+                            let mut entries = notes.entries;
+                            entries.retain(|n| !n.is_deleted());
+
+                            // Snapshot notes whose tag moved on, then reuse snapshots as full contents
+                            if let Err(e) = api::history::NoteHistory::snapshot_changed(c, &entries, 20).await {
+                                eprintln!("[Background API Error] History snapshot failed: {:?}", e);
+                            }
+                            api::history::NoteHistory::fill_content(c.data_dir(), &mut entries);
+                            entries.extend(api::outbox::Outbox::pending_notes(c.data_dir()));
+                            let fingerprint = notes_fingerprint(&entries);
+                            if last_fingerprint != Some(fingerprint) {
+                                outcome = api::schedule::Outcome::Changed;
+                                last_fingerprint = Some(fingerprint);
+                            }
+                            state::update_notes(entries);
+
+                            if config.search_all_profiles {
+                                state::update_other_notes(sync_other_profiles(&current_profile, &mut other_clients).await);
+                            } else {
+                                state::update_other_notes(Vec::new());
+                            }
+
+                            // Scheduled backups run alongside sync so a slow first archive doesn't stall it
+                            if api::backup::is_due(&config) {
+                                let c = c.clone();
+                                let config = config.clone();
+                                tokio::spawn(async move {
+                                    match api::backup::backup_now(&c, &config).await {
+                                        Ok(Some(_path)) => { dprintln!("[Background API] Backup written to {}", _path.display()); }
+                                        Ok(None) => {}
+                                        Err(e) => eprintln!("[Background API Error] Backup failed: {:?}", e),
+                                    }
+                                });
+                            }
                         }
                         Ok(Err(e)) => {
+                            // An expired session keeps the client; it reports `Expired` until
+                            // a new login replaces the cookie
                             eprintln!("[Background API Error] API reported error: {:?}", e);
-                            if e.to_string().contains("Authentication") {
-                                client = None;
-                            }
+                            outcome = api::schedule::Outcome::failed(&e);
                         }
                         Err(_) => {
                             eprintln!("[Background API Error] Request timed out after 45s");
+                            outcome = api::schedule::Outcome::Failed { retry_after: None, offline: false };
                         }
                     }
+                    // Writes still queued back off (and honour Retry-After) even if the list came through
+                    if let Some(failed) = flush_failed.filter(|_| !matches!(outcome, api::schedule::Outcome::Failed { .. })) {
+                        outcome = failed;
+                    }
                 }
             } else {
                 // Check if we have saved cookies in config
-                let config = api::AppConfig::load();
-                if let Some(saved_cookies) = config.account_cookie {
+                if let Some(saved_cookies) = config.account_cookie.clone() {
                     dprintln!("Using saved cookies from config...");
                     let mut guard = cookies_arc.lock().unwrap();
                     *guard = Some(saved_cookies);
@@ -82,14 +216,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
 
-            tokio::select! {
-                _ = sleep(Duration::from_secs(60)) => {
-                    dprintln!("[Background API] Timer wake-up.");
-                }
-                _ = state::WAKE_UP_NOTIFY.notified() => {
-                    dprintln!("[Background API] Notification wake-up (reactive sync).");
-                }
-            }
+            scheduler.record(outcome);
+            let delay = scheduler.next_delay(&config);
+            dprintln!("[Background API] Next sync in {:?} ({:?})", delay, outcome);
+            let first_hop = api::http::HttpSettings::from_config(&config).first_hop(&config.api_base_url);
+            let _wake = scheduler.wait(
+                delay,
+                state::WAKE_UP_NOTIFY.notified(),
+                api::schedule::network_up(&first_hop),
+                config_changes.changed(),
+            ).await;
+            dprintln!("[Background API] Wake-up: {:?}", _wake);
         }
     });
 
@@ -98,3 +235,65 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Mirrors the client's session status into `api::status` until aborted.
+fn report_status(client: &Client) -> tokio::task::JoinHandle<()> {
+    let mut changes = client.watch_status();
+    tokio::spawn(async move {
+        loop {
+            api::status::publish(Some(*changes.borrow_and_update()));
+            if changes.changed().await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Changes whenever a note is added, removed or edited (its tag moves on).
+fn notes_fingerprint(notes: &[api::models::Note]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for note in notes {
+        note.id.hash(&mut hasher);
+        note.tag.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Lists the notes of every other signed-in profile, labelled with the profile name.
+/// Clients are kept between rounds so their connections and refreshed sessions are reused.
+async fn sync_other_profiles(active: &str, clients: &mut HashMap<String, (String, Client)>) -> Vec<(String, api::models::Note)> {
+    let mut notes = Vec::new();
+    for name in api::profile::list().into_iter().filter(|n| n != active) {
+        let config = api::config_service::of_profile(&name);
+        let Some(cookie) = config.account_cookie.clone() else {
+            clients.remove(&name);
+            continue;
+        };
+        match clients.get_mut(&name) {
+            Some((last, c)) if *last != cookie => {
+                c.set_credentials(&cookie, config.user_agent.clone()).await;
+                *last = cookie.clone();
+            }
+            Some(_) => {}
+            None => match Client::new(&cookie, &config) {
+                Ok(client) => {
+                    clients.insert(name.clone(), (cookie.clone(), client));
+                }
+                Err(e) => {
+                    eprintln!("[Background API Error] Profile '{}': {}", name, e);
+                    continue;
+                }
+            },
+        }
+        let (_, c) = &clients[&name];
+        match tokio::time::timeout(Duration::from_secs(45), c.list_notes(100)).await {
+            Ok(Ok(list)) => {
+                notes.extend(list.entries.into_iter().filter(|n| !n.is_deleted()).map(|n| (name.clone(), n)));
+            }
+            Ok(Err(e)) => eprintln!("[Background API Error] Profile '{}': {:?}", name, e),
+            Err(_) => eprintln!("[Background API Error] Profile '{}' timed out after 45s", name),
+        }
+    }
+    notes
+}
@@ -12,12 +12,15 @@ unsafe impl Sync for SendHwnd {}
 pub struct GlobalState {
     pub main_hwnd: Option<SendHwnd>,
     pub notes_cache: Vec<Note>,
+    /// Notes of the other signed-in profiles, for cross-profile search.
+    pub other_notes: Vec<(String, Note)>,
 }
 
 pub static STATE: Lazy<Arc<Mutex<GlobalState>>> = Lazy::new(|| {
     Arc::new(Mutex::new(GlobalState {
         main_hwnd: None,
         notes_cache: Vec::new(),
+        other_notes: Vec::new(),
     }))
 });
 
@@ -29,11 +32,34 @@ pub fn update_notes(notes: Vec<Note>) {
     state.notes_cache = notes;
 }
 
+/// Shows a note immediately, before the next sync brings the server copy.
+pub fn add_note(note: Note) {
+    let mut state = STATE.lock().unwrap();
+    state.notes_cache.insert(0, note);
+}
+
 pub fn get_notes() -> Vec<Note> {
     let state = STATE.lock().unwrap();
     state.notes_cache.clone()
 }
 
+pub fn update_other_notes(notes: Vec<(String, Note)>) {
+    let mut state = STATE.lock().unwrap();
+    state.other_notes = notes;
+}
+
+pub fn get_other_notes() -> Vec<(String, Note)> {
+    let state = STATE.lock().unwrap();
+    state.other_notes.clone()
+}
+
+/// Forgets everything cached for the previous profile.
+pub fn clear_notes() {
+    let mut state = STATE.lock().unwrap();
+    state.notes_cache.clear();
+    state.other_notes.clear();
+}
+
 pub fn set_main_hwnd(hwnd: HWND) {
     let mut state = STATE.lock().unwrap();
     state.main_hwnd = Some(SendHwnd(hwnd));
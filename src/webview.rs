@@ -11,7 +11,8 @@ use winapi::um::libloaderapi::GetModuleHandleW;
 use winapi::um::objbase::{COINIT_APARTMENTTHREADED};
 use widestring::WideCStr;
 use com::{ComPtr, ComRc, interfaces::IUnknown};
-use crate::api::AppConfig;
+use crate::api::{config_service, AppConfig};
+use crate::api::config::Theme;
 
 use native_windows_gui as nwg;
 
@@ -20,13 +21,25 @@ const WINDOW_CLASS: &str = "MiNoteWebViewMain";
 const WINDOW_TITLE: &str = "Xiaomi Cloud Note";
 
 const WM_TRAY_ICON: UINT = WM_USER + 1;
+/// Posted to the main window when the shared settings change.
+const WM_CONFIG_CHANGED: UINT = WM_USER + 2;
+/// Posted to the main window when the active profile's session status changes.
+const WM_SESSION_STATUS: UINT = WM_USER + 3;
 const TRAY_ICON_ID: UINT = 1;
-const HOTKEY_ID: i32 = 100;
 
-use winapi::um::shellapi::{NOTIFYICONDATAW, NIM_ADD, NIM_DELETE, NIF_ICON, NIF_MESSAGE, NIF_TIP, Shell_NotifyIconW};
-use winapi::um::winuser::{RegisterHotKey, UnregisterHotKey, MOD_ALT};
+use winapi::um::shellapi::{NOTIFYICONDATAW, NIM_ADD, NIM_DELETE, NIM_MODIFY, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_WARNING, NIN_BALLOONUSERCLICK, Shell_NotifyIconW};
+use winapi::um::winuser::{RegisterHotKey, UnregisterHotKey, MOD_NOREPEAT};
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::shared::winerror::ERROR_HOTKEY_ALREADY_REGISTERED;
+use crate::api::hotkey::{self, Hotkey, HotkeyAction};
+use crate::api::status::{self, SessionStatus};
 
 static mut GLOBAL_CONTROLLER: Option<Controller> = None;
+/// What is in effect on the UI thread, to tell which settings changes need applying.
+static REGISTERED_HOTKEYS: Mutex<Vec<(HotkeyAction, Hotkey)>> = Mutex::new(Vec::new());
+static APPLIED_THEME: Mutex<Option<Theme>> = Mutex::new(None);
+/// The tray balloon asked to sign in again; cleared once the session works again.
+static RELOGIN_PROMPTED: Mutex<bool> = Mutex::new(false);
 
 pub struct WebViewManager {
     pub hwnd: HWND,
@@ -70,8 +83,8 @@ impl WebViewManager {
 
             RegisterClassExW(&wc);
 
-            let config = AppConfig::load();
-            let (w, h) = if config.theme == "Mobile" { (600, 800) } else { (1280, 960) };
+            let config = config_service::current();
+            let (w, h) = if config.theme == Theme::Mobile { (600, 800) } else { (1280, 960) };
 
             self.hwnd = CreateWindowExW(
                 0,
@@ -96,14 +109,33 @@ impl WebViewManager {
             crate::gui::launch_bar::init_launch_bar();
             crate::gui::settings::init_settings_window();
 
-            // Register Hotkey (Alt-L)
-            let _config = AppConfig::load();
-            let (mods, vk) = (MOD_ALT as UINT, 0x4C as UINT); // Alt-L
-            RegisterHotKey(self.hwnd, HOTKEY_ID, mods, vk);
-
             // Setup Tray Icon
             setup_tray_icon(self.hwnd);
 
+            // Register the configured hotkeys; the tray balloon reports any that are taken
+            let failures = register_hotkeys(self.hwnd, &config);
+            if !failures.is_empty() {
+                notify_tray(self.hwnd, "Some hotkeys are unavailable", &failures.join("\n"));
+            }
+
+            // Settings changes arrive on other threads; apply them on this one
+            let main_hwnd = crate::state::SendHwnd(self.hwnd);
+            tokio::spawn(async move {
+                let target = main_hwnd;
+                let mut changes = config_service::subscribe();
+                while changes.changed().await.is_ok() {
+                    PostMessageW(target.0, WM_CONFIG_CHANGED, 0, 0);
+                }
+            });
+            // Likewise for the session status the sync loop reports
+            tokio::spawn(async move {
+                let target = main_hwnd;
+                let mut changes = status::subscribe();
+                while changes.changed().await.is_ok() {
+                    PostMessageW(target.0, WM_SESSION_STATUS, 0, 0);
+                }
+            });
+
             ShowWindow(self.hwnd, SW_SHOW);
             UpdateWindow(self.hwnd);
 
@@ -126,12 +158,13 @@ impl WebViewManager {
                     let webview = controller.get_webview().expect("Failed to get webview");
 
                     // Initial Emulation setup: Default to Mobile as requested
-                    let mut config = AppConfig::load();
-                    config.theme = "Mobile".to_string();
-                    config.save().ok();
+                    *APPLIED_THEME.lock().unwrap() = Some(Theme::Mobile);
+                    if let Err(e) = config_service::update(|config| config.theme = Theme::Mobile) {
+                        eprintln!("Failed to save settings: {}", e);
+                    }
 
                     crate::dprintln!("Application starting. Forcing Mobile mode...");
-                    apply_professional_emulation(&webview, "Mobile");
+                    apply_professional_emulation(&webview, Theme::Mobile);
 
                     webview.navigate(INITIAL_URL).ok();
 
@@ -200,10 +233,13 @@ impl WebViewManager {
                                                     wv.execute_script("navigator.userAgent", move |res| {
                                                         let ua = res.trim_matches('"').to_string();
                                                         crate::dprintln!("Captured User-Agent: {}", ua);
-                                                        let mut config = AppConfig::load();
-                                                        config.account_cookie = Some(cookie_str.clone());
-                                                        config.user_agent = Some(ua);
-                                                        config.save().ok();
+                                                        let saved = config_service::update(|config| {
+                                                            config.account_cookie = Some(cookie_str.clone());
+                                                            config.user_agent = Some(ua);
+                                                        });
+                                                        if let Err(e) = saved {
+                                                            eprintln!("Failed to save the captured login: {}", e);
+                                                        }
 
                                                         let mut guard = cookies_arc_task.lock().unwrap();
                                                         *guard = Some(cookie_str);
@@ -255,6 +291,9 @@ const IDM_TRAY_RESTORE: usize = 2002;
 const IDM_TRAY_SWITCH: usize = 2003;
 const IDM_TRAY_SETTINGS: usize = 2004;
 const IDM_TRAY_EXIT: usize = 2005;
+/// Profiles submenu entries are numbered from here, in `profile::list()` order.
+const IDM_TRAY_PROFILE_BASE: usize = 2100;
+const IDM_TRAY_PROFILE_LAST: usize = 2199;
 
 fn setup_tray_icon(hwnd: HWND) {
     unsafe {
@@ -276,6 +315,135 @@ fn setup_tray_icon(hwnd: HWND) {
     }
 }
 
+/// Replaces the tray icon's tooltip.
+fn set_tray_tip(hwnd: HWND, text: &str) {
+    unsafe {
+        let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
+        nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+        nid.hWnd = hwnd;
+        nid.uID = TRAY_ICON_ID;
+        nid.uFlags = NIF_TIP;
+
+        let tip: Vec<u16> = text.encode_utf16().take(nid.szTip.len() - 1).chain(std::iter::once(0)).collect();
+        std::ptr::copy_nonoverlapping(tip.as_ptr(), nid.szTip.as_mut_ptr(), tip.len());
+
+        Shell_NotifyIconW(NIM_MODIFY, &mut nid);
+    }
+}
+
+/// Shows the session status in the tooltip and asks once per expiry to sign in again.
+fn on_session_status(hwnd: HWND) {
+    let current = status::current();
+    match current {
+        Some(s) => set_tray_tip(hwnd, &format!("MiNote WebView ({})", s.describe())),
+        None => set_tray_tip(hwnd, "MiNote WebView"),
+    }
+
+    let mut prompted = RELOGIN_PROMPTED.lock().unwrap();
+    match current {
+        // Refreshes keep failing while expired; one balloon is enough
+        Some(SessionStatus::Expired) if !*prompted => {
+            *prompted = true;
+            notify_tray(hwnd, "Xiaomi session expired", "Notes are no longer syncing. Click here to sign in again.");
+        }
+        Some(SessionStatus::Valid) | None => *prompted = false,
+        _ => {}
+    }
+}
+
+/// Brings up the main window on the notes page, which shows the sign-in form
+/// when the web session is gone too; the new cookies then reach the sync loop.
+fn show_sign_in(hwnd: HWND) {
+    unsafe {
+        ShowWindow(hwnd, SW_RESTORE);
+        SetForegroundWindow(hwnd);
+        if let Some(ref controller) = GLOBAL_CONTROLLER {
+            if let Ok(webview) = controller.get_webview() {
+                webview.navigate(INITIAL_URL).ok();
+            }
+        }
+    }
+}
+
+/// Shows a balloon notification from the tray icon.
+fn notify_tray(hwnd: HWND, title: &str, text: &str) {
+    unsafe {
+        let mut nid: NOTIFYICONDATAW = std::mem::zeroed();
+        nid.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+        nid.hWnd = hwnd;
+        nid.uID = TRAY_ICON_ID;
+        nid.uFlags = NIF_INFO;
+        nid.dwInfoFlags = NIIF_WARNING;
+
+        // Both buffers are fixed-size and must stay NUL-terminated
+        let title: Vec<u16> = title.encode_utf16().take(nid.szInfoTitle.len() - 1).chain(std::iter::once(0)).collect();
+        let text: Vec<u16> = text.encode_utf16().take(nid.szInfo.len() - 1).chain(std::iter::once(0)).collect();
+        std::ptr::copy_nonoverlapping(title.as_ptr(), nid.szInfoTitle.as_mut_ptr(), title.len());
+        std::ptr::copy_nonoverlapping(text.as_ptr(), nid.szInfo.as_mut_ptr(), text.len());
+
+        Shell_NotifyIconW(NIM_MODIFY, &mut nid);
+    }
+}
+
+/// Registers every configured hotkey on the main window, replacing earlier
+/// registrations. Returns a message for each hotkey that couldn't be registered.
+fn register_hotkeys(hwnd: HWND, config: &AppConfig) -> Vec<String> {
+    let mut failures = Vec::new();
+    unsafe {
+        for action in HotkeyAction::ALL {
+            UnregisterHotKey(hwnd, action.id());
+        }
+        let bindings = config.hotkey_bindings();
+        // Registering a combination twice also fails with ERROR_HOTKEY_ALREADY_REGISTERED,
+        // so duplicates within our own bindings are reported before Windows is asked
+        let duplicates = hotkey::conflicts(&bindings);
+        for &(action, hotkey) in &bindings {
+            if let Some((first, _, _)) = duplicates.iter().find(|(_, second, _)| *second == action) {
+                let message = format!("{} ({}) is already bound to {}", hotkey, action.label(), first.label());
+                eprintln!("Hotkey: {}", message);
+                failures.push(message);
+                continue;
+            }
+            let mods = hotkey.modifiers.win32_flags() | MOD_NOREPEAT as UINT;
+            if RegisterHotKey(hwnd, action.id(), mods, hotkey.key.vk()) == 0 {
+                let reason = match GetLastError() {
+                    ERROR_HOTKEY_ALREADY_REGISTERED => "is already used by another program".to_string(),
+                    code => format!("could not be registered (error {})", code),
+                };
+                let message = format!("{} ({}) {}", hotkey, action.label(), reason);
+                eprintln!("Hotkey: {}", message);
+                failures.push(message);
+            }
+        }
+        *REGISTERED_HOTKEYS.lock().unwrap() = bindings;
+    }
+    failures
+}
+
+/// Applies changed settings that the UI thread owns: hotkeys and the interface theme.
+fn on_config_changed(hwnd: HWND) {
+    let config = config_service::current();
+
+    if *REGISTERED_HOTKEYS.lock().unwrap() != config.hotkey_bindings() {
+        let failures = register_hotkeys(hwnd, &config);
+        if !failures.is_empty() {
+            notify_tray(hwnd, "Some hotkeys are unavailable", &failures.join("\n"));
+        }
+    }
+    if *APPLIED_THEME.lock().unwrap() != Some(config.theme) {
+        apply_theme(config.theme);
+    }
+}
+
+/// Re-registers the hotkeys after the settings changed. Must run on the UI thread.
+pub fn apply_hotkeys(config: &AppConfig) -> Vec<String> {
+    let hwnd = crate::state::STATE.lock().unwrap().main_hwnd;
+    match hwnd {
+        Some(hwnd) => register_hotkeys(hwnd.0, config),
+        None => Vec::new(),
+    }
+}
+
 fn show_tray_menu(hwnd: HWND) {
     unsafe {
         let h_menu = CreatePopupMenu();
@@ -284,6 +452,15 @@ fn show_tray_menu(hwnd: HWND) {
         AppendMenuW(h_menu, MF_SEPARATOR, 0, std::ptr::null());
         AppendMenuW(h_menu, MF_STRING, IDM_TRAY_SWITCH, "Switch Interface (Desktop/Mobile)\0".encode_utf16().collect::<Vec<u16>>().as_ptr());
         AppendMenuW(h_menu, MF_STRING, IDM_TRAY_SETTINGS, "Settings\0".encode_utf16().collect::<Vec<u16>>().as_ptr());
+
+        let h_profiles = CreatePopupMenu();
+        let active = crate::api::profile::active();
+        for (i, name) in crate::api::profile::list().iter().enumerate().take(IDM_TRAY_PROFILE_LAST - IDM_TRAY_PROFILE_BASE + 1) {
+            let flags = if *name == active { MF_STRING | MF_CHECKED } else { MF_STRING };
+            let label: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+            AppendMenuW(h_profiles, flags, IDM_TRAY_PROFILE_BASE + i, label.as_ptr());
+        }
+        AppendMenuW(h_menu, MF_POPUP, h_profiles as usize, "Profile\0".encode_utf16().collect::<Vec<u16>>().as_ptr());
         AppendMenuW(h_menu, MF_SEPARATOR, 0, std::ptr::null());
         AppendMenuW(h_menu, MF_STRING, IDM_TRAY_EXIT, "Exit\0".encode_utf16().collect::<Vec<u16>>().as_ptr());
 
@@ -305,24 +482,51 @@ unsafe extern "system" fn wnd_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam
             nid.hWnd = hwnd;
             nid.uID = TRAY_ICON_ID;
             Shell_NotifyIconW(NIM_DELETE, &mut nid);
-            UnregisterHotKey(hwnd, HOTKEY_ID);
+            for action in HotkeyAction::ALL {
+                UnregisterHotKey(hwnd, action.id());
+            }
             PostQuitMessage(0);
             0
         }
         WM_HOTKEY => {
-            if wparam as i32 == HOTKEY_ID {
-                crate::dprintln!("Hotkey pressed!");
-                // Toggle Launch Bar
-                crate::gui::launch_bar::toggle_launch_bar();
+            let action = HotkeyAction::from_id(wparam as i32);
+            crate::dprintln!("Hotkey pressed: {:?}", action);
+            match action {
+                Some(HotkeyAction::ToggleLaunchBar) => crate::gui::launch_bar::toggle_launch_bar(),
+                Some(HotkeyAction::QuickCapture) => crate::gui::launch_bar::show_launch_bar_with("+"),
+                Some(HotkeyAction::OpenMainWindow) => {
+                    ShowWindow(hwnd, SW_RESTORE);
+                    SetForegroundWindow(hwnd);
+                }
+                Some(HotkeyAction::SearchClipboard) => crate::gui::launch_bar::search_clipboard(),
+                None => {}
             }
             0
         }
+        WM_CONFIG_CHANGED => {
+            on_config_changed(hwnd);
+            0
+        }
+        WM_SESSION_STATUS => {
+            on_session_status(hwnd);
+            0
+        }
+        WM_POWERBROADCAST => {
+            // Sync right after waking from sleep instead of waiting out the interval
+            if wparam == PBT_APMRESUMEAUTOMATIC {
+                crate::dprintln!("Resumed from sleep; syncing");
+                crate::state::WAKE_UP_NOTIFY.notify_one();
+            }
+            1
+        }
         WM_TRAY_ICON => {
             if lparam as UINT == WM_RBUTTONUP {
                 show_tray_menu(hwnd);
             } else if lparam as UINT == WM_LBUTTONDBLCLK {
                 ShowWindow(hwnd, SW_RESTORE);
                 SetForegroundWindow(hwnd);
+            } else if lparam as UINT == NIN_BALLOONUSERCLICK && status::current() == Some(SessionStatus::Expired) {
+                show_sign_in(hwnd);
             }
             0
         }
@@ -333,6 +537,11 @@ unsafe extern "system" fn wnd_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam
                 IDM_TRAY_EXIT => { DestroyWindow(hwnd); }
                 IDM_TRAY_SWITCH => { switch_interface(); }
                 IDM_TRAY_SETTINGS => { crate::gui::settings::show_settings(); }
+                id @ IDM_TRAY_PROFILE_BASE..=IDM_TRAY_PROFILE_LAST => {
+                    if let Some(name) = crate::api::profile::list().get(id - IDM_TRAY_PROFILE_BASE) {
+                        switch_profile(name);
+                    }
+                }
                 _ => {}
             }
             0
@@ -355,26 +564,66 @@ unsafe extern "system" fn wnd_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam
 
 // toggle_launch_bar removed
 
-fn switch_interface() {
+/// Makes another profile active. The WebView still holds the previous account's
+/// cookies, so they are cleared and the page reloaded: the window then signs in
+/// as the new account and cookie capture stores it under the new profile.
+pub fn switch_profile(name: &str) {
+    if name == crate::api::profile::active() {
+        return;
+    }
+    if let Err(e) = crate::api::profile::switch(name) {
+        eprintln!("Failed to switch profile: {}", e);
+        return;
+    }
+    crate::dprintln!("Switched to profile '{}'", name);
+    config_service::reload();
+    crate::state::clear_notes();
+
     unsafe {
         if let Some(ref controller) = GLOBAL_CONTROLLER {
             if let Ok(webview) = controller.get_webview() {
-                let mut config = AppConfig::load();
+                let inner = webview.as_inner();
+                if let Some(wv2) = inner.get_interface::<dyn ICoreWebView2_2>() {
+                    let mut manager_ptr: *mut *mut ICoreWebView2CookieManagerVTable = std::ptr::null_mut();
+                    if wv2.get_cookie_manager(&mut manager_ptr) == S_OK && !manager_ptr.is_null() {
+                        let manager = ComPtr::new(manager_ptr);
+                        IUnknown::add_ref(&manager);
+                        let manager: ComRc<dyn ICoreWebView2CookieManager> = manager.upgrade();
+                        manager.delete_all_cookies();
+                    }
+                }
+                webview.navigate(INITIAL_URL).ok();
+            }
+        }
+    }
 
-                let new_theme = if config.theme == "Mobile" { "Desktop" } else { "Mobile" };
-                crate::dprintln!("Switching to {} mode...", new_theme);
+    crate::state::WAKE_UP_NOTIFY.notify_one();
+}
+
+fn switch_interface() {
+    let new_theme = config_service::current().theme.toggled();
+    crate::dprintln!("Switching to {:?} mode...", new_theme);
+    if let Err(e) = config_service::update(|config| config.theme = new_theme) {
+        eprintln!("Failed to save settings: {}", e);
+    }
+    apply_theme(new_theme);
+}
 
-                config.theme = new_theme.to_string();
-                config.save().ok();
+/// Resizes the window and reloads the page with the emulation for `theme`.
+fn apply_theme(theme: Theme) {
+    unsafe {
+        if let Some(ref controller) = GLOBAL_CONTROLLER {
+            if let Ok(webview) = controller.get_webview() {
+                *APPLIED_THEME.lock().unwrap() = Some(theme);
 
                 // Resize window based on new theme
-                let (w, h) = if new_theme == "Mobile" { (720, 960) } else { (1200, 900) };
+                let (w, h) = if theme == Theme::Mobile { (720, 960) } else { (1200, 900) };
                 if let Some(hwnd_wrapper) = crate::state::STATE.lock().unwrap().main_hwnd {
                     let hwnd = hwnd_wrapper.0;
                     SetWindowPos(hwnd, std::ptr::null_mut(), 0, 0, w, h, SWP_NOMOVE | SWP_NOZORDER);
                 }
 
-                apply_professional_emulation(&webview, new_theme);
+                apply_professional_emulation(&webview, theme);
 
                 webview.reload().ok();
             }
@@ -382,8 +631,8 @@ fn switch_interface() {
     }
 }
 
-fn apply_professional_emulation(webview: &webview2::WebView, theme: &str) {
-    let (ua, _platform, is_mobile) = if theme == "Mobile" {
+fn apply_professional_emulation(webview: &webview2::WebView, theme: Theme) {
+    let (ua, _platform, is_mobile) = if theme == Theme::Mobile {
         (
             "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1",
             "iPhone",
@@ -397,7 +646,7 @@ fn apply_professional_emulation(webview: &webview2::WebView, theme: &str) {
         )
     };
 
-    crate::dprintln!("Applying professional emulation for: {} (Mobile={})", theme, is_mobile);
+    crate::dprintln!("Applying professional emulation for: {:?} (Mobile={})", theme, is_mobile);
 
     // 1. User Agent Override (via Settings2 for persistence)
     if let Ok(settings) = webview.get_settings() {